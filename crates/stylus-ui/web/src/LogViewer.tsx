@@ -1,4 +1,5 @@
 import { useState, useEffect, useRef } from "react";
+import { fetchText } from "./utils.tsx";
 
 // Log Modal Component
 interface LogModalProps {
@@ -40,11 +41,7 @@ export function LogModal({ isOpen, onClose, monitorId }: LogModalProps) {
 
         const fetchLogs = async () => {
             try {
-                const response = await fetch(`/log/${encodeURIComponent(monitorId)}`);
-                if (!response.ok) {
-                    throw new Error(`HTTP error! status: ${response.status}`);
-                }
-                const logs = (await response.text()).split('\n');
+                const logs = (await fetchText(`log/${encodeURIComponent(monitorId)}`)).split('\n');
                 setLogEntries(logs);
                 setHasContentLoaded(true);
                 
@@ -3,7 +3,7 @@ import { createRoot } from "react-dom/client";
 import { StatusData, StatusResponse, Config, Monitor, Status, MonitorChildStatus } from "./types.ts";
 import { VisualizationGrid, VisualizationCard } from "./Visuals.tsx";
 import { LogModal } from "./LogViewer.tsx";
-import { StatusIndicator, createUrlSafeId, findVisualizationById, getStatus } from "./utils.tsx";
+import { StatusIndicator, createUrlSafeId, fetchText, findVisualizationById, getStatus } from "./utils.tsx";
 
 // Custom hook for fetching status data
 function useStatusData() {
@@ -14,11 +14,7 @@ function useStatusData() {
 
     // Fetch config once at startup
     const fetchConfig = async (): Promise<Config> => {
-        const response = await fetch('/config.json');
-        if (!response.ok) {
-            throw new Error(`Failed to fetch config: ${response.status}`);
-        }
-        const configData: Config = await response.json();
+        const configData: Config = JSON.parse(await fetchText('config.json'));
         if (configData.ui == undefined) {
             throw new Error("Missing UI configuration");
         }
@@ -34,11 +30,7 @@ function useStatusData() {
         try {
             setLoading(true);
             setError(null);
-            const response = await fetch('/status.json');
-            if (!response.ok) {
-                throw new Error(`HTTP error! status: ${response.status}`);
-            }
-            const statusResponse: StatusResponse = await response.json();
+            const statusResponse: StatusResponse = JSON.parse(await fetchText('status.json'));
             
             // Combine config and status into StatusData
             setData({
@@ -254,7 +246,7 @@ function Header({ title, description, themeMode, onThemeToggle }: HeaderProps) {
     return (
         <div className="header">
             <div className="header-content">
-                <img className="logo" src="/stylus.svg" alt="Stylus Logo" />
+                <img className="logo" src="stylus.svg" alt="Stylus Logo" />
                 <div className="header-text">
                     <h1 className="title">{title}</h1>
                     <p className="description">{description}</p>
@@ -402,7 +394,7 @@ function MonitorsTab({ data, loading, onRefetch, onShowLog }: MonitorsTabProps)
             
             <div style={{ marginTop: '40px', textAlign: 'center' }}>
                 <p>
-                    <a href="/status.json" target="_blank" rel="noopener noreferrer">
+                    <a href="status.json" target="_blank" rel="noopener noreferrer">
                         View Raw JSON
                     </a>
                 </p>
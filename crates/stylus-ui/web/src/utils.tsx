@@ -1,6 +1,31 @@
 import { JSX } from "react";
 import { MonitorStatus, Status } from "./types.ts";
 
+declare global {
+    interface Window {
+        // The files embedded by `stylus export`, by path
+        STYLUS_SNAPSHOT?: Record<string, string>;
+    }
+}
+
+// Fetches a file relative to the page, reading it from the exported snapshot when opened from disk, as browsers
+// won't fetch from file://
+export async function fetchText(path: string): Promise<string> {
+    const relative = path.replace(/^\//, '');
+    if (window.location.protocol === 'file:' && window.STYLUS_SNAPSHOT) {
+        const contents = window.STYLUS_SNAPSHOT[decodeURIComponent(relative.split('?')[0])];
+        if (contents === undefined) {
+            throw new Error(`${relative} is not in the snapshot`);
+        }
+        return contents;
+    }
+    const response = await fetch(relative);
+    if (!response.ok) {
+        throw new Error(`HTTP error! status: ${response.status}`);
+    }
+    return await response.text();
+}
+
 // Helper function for status handling
 export function getStatusClass(status: Status): string {
     return `status-${status}`;
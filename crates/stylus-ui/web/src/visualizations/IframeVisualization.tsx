@@ -11,7 +11,9 @@ function injectStylesIntoDocument(document: Document, cacheBuster: boolean = fal
     
     const css = document.createElement('link');
     css.rel = "stylesheet";
-    css.href = cacheBuster ? `/style.css?t=${new Date().valueOf()}` : '/style.css';
+    // Resolved against the page rather than the iframe, which may be in another directory
+    const href = new URL('style.css', window.location.href).href;
+    css.href = cacheBuster ? `${href}?t=${new Date().valueOf()}` : href;
     css.id = styleId;
     
     css.onload = function() {
@@ -1,5 +1,6 @@
 import { useEffect, useRef } from "react";
 import { VisualizationState } from "./VisualizationState.tsx";
+import { fetchText } from "../utils.tsx";
 
 // SVG Visualization Component
 interface SVGVisualizationProps {
@@ -30,8 +31,7 @@ export function SVGVisualization({ state, url }: SVGVisualizationProps) {
         }
 
         // Fetch CSS directly with cache buster
-        fetch(`/style.css?t=${new Date().valueOf()}`)
-            .then(res => res.text())
+        fetchText(`style.css?t=${new Date().valueOf()}`)
             .then(cssText => {
                 styleRef.current!.textContent = cssText;
             })
@@ -51,8 +51,7 @@ export function SVGVisualization({ state, url }: SVGVisualizationProps) {
         }
 
         // Load SVG with cache buster to force re-render when status changes
-        const loadSVG = fetch(`${url}?t=${new Date().valueOf()}`)
-            .then(res => res.text())
+        const loadSVG = fetchText(`${url}?t=${new Date().valueOf()}`)
             .then(svgText => {
                 // Update SVG content
                 svgContainer.innerHTML = svgText;
@@ -0,0 +1,175 @@
+use std::error::Error;
+use std::time::Duration;
+
+use subprocess::{Exec, Redirection};
+
+use crate::config::{AgentConfig, Config, CONFIG_ERROR_EXIT};
+use crate::monitor::Monitor;
+
+/// The longest wait between attempts to push results to an unreachable server.
+const MAX_RETRY: Duration = Duration::from_secs(300);
+
+/// Runs the monitors locally, pushing their state to the central server on every interval. Each push carries the
+/// full state of every monitor, so while the server is unreachable the latest state is kept and retried with
+/// backoff, replacing the older state that couldn't be delivered.
+pub fn run(config: Config, agent: AgentConfig) {
+    let monitor = match Monitor::new(&config) {
+        Ok(monitor) => monitor,
+        Err(e) => {
+            eprintln!();
+            eprintln!("Fatal error parsing monitor configuration:");
+            eprintln!("{e}");
+            std::process::exit(CONFIG_ERROR_EXIT);
+        }
+    };
+    eprintln!(
+        "Stylus agent {} is pushing to {}!",
+        agent.name, agent.server
+    );
+
+    let mut wait = agent.interval;
+    loop {
+        std::thread::sleep(wait);
+        let body = match serde_json::to_string(&monitor.status()) {
+            Ok(body) => body,
+            Err(e) => {
+                error!("Failed to serialize the monitor status: {e}");
+                continue;
+            }
+        };
+        match push(&agent, &body) {
+            Ok(()) => {
+                if wait != agent.interval {
+                    info!("Reconnected to {}", agent.server);
+                }
+                wait = agent.interval;
+            }
+            Err(e) => {
+                wait = (wait * 2).min(MAX_RETRY).max(agent.interval);
+                warn!(
+                    "Failed to push results to {}: {e} (retrying in {wait:?})",
+                    agent.server
+                );
+            }
+        }
+    }
+}
+
+/// The URL that an agent pushes its results to.
+fn ingest_url(agent: &AgentConfig) -> String {
+    format!(
+        "{}/ingest/{}",
+        agent.server.trim_end_matches('/'),
+        agent.name
+    )
+}
+
+/// Builds a curl configuration for the push, which is passed on stdin so that the token doesn't appear in the
+/// process list.
+fn curl_config(agent: &AgentConfig, body: &str) -> String {
+    let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+    [
+        ("url", ingest_url(agent)),
+        ("header", format!("Authorization: Bearer {}", agent.token)),
+        ("header", "Content-Type: application/json".to_string()),
+        ("data-binary", body.to_string()),
+    ]
+    .iter()
+    .map(|(key, value)| format!("{key} = {}\n", quote(value)))
+    .collect()
+}
+
+fn push(agent: &AgentConfig, body: &str) -> Result<(), Box<dyn Error>> {
+    let timeout = agent.interval.max(Duration::from_secs(10));
+    let capture = Exec::cmd("/usr/bin/env")
+        .args(&[
+            "curl",
+            "--silent",
+            "--show-error",
+            "--fail",
+            "--max-time",
+            &timeout.as_secs().to_string(),
+            "--config",
+            "-",
+        ])
+        .stdin(curl_config(agent, body).as_str())
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .capture()?;
+    if !capture.success() {
+        return Err(capture.stderr_str().trim().to_string().into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    fn agent(server: String) -> AgentConfig {
+        AgentConfig {
+            server,
+            name: "cabin".to_string(),
+            token: "s3cr\"t".to_string(),
+            interval: Duration::from_secs(1),
+        }
+    }
+
+    #[test]
+    fn test_curl_config() {
+        let agent = agent("http://central:8000/".to_string());
+        assert_eq!(
+            curl_config(&agent, r#"{"monitors":["a\\b"]}"#),
+            concat!(
+                "url = \"http://central:8000/ingest/cabin\"\n",
+                "header = \"Authorization: Bearer s3cr\\\"t\"\n",
+                "header = \"Content-Type: application/json\"\n",
+                "data-binary = \"{\\\"monitors\\\":[\\\"a\\\\\\\\b\\\"]}\"\n",
+            )
+        );
+    }
+
+    /// Pushes to a server on localhost that records the request.
+    #[test]
+    fn test_push() -> Result<(), Box<dyn Error>> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let agent = agent(format!(
+            "http://127.0.0.1:{}",
+            listener.local_addr()?.port()
+        ));
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![];
+            let mut buf = [0; 1024];
+            while !String::from_utf8_lossy(&request).ends_with("{\"monitors\":[]}") {
+                let n = stream.read(&mut buf).unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..n]);
+            }
+            write!(
+                stream,
+                "HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n"
+            )
+            .unwrap();
+            String::from_utf8_lossy(&request).into_owned()
+        });
+
+        push(&agent, r#"{"monitors":[]}"#)?;
+        let request = server.join().unwrap();
+        assert!(
+            request.starts_with("POST /ingest/cabin HTTP/1.1\r\n"),
+            "{request}"
+        );
+        assert!(
+            request.contains("Authorization: Bearer s3cr\"t\r\n"),
+            "{request}"
+        );
+
+        // Nothing listens on the port once the listener is dropped
+        assert!(push(&agent, "{}").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,609 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::monitor::TriggerError;
+use crate::status::{MonitorChildStatus, MonitorState, Status, StatusState};
+
+/// The number of monitors returned by a list request when no `limit` is given.
+const DEFAULT_LIMIT: usize = 100;
+/// The largest `limit` accepted by a list request.
+const MAX_LIMIT: usize = 1000;
+
+/// An error from the REST API, which is always returned as `{"error": {"code": ..., "message": ...}}`.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn not_found(message: impl ToString) -> Self {
+        ApiError {
+            status: StatusCode::NOT_FOUND,
+            code: "not_found",
+            message: message.to_string(),
+        }
+    }
+
+    pub fn bad_request(message: impl ToString) -> Self {
+        ApiError {
+            status: StatusCode::BAD_REQUEST,
+            code: "bad_request",
+            message: message.to_string(),
+        }
+    }
+
+    pub fn unauthorized(message: impl ToString) -> Self {
+        ApiError {
+            status: StatusCode::UNAUTHORIZED,
+            code: "unauthorized",
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<TriggerError> for ApiError {
+    fn from(error: TriggerError) -> Self {
+        let (status, code) = match error {
+            TriggerError::NotFound(_) => (StatusCode::NOT_FOUND, "not_found"),
+            TriggerError::NotRunnable(_) => (StatusCode::BAD_REQUEST, "bad_request"),
+            TriggerError::Running(_) => (StatusCode::CONFLICT, "running"),
+            TriggerError::TimedOut(_) => (StatusCode::GATEWAY_TIMEOUT, "timed_out"),
+        };
+        ApiError {
+            status,
+            code,
+            message: error.to_string(),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = json!({ "error": { "code": self.code, "message": self.message } });
+        (self.status, Json(body)).into_response()
+    }
+}
+
+/// The query parameters of `/api/v1/monitors`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MonitorQuery {
+    /// A comma-separated list of states.
+    pub status: Option<String>,
+    pub tag: Option<String>,
+    /// A glob matched against the monitor ID.
+    pub id: Option<String>,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+/// The query parameters of `/api/monitors/:id/run`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TriggerQuery {
+    /// Wait for the run to finish and return the resulting state.
+    #[serde(default)]
+    pub wait: bool,
+}
+
+/// A page of monitors.
+#[derive(Debug, Serialize)]
+pub struct MonitorList {
+    pub monitors: Vec<MonitorState>,
+    /// The number of monitors that matched the filters, across all pages.
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+/// A single child of a group monitor.
+#[derive(Debug, Serialize)]
+pub struct ChildResponse {
+    pub id: String,
+    pub monitor: String,
+    #[serde(flatten)]
+    pub child: MonitorChildStatus,
+}
+
+/// Lists the monitors that match the filters of the query, one page at a time.
+pub fn list_monitors(status: &Status, query: &MonitorQuery) -> Result<MonitorList, ApiError> {
+    let states = query
+        .status
+        .as_deref()
+        .map(parse_states)
+        .transpose()?
+        .unwrap_or_default();
+    let glob = query
+        .id
+        .as_deref()
+        .map(glob::Pattern::new)
+        .transpose()
+        .map_err(|e| ApiError::bad_request(format!("Invalid id pattern: {e}")))?;
+    let offset = query.offset.unwrap_or_default();
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err(ApiError::bad_request(format!(
+            "limit must be between 1 and {MAX_LIMIT}"
+        )));
+    }
+
+    let matching: Vec<_> = status
+        .monitors
+        .iter()
+        .map(|monitor| monitor.read())
+        .filter(|monitor| {
+            states.is_empty()
+                || states.contains(&monitor.status.status.unwrap_or(StatusState::Blank))
+        })
+        .filter(|monitor| {
+            query
+                .tag
+                .as_ref()
+                .is_none_or(|tag| monitor.tags.contains(tag))
+        })
+        .filter(|monitor| glob.as_ref().is_none_or(|glob| glob.matches(&monitor.id)))
+        .collect();
+    Ok(MonitorList {
+        total: matching.len(),
+        monitors: matching
+            .iter()
+            .skip(offset)
+            .take(limit)
+            .map(|monitor| (**monitor).clone())
+            .collect(),
+        offset,
+        limit,
+    })
+}
+
+/// Finds a single monitor by ID.
+pub fn get_monitor(status: &Status, id: &str) -> Result<MonitorState, ApiError> {
+    status
+        .monitors
+        .iter()
+        .map(|monitor| monitor.read())
+        .find(|monitor| monitor.id == id)
+        .map(|monitor| (*monitor).clone())
+        .ok_or_else(|| ApiError::not_found(format!("No monitor with id '{id}'")))
+}
+
+/// Finds a single child of a group monitor by ID.
+pub fn get_child(status: &Status, id: &str, child: &str) -> Result<ChildResponse, ApiError> {
+    let mut monitor = get_monitor(status, id)?;
+    let Some(status) = monitor.children.remove(child) else {
+        return Err(ApiError::not_found(format!(
+            "Monitor '{id}' has no child with id '{child}'"
+        )));
+    };
+    Ok(ChildResponse {
+        id: child.to_string(),
+        monitor: monitor.id,
+        child: status,
+    })
+}
+
+fn parse_states(s: &str) -> Result<Vec<StatusState>, ApiError> {
+    s.split(',')
+        .map(|state| {
+            serde_json::from_value(Value::String(state.trim().to_lowercase()))
+                .map_err(|_| ApiError::bad_request(format!("Invalid status '{state}'")))
+        })
+        .collect()
+}
+
+/// The OpenAPI document describing the v1 API.
+pub fn openapi(version: &str) -> Value {
+    let state = json!({
+        "type": "string",
+        "enum": ["blank", "green", "yellow", "red", "blue", "orange"],
+    });
+    let error = json!({ "$ref": "#/components/responses/Error" });
+    let id = |name: &str, description: &str| {
+        json!({
+            "name": name,
+            "in": "path",
+            "required": true,
+            "description": description,
+            "schema": { "type": "string" },
+        })
+    };
+    let query = |name: &str, description: &str, schema: Value| {
+        json!({
+            "name": name,
+            "in": "query",
+            "description": description,
+            "schema": schema,
+        })
+    };
+    let ok = |description: &str, schema: &str| {
+        json!({
+            "description": description,
+            "content": { "application/json": { "schema": { "$ref": format!("#/components/schemas/{schema}") } } },
+        })
+    };
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Stylus",
+            "description": "The status of the monitors of a Stylus server.",
+            "version": version,
+        },
+        "paths": {
+            "/api/v1/monitors": {
+                "get": {
+                    "summary": "List monitors",
+                    "operationId": "listMonitors",
+                    "parameters": [
+                        query("status", "A comma-separated list of states to include", json!({ "type": "string" })),
+                        query("tag", "Only include monitors with this tag", json!({ "type": "string" })),
+                        query("id", "A glob matched against the monitor ID", json!({ "type": "string" })),
+                        query("offset", "The number of monitors to skip", json!({ "type": "integer", "minimum": 0, "default": 0 })),
+                        query("limit", "The maximum number of monitors to return", json!({ "type": "integer", "minimum": 1, "maximum": MAX_LIMIT, "default": DEFAULT_LIMIT })),
+                    ],
+                    "responses": { "200": ok("A page of monitors", "MonitorList"), "400": error },
+                },
+            },
+            "/api/v1/monitors/{id}": {
+                "get": {
+                    "summary": "Get a monitor",
+                    "operationId": "getMonitor",
+                    "parameters": [id("id", "The monitor ID")],
+                    "responses": { "200": ok("The monitor", "Monitor"), "404": error },
+                },
+            },
+            "/api/v1/monitors/{id}/children/{child}": {
+                "get": {
+                    "summary": "Get a child of a group monitor",
+                    "operationId": "getChild",
+                    "parameters": [id("id", "The monitor ID"), id("child", "The child ID")],
+                    "responses": { "200": ok("The child", "Child"), "404": error },
+                },
+            },
+            "/api/monitors/{id}/run": {
+                "post": {
+                    "summary": "Run a monitor now",
+                    "description": "Only available when `server.trigger` is configured. Returns `404` otherwise.",
+                    "operationId": "runMonitor",
+                    "security": [{ "token": [] }],
+                    "parameters": [
+                        id("id", "The monitor ID"),
+                        query("wait", "Wait for the run to finish (up to five minutes) and return the monitor", json!({ "type": "boolean", "default": false })),
+                    ],
+                    "responses": {
+                        "200": ok("The monitor after the run, with `wait=true`", "Monitor"),
+                        "202": ok("The run was requested", "Triggered"),
+                        "400": error,
+                        "401": error,
+                        "404": error,
+                        "409": error,
+                        "504": error,
+                    },
+                },
+            },
+        },
+        "components": {
+            "schemas": {
+                "State": state,
+                "Status": {
+                    "type": "object",
+                    "properties": {
+                        "status": { "allOf": [{ "$ref": "#/components/schemas/State" }], "nullable": true },
+                        "code": { "type": "integer" },
+                        "description": { "type": "string" },
+                        "css": {
+                            "type": "object",
+                            "properties": { "metadata": { "type": "object", "additionalProperties": { "type": "string" } } },
+                        },
+                        "metadata": { "type": "object", "additionalProperties": { "type": "string" } },
+                        "last_change": { "type": "string", "format": "date-time" },
+                        "last_success": { "type": "string", "format": "date-time" },
+                        "last_failure": { "type": "string", "format": "date-time" },
+                    },
+                    "additionalProperties": { "type": "integer", "description": "Child counts of groups, eg: children_red" },
+                },
+                "Monitor": {
+                    "type": "object",
+                    "required": ["id", "config", "children"],
+                    "properties": {
+                        "id": { "type": "string" },
+                        "config": { "type": "object", "description": "The test configuration of the monitor" },
+                        "status": { "$ref": "#/components/schemas/Status" },
+                        "children": {
+                            "type": "object",
+                            "additionalProperties": {
+                                "type": "object",
+                                "properties": {
+                                    "axes": { "type": "object" },
+                                    "status": { "$ref": "#/components/schemas/Status" },
+                                },
+                            },
+                        },
+                        "tags": { "type": "array", "items": { "type": "string" } },
+                    },
+                },
+                "Child": {
+                    "type": "object",
+                    "required": ["id", "monitor", "axes"],
+                    "properties": {
+                        "id": { "type": "string" },
+                        "monitor": { "type": "string", "description": "The ID of the group monitor" },
+                        "axes": { "type": "object" },
+                        "status": { "$ref": "#/components/schemas/Status" },
+                    },
+                },
+                "MonitorList": {
+                    "type": "object",
+                    "required": ["monitors", "total", "offset", "limit"],
+                    "properties": {
+                        "monitors": { "type": "array", "items": { "$ref": "#/components/schemas/Monitor" } },
+                        "total": { "type": "integer", "description": "The number of monitors matching the filters" },
+                        "offset": { "type": "integer" },
+                        "limit": { "type": "integer" },
+                    },
+                },
+                "Triggered": {
+                    "type": "object",
+                    "required": ["id", "triggered"],
+                    "properties": {
+                        "id": { "type": "string" },
+                        "triggered": { "type": "boolean" },
+                    },
+                },
+                "Error": {
+                    "type": "object",
+                    "required": ["error"],
+                    "properties": {
+                        "error": {
+                            "type": "object",
+                            "required": ["code", "message"],
+                            "properties": {
+                                "code": { "type": "string", "enum": ["bad_request", "unauthorized", "not_found", "running", "timed_out"] },
+                                "message": { "type": "string" },
+                            },
+                        },
+                    },
+                },
+            },
+            "responses": {
+                "Error": {
+                    "description": "An error",
+                    "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Error" } } },
+                },
+            },
+            "securitySchemes": {
+                "token": { "type": "http", "scheme": "bearer", "description": "The token of `server.trigger`" },
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{parse_monitor_config, CssMetadataConfig};
+    use crate::worker::monitor_run;
+    use keepcalm::SharedMut;
+    use std::path::Path;
+
+    fn status() -> Status {
+        let monitors = ["success", "group_complete", "parse_json"]
+            .iter()
+            .map(|test| {
+                let config =
+                    parse_monitor_config(Path::new(&format!("src/testcases/{test}/config.yaml")))
+                        .unwrap();
+                let mut state = MonitorState::from(&config);
+                if *test == "success" {
+                    state.status.status = Some(StatusState::Green);
+                    state.tags = vec!["web".into()];
+                }
+                SharedMut::new(state)
+            })
+            .collect();
+        Status { monitors }
+    }
+
+    /// The states of monitors that have actually run, so that their status, metadata and child counts are set.
+    fn run_status() -> Status {
+        let monitors = ["metadata_success", "group_rollup", "rules_metadata"]
+            .iter()
+            .map(|test| {
+                let config =
+                    parse_monitor_config(Path::new(&format!("src/testcases/{test}/config.yaml")))
+                        .unwrap();
+                let mut state = MonitorState::from(&config);
+                let metadata = CssMetadataConfig::default();
+                monitor_run(&config, &Default::default(), &mut |id, m| {
+                    state.process_message(id, m, &metadata, &mut |_| {})
+                })
+                .1
+                .unwrap();
+                state.tags = vec!["web".into()];
+                SharedMut::new(state)
+            })
+            .collect();
+        Status { monitors }
+    }
+
+    /// Checks a value against a schema of the OpenAPI document. Unlike OpenAPI, an object may only have the
+    /// properties that its schema lists, so that fields missing from the document are caught.
+    fn check(document: &Value, schema: &Value, value: &Value, path: &str) -> Result<(), String> {
+        if let Some(reference) = schema["$ref"].as_str() {
+            let name = reference.trim_start_matches("#/components/schemas/");
+            return check(
+                document,
+                &document["components"]["schemas"][name],
+                value,
+                path,
+            );
+        }
+        if value.is_null() && schema["nullable"] == true {
+            return Ok(());
+        }
+        if let Some(all) = schema["allOf"].as_array() {
+            return all
+                .iter()
+                .try_for_each(|schema| check(document, schema, value, path));
+        }
+        if let Some(options) = schema["enum"].as_array() {
+            if !options.contains(value) {
+                return Err(format!("{path}: {value} is not one of {options:?}"));
+            }
+        }
+        let matches = match schema["type"].as_str() {
+            Some("string") => value.is_string(),
+            Some("integer") => value.is_i64() || value.is_u64(),
+            Some("number") => value.is_number(),
+            Some("boolean") => value.is_boolean(),
+            Some("array") => value.is_array(),
+            Some("object") => value.is_object(),
+            _ => true,
+        };
+        if !matches {
+            return Err(format!("{path}: {value} is not a {}", schema["type"]));
+        }
+
+        if let Some(items) = value.as_array() {
+            for (index, item) in items.iter().enumerate() {
+                check(
+                    document,
+                    &schema["items"],
+                    item,
+                    &format!("{path}[{index}]"),
+                )?;
+            }
+        }
+        if let Some(object) = value.as_object() {
+            for required in schema["required"].as_array().into_iter().flatten() {
+                let required = required.as_str().unwrap();
+                if !object.contains_key(required) {
+                    return Err(format!("{path}: missing {required}"));
+                }
+            }
+            // Free-form objects such as `config` and `axes` have no properties to check
+            let free_form =
+                schema.get("properties").is_none() && schema.get("additionalProperties").is_none();
+            for (key, value) in object {
+                let path = format!("{path}.{key}");
+                match (&schema["properties"][key], &schema["additionalProperties"]) {
+                    (Value::Null, Value::Null) if free_form => {}
+                    (Value::Null, Value::Null) => return Err(format!("{path} is not documented")),
+                    (Value::Null, additional) => check(document, additional, value, &path)?,
+                    (property, _) => check(document, property, value, &path)?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_schema(name: &str, value: impl Serialize) {
+        let document = openapi("test");
+        let value = serde_json::to_value(value).unwrap();
+        let schema = json!({ "$ref": format!("#/components/schemas/{name}") });
+        if let Err(error) = check(&document, &schema, &value, name) {
+            panic!("{error}\n{value:#}");
+        }
+    }
+
+    /// Checks the responses of the API against the schemas of the OpenAPI document, so the two can't drift apart.
+    #[test]
+    fn openapi_schemas() {
+        let status = run_status();
+        let list = list_monitors(&status, &MonitorQuery::default()).unwrap();
+        assert_eq!(list.monitors.len(), 3);
+        assert!(list.monitors[0].status.metadata.contains_key("key"));
+        assert!(list.monitors[1].status.child_counts.is_some());
+        check_schema("MonitorList", &list);
+        check_schema("Monitor", get_monitor(&status, "group_rollup").unwrap());
+
+        let group = get_monitor(&status, "group_rollup").unwrap();
+        let child = group.children.keys().next().unwrap();
+        check_schema("Child", get_child(&status, "group_rollup", child).unwrap());
+        // Returned by a run with `wait=true`
+        check_schema("Monitor", &*status.monitors[1].read());
+        check_schema(
+            "Triggered",
+            json!({ "id": "group_rollup", "triggered": true }),
+        );
+
+        let errors = [
+            ApiError::bad_request("bad"),
+            ApiError::unauthorized("token"),
+            ApiError::not_found("missing"),
+            TriggerError::Running("id".into()).into(),
+            TriggerError::TimedOut("id".into()).into(),
+        ];
+        for error in errors {
+            let body = json!({ "error": { "code": error.code, "message": error.message } });
+            check_schema("Error", body);
+        }
+    }
+
+    fn ids(list: &MonitorList) -> Vec<&str> {
+        list.monitors.iter().map(|m| m.id.as_str()).collect()
+    }
+
+    fn query(s: &str) -> MonitorQuery {
+        serde_json::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn filter_monitors() {
+        let status = status();
+        let all = list_monitors(&status, &MonitorQuery::default()).unwrap();
+        assert_eq!(ids(&all), ["success", "group_complete", "parse_json"]);
+        assert_eq!(all.total, 3);
+
+        let list = list_monitors(&status, &query(r#"{"status": "green,red"}"#)).unwrap();
+        assert_eq!(ids(&list), ["success"]);
+        let list = list_monitors(&status, &query(r#"{"status": "blank"}"#)).unwrap();
+        assert_eq!(ids(&list), ["group_complete", "parse_json"]);
+        let list = list_monitors(&status, &query(r#"{"tag": "web"}"#)).unwrap();
+        assert_eq!(ids(&list), ["success"]);
+        let list = list_monitors(&status, &query(r#"{"id": "*_*"}"#)).unwrap();
+        assert_eq!(ids(&list), ["group_complete", "parse_json"]);
+
+        let error = list_monitors(&status, &query(r#"{"status": "purple"}"#)).unwrap_err();
+        assert_eq!(error.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn paginate_monitors() {
+        let status = status();
+        let list = list_monitors(&status, &query(r#"{"offset": 1, "limit": 1}"#)).unwrap();
+        assert_eq!(ids(&list), ["group_complete"]);
+        assert_eq!((list.total, list.offset, list.limit), (3, 1, 1));
+        let list = list_monitors(&status, &query(r#"{"offset": 5}"#)).unwrap();
+        assert!(list.monitors.is_empty());
+        assert!(list_monitors(&status, &query(r#"{"limit": 0}"#)).is_err());
+    }
+
+    #[test]
+    fn find_monitors() {
+        let status = status();
+        assert_eq!(get_monitor(&status, "success").unwrap().id, "success");
+        let error = get_monitor(&status, "missing").unwrap_err();
+        assert_eq!(error.status, StatusCode::NOT_FOUND);
+
+        let id = get_monitor(&status, "group_complete")
+            .unwrap()
+            .children
+            .into_keys()
+            .next()
+            .unwrap();
+        let child =
+            serde_json::to_value(get_child(&status, "group_complete", &id).unwrap()).unwrap();
+        assert_eq!(child["id"], id);
+        assert_eq!(child["monitor"], "group_complete");
+        assert!(child["axes"].is_object());
+        assert!(get_child(&status, "group_complete", "missing").is_err());
+        assert!(get_child(&status, "success", &id).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use crate::status::{MonitorStatus, StatusState};
+
+/// The height of a badge, in pixels.
+const HEIGHT: usize = 20;
+
+/// Renders a shields-style badge showing the state of a monitor or group child.
+pub fn render_badge(label: &str, status: &MonitorStatus) -> String {
+    let state = status.status.unwrap_or(StatusState::Blank);
+    let message = state.to_string().to_lowercase();
+    let color = status
+        .css
+        .metadata
+        .get("color")
+        .filter(|color| is_safe_color(color))
+        .cloned()
+        .unwrap_or_else(|| default_color(state).to_string());
+
+    let label_width = text_width(label) + 10;
+    let message_width = text_width(&message) + 10;
+    let width = label_width + message_width;
+    let label_x = label_width / 2;
+    let message_x = label_width + message_width / 2;
+    let (label, message) = (escape(label), escape(&message));
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{HEIGHT}" role="img" aria-label="{label}: {message}">
+<title>{label}: {message}</title>
+<linearGradient id="s" x2="0" y2="100%"><stop offset="0" stop-color="#bbb" stop-opacity=".1"/><stop offset="1" stop-opacity=".1"/></linearGradient>
+<clipPath id="r"><rect width="{width}" height="{HEIGHT}" rx="3" fill="#fff"/></clipPath>
+<g clip-path="url(#r)"><rect width="{label_width}" height="{HEIGHT}" fill="#555"/><rect x="{label_width}" width="{message_width}" height="{HEIGHT}" fill="{color}"/><rect width="{width}" height="{HEIGHT}" fill="url(#s)"/></g>
+<g text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="11">
+<text x="{label_x}" y="15" fill="#010101" fill-opacity=".3">{label}</text><text x="{label_x}" y="14" fill="#fff">{label}</text>
+<text x="{message_x}" y="15" fill="#010101" fill-opacity=".3">{message}</text><text x="{message_x}" y="14" fill="#fff">{message}</text>
+</g>
+</svg>
+"##
+    )
+}
+
+/// The color of a state when the CSS metadata doesn't give one.
+fn default_color(state: StatusState) -> &'static str {
+    match state {
+        StatusState::Blank => "#9f9f9f",
+        StatusState::Green => "#4c1",
+        StatusState::Yellow => "#dfb317",
+        StatusState::Red => "#e05d44",
+        StatusState::Blue => "#007ec6",
+        StatusState::Orange => "#fe7d37",
+    }
+}
+
+/// Colors come from the configuration, so only plain color values are placed in the SVG.
+fn is_safe_color(color: &str) -> bool {
+    !color.is_empty()
+        && color
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "#(),.% ".contains(c))
+}
+
+/// Approximates the width of text in 11px Verdana.
+fn text_width(s: &str) -> usize {
+    s.chars()
+        .map(|c| match c {
+            'i' | 'j' | 'l' | '.' | ',' | ':' | ';' | '|' | '!' | '\'' => 4,
+            'f' | 'r' | 't' | ' ' | '-' | '(' | ')' | '[' | ']' => 5,
+            'm' | 'w' | 'M' | 'W' => 10,
+            c if c.is_ascii_uppercase() || c.is_ascii_digit() => 8,
+            _ => 7,
+        })
+        .sum()
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    fn status(state: StatusState, color: Option<&str>) -> MonitorStatus {
+        let mut status = MonitorStatus {
+            status: Some(state),
+            ..Default::default()
+        };
+        if let Some(color) = color {
+            status.css.metadata = Arc::new(BTreeMap::from([("color".into(), color.into())]));
+        }
+        status
+    }
+
+    #[test]
+    fn badge_colors() {
+        let badge = render_badge("web", &status(StatusState::Red, None));
+        assert!(badge.contains(r##"fill="#e05d44""##), "{badge}");
+        assert!(badge.contains("<title>web: red</title>"));
+
+        let badge = render_badge("web", &status(StatusState::Green, Some("#d0e6a5")));
+        assert!(badge.contains(r##"fill="#d0e6a5""##), "{badge}");
+
+        // Colors that could break out of the attribute fall back to the default
+        let badge = render_badge("web", &status(StatusState::Green, Some("\"/><script>")));
+        assert!(badge.contains(r##"fill="#4c1""##), "{badge}");
+        assert!(!badge.contains("<script>"));
+
+        let badge = render_badge("a<b>", &MonitorStatus::default());
+        assert!(
+            badge.contains("<title>a&lt;b&gt;: blank</title>"),
+            "{badge}"
+        );
+    }
+}
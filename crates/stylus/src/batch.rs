@@ -0,0 +1,269 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+use keepcalm::SharedMut;
+use serde::Serialize;
+
+use crate::config::*;
+use crate::monitor::evaluate_composites;
+use crate::monitors::composite::evaluation_order;
+use crate::status::*;
+use crate::worker::{monitor_run, WorkerMessage};
+
+/// The status of a monitor or group child after it was run with `stylus test`.
+#[derive(Debug, Serialize)]
+pub struct TestStatus {
+    pub status: Option<StatusState>,
+    pub code: i64,
+    pub description: String,
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl From<&MonitorStatus> for TestStatus {
+    fn from(status: &MonitorStatus) -> Self {
+        TestStatus {
+            status: status.status,
+            code: status.code,
+            description: status.description.clone(),
+            metadata: status.metadata.clone(),
+        }
+    }
+}
+
+/// The result of running a single monitor with `stylus test`.
+#[derive(Debug, Serialize)]
+pub struct TestResult {
+    pub id: String,
+    #[serde(flatten)]
+    pub status: TestStatus,
+    pub duration_ms: u128,
+    pub log: Vec<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub children: BTreeMap<String, TestStatus>,
+}
+
+impl TestResult {
+    /// Whether the monitor and all of its children ended green.
+    pub fn is_green(&self) -> bool {
+        std::iter::once(&self.status)
+            .chain(self.children.values())
+            .all(|status| status.status == Some(StatusState::Green))
+    }
+}
+
+/// Finds the monitors whose IDs match any of the patterns, failing if a pattern matches nothing.
+pub fn select(
+    monitors: &[MonitorDirConfig],
+    patterns: &[String],
+) -> Result<Vec<usize>, Box<dyn Error>> {
+    let mut selected = vec![];
+    for pattern in patterns {
+        let glob = glob::Pattern::new(pattern)
+            .map_err(|e| format!("Invalid monitor pattern {pattern:?}: {e}"))?;
+        let matches: Vec<_> = (0..monitors.len())
+            .filter(|index| *pattern == monitors[*index].id || glob.matches(&monitors[*index].id))
+            .collect();
+        if matches.is_empty() {
+            return Err(format!("Unable to locate monitor with id '{pattern}'").into());
+        }
+        selected.extend(matches);
+    }
+    selected.sort();
+    selected.dedup();
+    Ok(selected)
+}
+
+/// Runs the selected monitors once, in parallel, with their configured timeouts. Composites are evaluated from
+/// a run of every other monitor.
+pub fn run_monitors(
+    config: &Config,
+    monitors: &[MonitorDirConfig],
+    selected: &[usize],
+) -> Result<Vec<TestResult>, Box<dyn Error>> {
+    let is_composite =
+        |index: &usize| matches!(monitors[*index].root, MonitorDirRootConfig::Composite(_));
+    let runs: Vec<usize> = if selected.iter().any(is_composite) {
+        (0..monitors.len())
+            .filter(|index| !is_composite(index))
+            .collect()
+    } else {
+        selected.to_vec()
+    };
+    let MonitorRuns { states, durations } = run_states(config, monitors, &runs)?;
+
+    Ok(selected
+        .iter()
+        .map(|index| {
+            let state = states[*index].read();
+            TestResult {
+                id: state.id.clone(),
+                status: (&state.status).into(),
+                duration_ms: durations[*index].as_millis(),
+                log: state.status.log.iter().cloned().collect(),
+                children: state
+                    .children
+                    .iter()
+                    .map(|(id, child)| (id.clone(), (&child.status).into()))
+                    .collect(),
+            }
+        })
+        .collect())
+}
+
+/// The state of every monitor after [`run_states`], along with how long each run took.
+pub struct MonitorRuns {
+    pub states: Vec<SharedMut<MonitorState>>,
+    pub durations: Vec<Duration>,
+}
+
+/// Runs the given monitors once, in parallel, and then evaluates the composites. The monitors that weren't run
+/// are left blank.
+pub fn run_states(
+    config: &Config,
+    monitors: &[MonitorDirConfig],
+    runs: &[usize],
+) -> Result<MonitorRuns, Box<dyn Error>> {
+    let metadata = &config.css.metadata;
+    let mut durations = vec![Duration::ZERO; monitors.len()];
+    let mut states: Vec<MonitorState> = monitors.iter().map(|monitor| monitor.into()).collect();
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = runs
+            .iter()
+            .map(|index| {
+                let monitor = &monitors[*index];
+                (*index, scope.spawn(move || run_once(monitor, metadata)))
+            })
+            .collect();
+        for (index, handle) in handles {
+            let (state, duration) = handle.join().expect("Monitor panicked");
+            states[index] = state;
+            durations[index] = duration;
+        }
+    });
+
+    let states: Vec<_> = states.into_iter().map(SharedMut::new).collect();
+    let composites: Vec<_> = evaluation_order(monitors)?
+        .into_iter()
+        .filter_map(|index| match &monitors[index].root {
+            MonitorDirRootConfig::Composite(composite) => {
+                Some((states[index].clone(), composite.clone()))
+            }
+            _ => None,
+        })
+        .collect();
+    evaluate_composites(&states, &composites, metadata);
+    Ok(MonitorRuns { states, durations })
+}
+
+fn run_once(monitor: &MonitorDirConfig, metadata: &CssMetadataConfig) -> (MonitorState, Duration) {
+    let mut state: MonitorState = monitor.into();
+    let start = Instant::now();
+    let (_, result) = monitor_run(monitor, &Default::default(), &mut |id, msg| {
+        state.process_message(id, msg, metadata, &mut |_| {})
+    });
+    if let Err(e) = result {
+        // Report failed runs the same way the monitor thread does
+        let message = WorkerMessage::AbnormalTermination(e.to_string());
+        if let Err(e) = state.process_message(&monitor.id, message, metadata, &mut |_| {}) {
+            error!("[{}] Failed to process message: {e}", monitor.id);
+        }
+    }
+    (state, start.elapsed())
+}
+
+/// Prints a table of the results, with each group's children below it.
+pub fn print_table(results: &[TestResult]) {
+    let mut rows = vec![];
+    for result in results {
+        rows.push((
+            result.id.clone(),
+            &result.status,
+            format!("{}ms", result.duration_ms),
+        ));
+        for (id, status) in &result.children {
+            rows.push((format!("  {id}"), status, String::new()));
+        }
+    }
+
+    let width = rows
+        .iter()
+        .map(|(id, _, _)| id.len())
+        .max()
+        .unwrap_or_default()
+        .max("ID".len());
+    println!(
+        "{:width$}  {:6}  {:>5}  {:>8}  DESCRIPTION",
+        "ID", "STATUS", "CODE", "DURATION"
+    );
+    for (id, status, duration) in rows {
+        let state = status
+            .status
+            .map(|s| s.to_string().to_lowercase())
+            .unwrap_or_else(|| "-".to_string());
+        println!(
+            "{id:width$}  {state:6}  {:>5}  {duration:>8}  {}",
+            status.code, status.description
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn config() -> Config {
+        serde_yaml_ng::from_str("version: 1\nserver:\n  port: 8000\nmonitor:\n  dir: /tmp\n")
+            .unwrap()
+    }
+
+    fn monitors(tests: &[&str]) -> Vec<MonitorDirConfig> {
+        tests
+            .iter()
+            .map(|test| {
+                parse_monitor_config(Path::new(&format!("src/testcases/{test}/config.yaml")))
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn select_monitors() {
+        let monitors = monitors(&["group_complete", "group_fail", "parse_json"]);
+        let ids = |patterns: &[&str]| -> Result<Vec<String>, Box<dyn Error>> {
+            let patterns: Vec<_> = patterns.iter().map(|p| p.to_string()).collect();
+            Ok(select(&monitors, &patterns)?
+                .into_iter()
+                .map(|index| monitors[index].id.clone())
+                .collect())
+        };
+        assert_eq!(ids(&["parse_json"]).unwrap(), ["parse_json"]);
+        assert_eq!(
+            ids(&["group_*", "group_fail"]).unwrap(),
+            ["group_complete", "group_fail"]
+        );
+        assert_eq!(ids(&["*"]).unwrap().len(), 3);
+        assert!(ids(&["missing"]).is_err());
+    }
+
+    #[test]
+    fn run_in_parallel() -> Result<(), Box<dyn Error>> {
+        let monitors = monitors(&["success", "group_complete"]);
+        let results = run_monitors(&config(), &monitors, &[0, 1])?;
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].id, "success");
+        assert!(results[0].is_green(), "{:?}", results[0]);
+        assert!(!results[0].log.is_empty());
+        // The group is green, but some of its children are not
+        assert_eq!(results[1].status.status, Some(StatusState::Green));
+        assert_eq!(results[1].children.len(), 4);
+        assert!(!results[1].is_green());
+
+        let json = serde_json::to_value(&results[0])?;
+        assert_eq!(json["status"], "green");
+        assert!(json["duration_ms"].is_number());
+        assert!(json["log"].is_array());
+        Ok(())
+    }
+}
@@ -0,0 +1,317 @@
+use std::error::Error;
+use std::io::IsTerminal;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use subprocess::{Exec, Redirection};
+
+use crate::config::{StatusClientConfig, TriggerClientConfig};
+use crate::monitors::remote::{RemoteMonitorState, RemoteStatus};
+use crate::status::{MonitorStatus, StatusState};
+
+/// Prints the status of the monitors of a running server, refreshing it with `--watch`.
+pub fn run(client: &StatusClientConfig) -> Result<(), Box<dyn Error>> {
+    if let Some(id) = &client.log {
+        print!("{}", get(&format!("{}/log/{id}", client.url))?);
+        return Ok(());
+    }
+
+    let color = std::io::stdout().is_terminal();
+    let Some(interval) = client.watch else {
+        print!("{}", render(&fetch(client)?, client, Utc::now(), color));
+        return Ok(());
+    };
+    loop {
+        // Keep watching through restarts of the server
+        let output = match fetch(client) {
+            Ok(status) => render(&status, client, Utc::now(), color),
+            Err(e) => format!("{e}\n"),
+        };
+        print!(
+            "\x1b[2J\x1b[H{} ({})\n\n{output}",
+            client.url,
+            Utc::now().format("%H:%M:%S")
+        );
+        std::thread::sleep(interval);
+    }
+}
+
+/// Asks the server to run a monitor now, returning whether it ended green if waiting for the result.
+pub fn trigger(trigger: &TriggerClientConfig) -> Result<bool, Box<dyn Error>> {
+    let url = format!(
+        "{}/api/monitors/{}/run{}",
+        trigger.url,
+        trigger.monitor,
+        if trigger.wait { "?wait=true" } else { "" }
+    );
+    let body = post(&url, trigger.token.as_deref())?;
+    if !trigger.wait {
+        println!("Triggered {}", trigger.monitor);
+        return Ok(true);
+    }
+
+    let monitor: RemoteMonitorState =
+        serde_json::from_str(&body).map_err(|e| format!("Invalid status from {url}: {e}"))?;
+    let green = std::iter::once(&monitor.status)
+        .chain(monitor.children.values().map(|child| &child.status))
+        .all(|status| matches!(status, Some(status) if status.status == Some(StatusState::Green)));
+    let client = StatusClientConfig {
+        url: trigger.url.clone(),
+        monitors: vec![],
+        states: vec![],
+        watch: None,
+        log: None,
+    };
+    let status = RemoteStatus {
+        monitors: vec![monitor],
+        ..Default::default()
+    };
+    let color = std::io::stdout().is_terminal();
+    print!("{}", render(&status, &client, Utc::now(), color));
+    Ok(green)
+}
+
+fn fetch(client: &StatusClientConfig) -> Result<RemoteStatus, Box<dyn Error>> {
+    let url = format!("{}/status.json", client.url);
+    serde_json::from_str(&get(&url)?).map_err(|e| format!("Invalid status from {url}: {e}").into())
+}
+
+fn get(url: &str) -> Result<String, Box<dyn Error>> {
+    let capture = Exec::cmd("/usr/bin/env")
+        .args(&[
+            "curl",
+            "--silent",
+            "--show-error",
+            "--fail",
+            "--max-time",
+            "10",
+            url,
+        ])
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .capture()?;
+    if !capture.success() {
+        return Err(format!("Unable to fetch {url}: {}", capture.stderr_str().trim()).into());
+    }
+    Ok(capture.stdout_str())
+}
+
+/// Posts to the API, reporting the message of any error the server returns. The token is passed to curl on stdin so
+/// that it doesn't appear in the process list.
+fn post(url: &str, token: Option<&str>) -> Result<String, Box<dyn Error>> {
+    let config = token
+        .map(|token| {
+            format!(
+                "header = \"Authorization: Bearer {}\"\n",
+                token.replace('\\', "\\\\").replace('"', "\\\"")
+            )
+        })
+        .unwrap_or_default();
+    let capture = Exec::cmd("/usr/bin/env")
+        .args(&[
+            "curl",
+            "--silent",
+            "--show-error",
+            "--request",
+            "POST",
+            "--write-out",
+            "\n%{http_code}",
+            "--config",
+            "-",
+            url,
+        ])
+        .stdin(config.as_str())
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .capture()?;
+    if !capture.success() {
+        return Err(format!("Unable to fetch {url}: {}", capture.stderr_str().trim()).into());
+    }
+    let stdout = capture.stdout_str();
+    let (body, code) = stdout.rsplit_once('\n').unwrap_or_default();
+    if code.starts_with('2') {
+        return Ok(body.to_string());
+    }
+    let message = serde_json::from_str::<serde_json::Value>(body)
+        .ok()
+        .and_then(|error| Some(error["error"]["message"].as_str()?.to_string()))
+        .unwrap_or_else(|| format!("HTTP status {code}"));
+    Err(message.into())
+}
+
+/// Renders a table of the monitors and children that pass the filters, with each group's children below it.
+fn render(
+    status: &RemoteStatus,
+    client: &StatusClientConfig,
+    now: DateTime<Utc>,
+    color: bool,
+) -> String {
+    let matches_id = |id: &str| {
+        client.monitors.is_empty() || client.monitors.iter().any(|pattern| pattern.matches(id))
+    };
+    let matches_state = |status: &MonitorStatus| {
+        client.states.is_empty()
+            || client
+                .states
+                .contains(&status.status.unwrap_or(StatusState::Blank))
+    };
+
+    let blank = MonitorStatus::default();
+    let mut rows = vec![];
+    for monitor in &status.monitors {
+        let status = monitor.status.as_ref().unwrap_or(&blank);
+        let children: Vec<_> = monitor
+            .children
+            .iter()
+            .filter_map(|(id, child)| Some((id, child.status.as_ref()?)))
+            .filter(|(id, status)| {
+                (matches_id(&monitor.id) || matches_id(id)) && matches_state(status)
+            })
+            .collect();
+        if !(matches_id(&monitor.id) && matches_state(status)) && children.is_empty() {
+            continue;
+        }
+        rows.push((monitor.id.clone(), status));
+        for (id, status) in children {
+            rows.push((format!("  {id}"), status));
+        }
+    }
+    if rows.is_empty() {
+        return "No matching monitors\n".to_string();
+    }
+
+    let width = rows
+        .iter()
+        .map(|(id, _)| id.len())
+        .max()
+        .unwrap_or_default()
+        .max("ID".len());
+    let mut output = format!(
+        "{:width$}  {:6}  {:>5}  DESCRIPTION\n",
+        "ID", "STATUS", "AGE"
+    );
+    for (id, status) in rows {
+        let state = status.status.unwrap_or(StatusState::Blank);
+        let name = format!("{:6}", state.to_string().to_lowercase());
+        let age = status
+            .last_change
+            .map(|last_change| format_age(now - last_change))
+            .unwrap_or_else(|| "-".to_string());
+        output += &format!(
+            "{id:width$}  {}  {age:>5}  {}\n",
+            if color { colorize(state, &name) } else { name },
+            status.description
+        );
+    }
+    output
+}
+
+fn colorize(state: StatusState, s: &str) -> String {
+    let code = match state {
+        StatusState::Blank => "2",
+        StatusState::Green => "32",
+        StatusState::Yellow => "33",
+        StatusState::Red => "31",
+        StatusState::Blue => "34",
+        StatusState::Orange => "38;5;208",
+    };
+    format!("\x1b[{code}m{s}\x1b[0m")
+}
+
+/// Formats the time since a status changed in its largest unit (eg: `5m`).
+fn format_age(age: chrono::TimeDelta) -> String {
+    let seconds = age.to_std().unwrap_or(Duration::ZERO).as_secs();
+    match seconds {
+        0..60 => format!("{seconds}s"),
+        60..3600 => format!("{}m", seconds / 60),
+        3600..86400 => format!("{}h", seconds / 3600),
+        _ => format!("{}d", seconds / 86400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status() -> RemoteStatus {
+        serde_json::from_str(
+            r#"{"monitors": [
+                {"id": "web", "status": {"status": "green", "code": 0, "description": "Success",
+                    "css": {"metadata": {}}, "metadata": {}, "last_change": "2025-01-01T11:55:00Z"}},
+                {"id": "ports", "status": {"status": "green", "code": 0, "description": "Success",
+                    "css": {"metadata": {}}, "metadata": {}, "last_change": "2025-01-01T09:00:00Z"},
+                 "children": {
+                    "port-0": {"axes": {"index": 0}, "status": {"status": "red", "code": 1,
+                        "description": "Port down", "css": {"metadata": {}}, "metadata": {}}},
+                    "port-1": {"axes": {"index": 1}, "status": {"status": "green", "code": 0,
+                        "description": "Success", "css": {"metadata": {}}, "metadata": {}}}
+                 }}
+            ]}"#,
+        )
+        .unwrap()
+    }
+
+    fn client(monitors: &[&str], states: &[StatusState]) -> StatusClientConfig {
+        StatusClientConfig {
+            url: "http://localhost:8000".to_string(),
+            monitors: monitors
+                .iter()
+                .map(|m| glob::Pattern::new(m).unwrap())
+                .collect(),
+            states: states.to_vec(),
+            watch: None,
+            log: None,
+        }
+    }
+
+    fn now() -> DateTime<Utc> {
+        "2025-01-01T12:00:00Z".parse().unwrap()
+    }
+
+    #[test]
+    fn render_table() {
+        assert_eq!(
+            render(&status(), &client(&[], &[]), now(), false),
+            concat!(
+                "ID        STATUS    AGE  DESCRIPTION\n",
+                "web       green      5m  Success\n",
+                "ports     green      3h  Success\n",
+                "  port-0  red         -  Port down\n",
+                "  port-1  green       -  Success\n",
+            )
+        );
+        assert!(render(&status(), &client(&[], &[]), now(), true).contains("\x1b[31mred   \x1b[0m"));
+    }
+
+    #[test]
+    fn render_filtered() {
+        // Groups are shown when any of their children match
+        assert_eq!(
+            render(&status(), &client(&[], &[StatusState::Red]), now(), false),
+            concat!(
+                "ID        STATUS    AGE  DESCRIPTION\n",
+                "ports     green      3h  Success\n",
+                "  port-0  red         -  Port down\n",
+            )
+        );
+        assert_eq!(
+            render(&status(), &client(&["we*"], &[]), now(), false),
+            concat!(
+                "ID   STATUS    AGE  DESCRIPTION\n",
+                "web  green      5m  Success\n",
+            )
+        );
+        assert_eq!(
+            render(&status(), &client(&["nothing"], &[]), now(), false),
+            "No matching monitors\n"
+        );
+    }
+
+    #[test]
+    fn test_format_age() {
+        assert_eq!(format_age(chrono::TimeDelta::seconds(-5)), "0s");
+        assert_eq!(format_age(chrono::TimeDelta::seconds(59)), "59s");
+        assert_eq!(format_age(chrono::TimeDelta::seconds(7200)), "2h");
+        assert_eq!(format_age(chrono::TimeDelta::days(3)), "3d");
+    }
+}
@@ -1,6 +1,8 @@
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 
+use super::OutputFormat;
+
 #[derive(Debug, Parser)]
 pub struct Args {
     /// Pass multiple times to increase the level of verbosity (overwritten by STYLUS_LOG)
@@ -22,7 +24,10 @@ pub enum Commands {
     /// Dumps the effective configuration without running
     Dump(DumpArgs),
 
-    /// Runs the given test immediately and displays the status of the given monitor after it completes
+    /// Checks a stylus directory for problems without running any monitors
+    Validate(ValidateArgs),
+
+    /// Runs the given monitors immediately and displays their status after they complete
     Test(TestArgs),
 
     /// Initialize a new stylus directory
@@ -30,6 +35,18 @@ pub enum Commands {
 
     /// Run stylus (default command)
     Run(RunArgs),
+
+    /// Run the monitors locally and push their results to a central stylus server
+    Agent(AgentArgs),
+
+    /// Displays the status of the monitors of a running stylus server
+    Status(StatusArgs),
+
+    /// Asks a running stylus server to run a monitor now instead of waiting for its interval
+    Trigger(TriggerArgs),
+
+    /// Runs every monitor once and writes a static snapshot of the status page
+    Export(ExportArgs),
 }
 
 #[derive(Debug, Parser)]
@@ -43,11 +60,34 @@ pub struct DumpArgs {
     pub force_container_path: Option<PathBuf>,
 }
 
+#[derive(Debug, Parser)]
+pub struct ValidateArgs {
+    /// The stylus directory containing the configuration file
+    #[arg(name = "DIRECTORY", required_unless_present_any = ["force_container_path"])]
+    pub directory: Option<PathBuf>,
+
+    /// Print the problems as JSON
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub json: bool,
+
+    /// Advanced: if running a container, allows the container to override any path specified on the command line
+    #[arg(env = "FORCE_CONTAINER_PATH", hide = true)]
+    pub force_container_path: Option<PathBuf>,
+}
+
 #[derive(Debug, Parser)]
 pub struct TestArgs {
-    /// The test to run
-    #[arg(short, long, required = true)]
-    pub monitor: String,
+    /// The monitors to run, by ID or glob (eg: `web-*`), which may be passed multiple times
+    #[arg(short, long, required_unless_present = "all")]
+    pub monitor: Vec<String>,
+
+    /// Run every monitor
+    #[arg(long, conflicts_with = "monitor", action = clap::ArgAction::SetTrue)]
+    pub all: bool,
+
+    /// How to print the results
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
 
     /// The stylus directory containing the configuration file
     #[arg(name = "DIRECTORY", required_unless_present_any = ["force_container_path"])]
@@ -95,3 +135,99 @@ pub struct RunArgs {
     #[arg(env = "FORCE_CONTAINER_PATH", hide = true)]
     pub force_container_path: Option<PathBuf>,
 }
+
+#[derive(Debug, Parser)]
+pub struct AgentArgs {
+    /// The stylus directory containing the configuration file
+    #[arg(name = "DIRECTORY", required_unless_present_any = ["force_container_path"])]
+    pub directory: Option<PathBuf>,
+
+    /// The base URL of the central stylus server (eg: `https://status.example.com`)
+    #[arg(long, env = "STYLUS_AGENT_SERVER")]
+    pub server: String,
+
+    /// The name of this agent, which prefixes the IDs of its monitors on the server
+    #[arg(long, env = "STYLUS_AGENT_NAME")]
+    pub name: String,
+
+    /// The token shared with the server
+    #[arg(long, env = "STYLUS_AGENT_TOKEN", hide_env_values = true)]
+    pub token: String,
+
+    /// How often to push results to the server, in seconds
+    #[arg(long, default_value_t = 10)]
+    pub interval: u64,
+
+    /// Advanced: if running a container, allows the container to override any path specified on the command line
+    #[arg(env = "FORCE_CONTAINER_PATH", hide = true)]
+    pub force_container_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Parser)]
+pub struct StatusArgs {
+    /// The URL of the running server (eg: `http://localhost:8000`), or a stylus directory to read its port from
+    #[arg(name = "TARGET", required_unless_present_any = ["force_container_path"])]
+    pub target: Option<String>,
+
+    /// Only show monitors and children whose IDs match, by ID or glob (eg: `web-*`)
+    #[arg(short, long)]
+    pub monitor: Vec<String>,
+
+    /// Only show monitors and children in the given state (eg: `red`)
+    #[arg(short, long)]
+    pub state: Vec<String>,
+
+    /// Refresh the display every few seconds
+    #[arg(short, long, action = clap::ArgAction::SetTrue)]
+    pub watch: bool,
+
+    /// How often to refresh with --watch, in seconds
+    #[arg(long, default_value_t = 2)]
+    pub interval: u64,
+
+    /// Print the log of the given monitor instead
+    #[arg(long, value_name = "MONITOR")]
+    pub log: Option<String>,
+
+    /// Advanced: if running a container, allows the container to override any path specified on the command line
+    #[arg(env = "FORCE_CONTAINER_PATH", hide = true)]
+    pub force_container_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Parser)]
+pub struct TriggerArgs {
+    /// The URL of the running server (eg: `http://localhost:8000`), or a stylus directory to read its port from
+    #[arg(name = "TARGET", required_unless_present_any = ["force_container_path"])]
+    pub target: Option<String>,
+
+    /// The ID of the monitor to run
+    #[arg(short, long, required = true)]
+    pub monitor: String,
+
+    /// Wait for the run to finish and display the result, exiting nonzero if it isn't green
+    #[arg(short, long, action = clap::ArgAction::SetTrue)]
+    pub wait: bool,
+
+    /// The token configured in the server's `server.trigger` (read from the stylus directory if not given)
+    #[arg(long, env = "STYLUS_TRIGGER_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+
+    /// Advanced: if running a container, allows the container to override any path specified on the command line
+    #[arg(env = "FORCE_CONTAINER_PATH", hide = true)]
+    pub force_container_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Parser)]
+pub struct ExportArgs {
+    /// The stylus directory containing the configuration file
+    #[arg(name = "DIRECTORY", required_unless_present_any = ["force_container_path"])]
+    pub directory: Option<PathBuf>,
+
+    /// The directory to write the snapshot to
+    #[arg(short, long)]
+    pub output: PathBuf,
+
+    /// Advanced: if running a container, allows the container to override any path specified on the command line
+    #[arg(env = "FORCE_CONTAINER_PATH", hide = true)]
+    pub force_container_path: Option<PathBuf>,
+}
@@ -0,0 +1,250 @@
+use std::error::Error;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+
+use super::structs::*;
+
+/// The most values a single axis may generate.
+const MAX_VALUES: usize = 65536;
+
+impl MonitorDirAxisValue {
+    /// Parses a value as a number if possible, otherwise keeping it as a string.
+    pub fn parse(s: &str) -> Self {
+        match s.parse() {
+            Ok(n) => MonitorDirAxisValue::Number(n),
+            Err(_) => MonitorDirAxisValue::String(s.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for MonitorDirAxisValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MonitorDirAxisValue::String(s) => f.write_str(s),
+            MonitorDirAxisValue::Number(n) => write!(f, "{n}"),
+        }
+    }
+}
+
+impl MonitorDirAxisConfig {
+    /// Replaces the values of this axis with those of its generator, if it has one.
+    pub fn generate(&mut self, base_path: &Path) -> Result<(), Box<dyn Error>> {
+        let generators = [
+            self.range.is_some(),
+            self.file.is_some(),
+            self.cidr.is_some(),
+            self.glob.is_some(),
+        ]
+        .into_iter()
+        .filter(|b| *b)
+        .count();
+        if generators == 0 {
+            return Ok(());
+        }
+        if generators > 1 || !self.values.is_empty() {
+            return Err(format!(
+                "Axis {} must use only one of values, range, file, cidr or glob",
+                self.name
+            )
+            .into());
+        }
+
+        let values = if let Some(range) = &self.range {
+            range_values(range)
+        } else if let Some(file) = &self.file {
+            std::fs::read_to_string(base_path.join(file))
+                .map_err(|e| format!("Failed to read {}: {e}", file.display()).into())
+                .map(|s| file_values(&s))
+        } else if let Some(cidr) = &self.cidr {
+            cidr_values(cidr)
+        } else if let Some(glob) = &self.glob {
+            glob_values(base_path, glob)
+        } else {
+            unreachable!()
+        };
+        self.values = values.map_err(|e| format!("Axis {}: {e}", self.name))?;
+        if self.values.is_empty() {
+            log::warn!("Axis {} has no values", self.name);
+        }
+        Ok(())
+    }
+}
+
+fn range_values(
+    range: &MonitorDirAxisRangeConfig,
+) -> Result<Vec<MonitorDirAxisValue>, Box<dyn Error>> {
+    if range.step == 0 {
+        return Err("range step must not be zero".into());
+    }
+    let mut values = vec![];
+    let mut value = range.start;
+    while (range.step > 0 && value <= range.end) || (range.step < 0 && value >= range.end) {
+        if values.len() >= MAX_VALUES {
+            return Err(format!("range has more than {MAX_VALUES} values").into());
+        }
+        values.push(match range.pad {
+            Some(pad) => MonitorDirAxisValue::String(format!("{value:0pad$}")),
+            None => MonitorDirAxisValue::Number(value),
+        });
+        let Some(next) = value.checked_add(range.step) else {
+            break;
+        };
+        value = next;
+    }
+    Ok(values)
+}
+
+fn file_values(s: &str) -> Vec<MonitorDirAxisValue> {
+    s.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(MonitorDirAxisValue::parse)
+        .collect()
+}
+
+fn cidr_values(cidr: &str) -> Result<Vec<MonitorDirAxisValue>, Box<dyn Error>> {
+    let invalid = || format!("Invalid CIDR block {cidr:?}");
+    let (addr, prefix) = cidr.split_once('/').ok_or_else(invalid)?;
+    let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+    let prefix: u32 = prefix.parse().map_err(|_| invalid())?;
+    let bits = if addr.is_ipv4() { 32 } else { 128 };
+    if prefix > bits {
+        return Err(invalid().into());
+    }
+    let host_bits = bits - prefix;
+    if 1_u128 << host_bits.min(127) > MAX_VALUES as u128 {
+        return Err(format!("CIDR block {cidr:?} has more than {MAX_VALUES} addresses").into());
+    }
+
+    let size = 1_u128 << host_bits;
+    let network = match addr {
+        IpAddr::V4(addr) => u32::from(addr) as u128,
+        IpAddr::V6(addr) => u128::from(addr),
+    } & !(size - 1);
+    // The network address (and the broadcast address for IPv4) is not a host, except in the tiniest blocks
+    let (first, last) = match (addr, host_bits) {
+        (_, 0 | 1) => (network, network + size - 1),
+        (IpAddr::V4(_), _) => (network + 1, network + size - 2),
+        (IpAddr::V6(_), _) => (network + 1, network + size - 1),
+    };
+    Ok((first..=last)
+        .map(|host| {
+            MonitorDirAxisValue::String(match addr {
+                IpAddr::V4(_) => Ipv4Addr::from(host as u32).to_string(),
+                IpAddr::V6(_) => Ipv6Addr::from(host).to_string(),
+            })
+        })
+        .collect())
+}
+
+fn glob_values(base_path: &Path, glob: &str) -> Result<Vec<MonitorDirAxisValue>, Box<dyn Error>> {
+    let pattern = base_path.join(glob);
+    let paths = glob::glob(&pattern.to_string_lossy())
+        .map_err(|e| format!("Invalid glob {glob:?}: {e}"))?;
+    let mut values = vec![];
+    for path in paths.flatten() {
+        if values.len() >= MAX_VALUES {
+            return Err(format!("glob matches more than {MAX_VALUES} paths").into());
+        }
+        let path = path.strip_prefix(base_path).unwrap_or(&path);
+        values.push(MonitorDirAxisValue::String(
+            path.to_string_lossy().into_owned(),
+        ));
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use MonitorDirAxisValue::*;
+
+    fn range(start: i64, end: i64, step: i64, pad: Option<usize>) -> MonitorDirAxisRangeConfig {
+        MonitorDirAxisRangeConfig {
+            start,
+            end,
+            step,
+            pad,
+        }
+    }
+
+    fn strings(values: &[&str]) -> Vec<MonitorDirAxisValue> {
+        values.iter().map(|s| String(s.to_string())).collect()
+    }
+
+    #[test]
+    fn test_range() {
+        assert_eq!(
+            range_values(&range(1, 4, 1, None)).unwrap(),
+            vec![Number(1), Number(2), Number(3), Number(4)]
+        );
+        assert_eq!(
+            range_values(&range(0, 20, 8, Some(2))).unwrap(),
+            strings(&["00", "08", "16"])
+        );
+        assert_eq!(
+            range_values(&range(3, 1, -1, None)).unwrap(),
+            vec![Number(3), Number(2), Number(1)]
+        );
+        assert!(range_values(&range(1, 4, 0, None)).is_err());
+        assert!(range_values(&range(0, i64::MAX, 1, None)).is_err());
+    }
+
+    #[test]
+    fn test_file() {
+        assert_eq!(
+            file_values("# Hosts\nweb-1\n\n  db-1  \n42\n"),
+            vec![String("web-1".into()), String("db-1".into()), Number(42)]
+        );
+    }
+
+    #[test]
+    fn test_cidr() {
+        assert_eq!(
+            cidr_values("192.168.1.5/30").unwrap(),
+            strings(&["192.168.1.5", "192.168.1.6"])
+        );
+        assert_eq!(
+            cidr_values("10.0.0.0/31").unwrap(),
+            strings(&["10.0.0.0", "10.0.0.1"])
+        );
+        assert_eq!(cidr_values("10.0.0.7/32").unwrap(), strings(&["10.0.0.7"]));
+        assert_eq!(cidr_values("10.0.0.0/24").unwrap().len(), 254);
+        assert_eq!(
+            cidr_values("fd00::/126").unwrap(),
+            strings(&["fd00::1", "fd00::2", "fd00::3"])
+        );
+        assert!(cidr_values("10.0.0.0/8").is_err());
+        assert!(cidr_values("fd00::/0").is_err());
+        assert!(cidr_values("10.0.0.0/33").is_err());
+        assert!(cidr_values("10.0.0.0").is_err());
+    }
+
+    #[test]
+    fn test_glob() {
+        let base_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/testcases");
+        let values = glob_values(&base_path, "group_*/config.yaml").unwrap();
+        assert!(values.contains(&String("group_complete/config.yaml".into())));
+        assert!(values
+            .iter()
+            .all(|v| matches!(v, String(s) if s.starts_with("group_"))));
+    }
+
+    #[test]
+    fn test_generate() {
+        let axis = |yaml: &str| {
+            let mut axis: MonitorDirAxisConfig = serde_yaml_ng::from_str(yaml).unwrap();
+            axis.generate(Path::new("/")).map(|_| axis.values)
+        };
+        assert_eq!(
+            axis("{name: port, range: {start: 1, end: 3}}").unwrap(),
+            vec![Number(1), Number(2), Number(3)]
+        );
+        assert_eq!(
+            axis("{name: port, values: [1, 2]}").unwrap(),
+            vec![Number(1), Number(2)]
+        );
+        assert!(axis("{name: port, values: [1], range: {start: 1, end: 3}}").is_err());
+        assert!(axis("{name: host, cidr: 10.0.0.0/30, glob: '*'}").is_err());
+    }
+}
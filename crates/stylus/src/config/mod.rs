@@ -1,6 +1,8 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::error::Error;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
 use clap::Parser;
 use itertools::Itertools;
@@ -8,10 +10,19 @@ use walkdir::WalkDir;
 
 use self::args::{Args, Commands};
 pub use self::structs::*;
+pub use self::validate::*;
 use crate::interpolate::*;
+use crate::monitors::composite::evaluation_order;
+use crate::monitors::nagios::NagiosMessageProcessor;
 
 mod args;
+mod axes;
 mod structs;
+mod validate;
+
+/// The exit code of every command whose configuration can't be loaded, which `stylus test` keeps apart from monitors
+/// that aren't green (`1`).
+pub const CONFIG_ERROR_EXIT: i32 = 2;
 
 pub fn parse_config_from_args() -> Result<OperationMode, Box<dyn Error>> {
     let args = Args::parse();
@@ -29,6 +40,17 @@ pub fn parse_config_from_args() -> Result<OperationMode, Box<dyn Error>> {
             let config = parse_config(&path)?;
             Ok(OperationMode::Dump(config))
         }
+        Commands::Validate(validate_args) => {
+            let path = if let Some(path) = validate_args.directory {
+                path
+            } else {
+                validate_args
+                    .force_container_path
+                    .expect("No forced container path specified")
+            };
+            // Problems are reported by the command itself rather than failing here
+            Ok(OperationMode::Validate(path, validate_args.json))
+        }
         Commands::Test(test_args) => {
             let path = if let Some(path) = test_args.directory {
                 path
@@ -38,7 +60,12 @@ pub fn parse_config_from_args() -> Result<OperationMode, Box<dyn Error>> {
                     .expect("No forced container path specified")
             };
             let config = parse_config(&path)?;
-            Ok(OperationMode::Test(config, test_args.monitor))
+            let monitors = if test_args.all {
+                vec!["*".to_string()]
+            } else {
+                test_args.monitor
+            };
+            Ok(OperationMode::Test(config, monitors, test_args.format))
         }
         Commands::Init(init_args) => {
             let docker = init_args.directory.is_none();
@@ -62,6 +89,75 @@ pub fn parse_config_from_args() -> Result<OperationMode, Box<dyn Error>> {
             }
             Ok(OperationMode::Init(path, docker))
         }
+        Commands::Agent(agent_args) => {
+            let path = if let Some(path) = agent_args.directory {
+                path
+            } else {
+                agent_args
+                    .force_container_path
+                    .expect("No forced container path specified")
+            };
+            let config = parse_config(&path)?;
+            validate_agent_name(&agent_args.name)?;
+            let agent = AgentConfig {
+                server: agent_args.server,
+                name: agent_args.name,
+                token: agent_args.token,
+                interval: Duration::from_secs(agent_args.interval.max(1)),
+            };
+            Ok(OperationMode::Agent(config, agent))
+        }
+        Commands::Export(export_args) => {
+            let path = if let Some(path) = export_args.directory {
+                path
+            } else {
+                export_args
+                    .force_container_path
+                    .expect("No forced container path specified")
+            };
+            let config = parse_config(&path)?;
+            Ok(OperationMode::Export(config, export_args.output))
+        }
+        Commands::Status(status_args) => {
+            let (url, _) = target_server(status_args.target, status_args.force_container_path)?;
+            let monitors = status_args
+                .monitor
+                .iter()
+                .map(|pattern| {
+                    glob::Pattern::new(pattern)
+                        .map_err(|e| format!("Invalid monitor pattern {pattern:?}: {e}"))
+                })
+                .collect::<Result<_, _>>()?;
+            let states = status_args
+                .state
+                .iter()
+                .map(|state| {
+                    serde_json::from_value(serde_json::Value::String(state.to_lowercase()))
+                        .map_err(|_| format!("Invalid state {state:?}"))
+                })
+                .collect::<Result<_, _>>()?;
+            Ok(OperationMode::Status(StatusClientConfig {
+                url,
+                monitors,
+                states,
+                watch: status_args
+                    .watch
+                    .then(|| Duration::from_secs(status_args.interval.max(1))),
+                log: status_args.log,
+            }))
+        }
+        Commands::Trigger(trigger_args) => {
+            let (url, server) =
+                target_server(trigger_args.target, trigger_args.force_container_path)?;
+            // A stylus directory also provides the token, unless one is given
+            let token = trigger_args.token.or_else(|| Some(server?.trigger?.token));
+            Ok(OperationMode::Trigger(TriggerClientConfig {
+                url,
+                monitor: trigger_args.monitor,
+                wait: trigger_args.wait,
+                token,
+            }))
+        }
         Commands::Run(run_args) => {
             let config_path = if let Some(path) = run_args.config {
                 path
@@ -91,7 +187,34 @@ pub fn parse_config_from_args() -> Result<OperationMode, Box<dyn Error>> {
     }
 }
 
+/// Resolves the server a client command talks to, given either its URL or the stylus directory it runs from.
+/// Finds the URL of a server given either its URL or its stylus directory, along with its configuration for the latter.
+fn target_server(
+    target: Option<String>,
+    force_container_path: Option<PathBuf>,
+) -> Result<(String, Option<ServerConfig>), Box<dyn Error>> {
+    match target {
+        Some(target) if target.starts_with("http://") || target.starts_with("https://") => {
+            Ok((target.trim_end_matches('/').to_string(), None))
+        }
+        target => {
+            let path = target.map(PathBuf::from).unwrap_or_else(|| {
+                force_container_path.expect("No forced container path specified")
+            });
+            let server = parse_config(&path)?.server;
+            Ok((server_url(&server), Some(server)))
+        }
+    }
+}
+
 pub fn parse_config(file: &Path) -> Result<Config, Box<dyn Error>> {
+    let path = config_file_path(file)?;
+    let s = std::fs::read_to_string(&path)?;
+    parse_config_string(&path, s)
+}
+
+/// Locates the config.yaml given either the stylus directory or the config.yaml itself.
+fn config_file_path(file: &Path) -> Result<PathBuf, Box<dyn Error>> {
     let curr = std::env::current_dir()?;
     let mut path = Path::new(&file).into();
     canonicalize("configuration", Some(&curr), &mut path)?;
@@ -110,8 +233,7 @@ pub fn parse_config(file: &Path) -> Result<Config, Box<dyn Error>> {
     if !path.exists() {
         return Err(format!("Configuration file {} does not exist.", path.display()).into());
     }
-    let s = std::fs::read_to_string(&path)?;
-    parse_config_string(&path, s)
+    Ok(path)
 }
 
 /// Given a base path and a relative path, gets the full path (or errors out if it doesn't exist).
@@ -181,9 +303,25 @@ pub fn parse_config_string(file: &Path, s: String) -> Result<Config, Box<dyn Err
         css.declarations = css.declarations.trim().to_string();
         css.selectors = css.selectors.trim().to_string();
     }
+    config.css.templates = CssTemplates::compile(&config.css.rules)?;
+
+    if let Some(ingest) = &config.server.ingest {
+        if ingest.token.is_empty() {
+            return Err("server.ingest requires a token".into());
+        }
+    }
+    if let Some(trigger) = &config.server.trigger {
+        if trigger.token.is_empty() {
+            return Err("server.trigger requires a token".into());
+        }
+    }
 
     // Canonical paths
     canonicalize("base path", None, &mut config.base_path)?;
+    if let Some(export) = &mut config.server.export {
+        // The export directory is created when the first snapshot is written
+        export.path = config.base_path.join(&export.path);
+    }
     if let Some(static_path) = &mut config.server.static_path {
         canonicalize("static file path", Some(&config.base_path), static_path)?;
     } else {
@@ -208,34 +346,21 @@ pub fn parse_config_string(file: &Path, s: String) -> Result<Config, Box<dyn Err
 }
 
 pub fn parse_monitor_configs(root: &Path) -> Result<Vec<MonitorDirConfig>, Box<dyn Error>> {
-    if !root.exists() {
-        return Err(format!(
-            "Monitor directory {} does not exist",
-            root.to_string_lossy()
-        )
-        .into());
+    let mut monitor_configs = vec![];
+    for p in monitor_config_files(root)? {
+        monitor_configs
+            .push(parse_monitor_config(&p).map_err(|e| format!("{}: {e}", p.display()))?);
+        info!("Found monitor in {:?}", p);
     }
 
-    let mut monitor_configs = vec![];
-    for e in WalkDir::new(root)
-        .min_depth(1)
-        .max_depth(1)
-        .follow_links(true)
-        .into_iter()
-    {
-        debug!("Got entry: {e:?}");
-        let e = e?;
-        if e.file_type().is_dir() {
-            let mut p = e.into_path();
-            p.push("config.yaml");
-            if p.exists() {
-                monitor_configs.push(parse_monitor_config(&p)?);
-                info!("Found monitor in {:?}", p);
-            } else {
-                debug!("Ignoring {:?} as there was no config.yaml", p);
-            }
-        } else {
-            debug!("Ignoring {:?} as it was not a directory", e.path());
+    // Catch cycles between composite monitors before anything runs
+    evaluation_order(&monitor_configs)?;
+
+    // Remote monitors skip the monitors they import with the same ID as a local one
+    let local = local_ids(&monitor_configs);
+    for config in &mut monitor_configs {
+        if let MonitorDirRootConfig::Remote(remote) = &mut config.root {
+            remote.reserved = local.clone();
         }
     }
 
@@ -250,6 +375,18 @@ pub fn parse_monitor_configs(root: &Path) -> Result<Vec<MonitorDirConfig>, Box<d
     }
 }
 
+/// The IDs of the monitors and group children that are configured locally.
+pub fn local_ids(configs: &[MonitorDirConfig]) -> BTreeSet<String> {
+    let mut ids = BTreeSet::new();
+    for config in configs {
+        ids.insert(config.id.clone());
+        if let MonitorDirRootConfig::Group(group) = &config.root {
+            ids.extend(group.children.keys().cloned());
+        }
+    }
+    ids
+}
+
 pub fn parse_monitor_config(file: &Path) -> Result<MonitorDirConfig, Box<dyn Error>> {
     let s = std::fs::read_to_string(file)?;
     parse_monitor_config_string(file, s)
@@ -277,45 +414,174 @@ pub fn parse_monitor_config_string(
             .to_string();
     }
 
+    // Catch typos in expressions before the monitor runs
+    match &config.root {
+        MonitorDirRootConfig::Snmp(snmp) => snmp.validate()?,
+        MonitorDirRootConfig::Ping(ping) => ping.validate()?,
+        MonitorDirRootConfig::File(file) => file.validate()?,
+        MonitorDirRootConfig::Composite(composite) => composite.validate()?,
+        MonitorDirRootConfig::Remote(remote) => remote.validate()?,
+        MonitorDirRootConfig::Test(_) | MonitorDirRootConfig::Group(_) => {}
+    }
+
+    for css in &mut config.css {
+        css.declarations = css.declarations.trim().to_string();
+        css.selectors = css.selectors.trim().to_string();
+    }
+    config.css_templates = CssTemplates::compile(&config.css)?;
+
+    // File monitors resolve their paths relative to the monitor directory
+    if let MonitorDirRootConfig::File(ref mut file) = config.root {
+        file.base_path = config.base_path.clone();
+    }
+
     let test = config.root.test_mut();
-    let executable = config.base_path.join(&test.command);
-    if executable.exists() {
-        test.command = Path::canonicalize(&executable)?;
-    } else {
-        let command_line = test.command.to_string_lossy().to_string();
-        if !command_line.contains(' ') {
-            return Err(format!("Command {} is not available", command_line).into());
+    resolve_command(&config.base_path, &mut test.command, &mut test.args)?;
+    if let Some(parse) = &test.parse {
+        if test.nagios {
+            return Err("The parse and nagios options cannot be combined".into());
         }
-        test.args = vec!["-c".to_string(), command_line];
-        test.command = PathBuf::from("/bin/sh");
+        test.processor = Some(parse.processor()?);
+    } else if test.nagios {
+        test.processor = Some(Arc::new(NagiosMessageProcessor {}));
     }
 
     let mut children = BTreeMap::new();
     if let MonitorDirRootConfig::Group(ref mut group) = config.root {
-        for values in group
-            .axes
-            .iter()
-            .map(|axis| axis.values.iter().map(move |v| (v, &axis.name)))
-            .multi_cartesian_product()
-        {
-            let mut axes = BTreeMap::new();
-            for val in values {
-                axes.insert(val.1.to_owned(), val.0.to_owned());
+        for axis in &mut group.axes {
+            axis.generate(&config.base_path)?;
+        }
+        if let Some(axes_from) = &mut group.axes_from {
+            // Children are discovered on each run instead
+            axes_from.validate()?;
+            if let Some(command) = &mut axes_from.command {
+                resolve_command(&config.base_path, command, &mut axes_from.args)?;
+            }
+        } else {
+            for axes in expand_axes(&group.axes) {
+                let id = interpolate_id(&axes, &group.id)?;
+                let child = MonitorDirChildConfig {
+                    axes,
+                    test: group.test.clone(),
+                };
+                children.insert(id, child);
             }
-
-            let id = interpolate_id(&axes, &group.id)?;
-            let child = MonitorDirChildConfig {
-                axes,
-                test: group.test.clone(),
-            };
-            children.insert(id, child);
         }
         group.children = children;
+
+        if let Some(rollup) = &group.rollup {
+            rollup.validate()?;
+        }
+        if let Some(per_child) = &group.per_child {
+            if per_child.concurrency == 0 {
+                return Err("per_child concurrency must be at least 1".into());
+            }
+            // Check that the arguments can be interpolated for every child known up front
+            for child in group.children.values() {
+                for arg in group.test.args.iter().chain(&per_child.args) {
+                    interpolate_id(&child.axes, arg)?;
+                }
+            }
+        }
     }
 
     Ok(config)
 }
 
+/// Finds the config.yaml of every monitor directory in the root.
+fn monitor_config_files(root: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    if !root.exists() {
+        return Err(format!(
+            "Monitor directory {} does not exist",
+            root.to_string_lossy()
+        )
+        .into());
+    }
+
+    let mut files = vec![];
+    for e in WalkDir::new(root)
+        .min_depth(1)
+        .max_depth(1)
+        .follow_links(true)
+        .into_iter()
+    {
+        debug!("Got entry: {e:?}");
+        let e = e?;
+        if e.file_type().is_dir() {
+            let mut p = e.into_path();
+            p.push("config.yaml");
+            if p.exists() {
+                files.push(p);
+            } else {
+                debug!("Ignoring {:?} as there was no config.yaml", p);
+            }
+        } else {
+            debug!("Ignoring {:?} as it was not a directory", e.path());
+        }
+    }
+    Ok(files)
+}
+
+/// The local URL of a server, connecting to the loopback address when it listens on every address.
+pub fn server_url(server: &ServerConfig) -> String {
+    let host = match server.listen_addr.as_str() {
+        "0.0.0.0" => "127.0.0.1".to_string(),
+        "::" => "[::1]".to_string(),
+        addr if addr.contains(':') => format!("[{addr}]"),
+        addr => addr.to_string(),
+    };
+    format!("http://{host}:{}", server.port)
+}
+
+/// Agent names prefix the IDs of their monitors, so they are limited to characters that are safe in IDs.
+pub fn validate_agent_name(name: &str) -> Result<(), Box<dyn Error>> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(
+            format!("Invalid agent name {name:?}: use only letters, numbers, '-' and '_'").into(),
+        );
+    }
+    Ok(())
+}
+
+/// Resolves a command relative to the monitor directory, falling back to running it with the shell if it
+/// contains spaces.
+fn resolve_command(
+    base_path: &Path,
+    command: &mut PathBuf,
+    args: &mut Vec<String>,
+) -> Result<(), Box<dyn Error>> {
+    let executable = base_path.join(&command);
+    if executable.exists() {
+        *command = Path::canonicalize(&executable)?;
+    } else {
+        let command_line = command.to_string_lossy().to_string();
+        if !command_line.contains(' ') {
+            return Err(format!("Command {} is not available", command_line).into());
+        }
+        *args = vec!["-c".to_string(), command_line];
+        *command = PathBuf::from("/bin/sh");
+    }
+    Ok(())
+}
+
+/// Expands a set of axes into the cartesian product of their values.
+pub fn expand_axes(axes: &[MonitorDirAxisConfig]) -> Vec<BTreeMap<String, MonitorDirAxisValue>> {
+    axes.iter()
+        .map(|axis| axis.values.iter().map(move |v| (v, &axis.name)))
+        .multi_cartesian_product()
+        .map(|values| {
+            values
+                .into_iter()
+                .map(|(value, name)| (name.to_owned(), value.to_owned()))
+                .collect()
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -382,4 +648,181 @@ group:
 
         Ok(())
     }
+
+    #[test]
+    fn deserialize_monitor_group_generated_axes() -> Result<(), Box<dyn Error>> {
+        let config = parse_monitor_config_string(
+            Path::new("/tmp/test.yaml"),
+            r#"
+id: switch
+group:
+    id: port-{{ index }}-{{ host }}
+    axes:
+        - name: index
+          range: { start: 1, end: 48, step: 16, pad: 2 }
+        - name: host
+          cidr: 10.0.0.0/30
+    test:
+        interval: 60s
+        timeout: 30s
+        command: /bin/sleep
+          "#
+            .into(),
+        )?;
+
+        let MonitorDirRootConfig::Group(group) = config.root else {
+            panic!("Expected a group");
+        };
+        assert_eq!(
+            group.children.keys().collect::<Vec<_>>(),
+            vec![
+                "port-01-10.0.0.1",
+                "port-01-10.0.0.2",
+                "port-17-10.0.0.1",
+                "port-17-10.0.0.2",
+                "port-33-10.0.0.1",
+                "port-33-10.0.0.2",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rollup_policies() {
+        use crate::status::{MonitorChildCounts, StatusState::*};
+
+        let rollup = |rollup: &str| {
+            let config = parse_monitor_config_string(
+                Path::new("/tmp/test.yaml"),
+                format!(
+                    r#"
+id: switch
+group:
+    id: port-{{{{ index }}}}
+    axes:
+        - name: index
+          values: [1, 2, 3, 4]
+    rollup: {rollup}
+    test:
+        interval: 60s
+        timeout: 30s
+        command: /bin/sleep
+                    "#
+                ),
+            )?;
+            match config.root {
+                MonitorDirRootConfig::Group(group) => {
+                    Ok::<_, Box<dyn Error>>(group.rollup.unwrap())
+                }
+                _ => panic!("Expected a group"),
+            }
+        };
+        let counts = MonitorChildCounts {
+            children: 4,
+            children_green: 3,
+            children_red: 1,
+            ..Default::default()
+        };
+        let evaluate = |rollup_yaml: &str| rollup(rollup_yaml).unwrap().evaluate(&counts);
+
+        assert_eq!(evaluate("worst"), Some(Red));
+        assert_eq!(evaluate("best"), Some(Green));
+        assert_eq!(evaluate("{ quorum: 3 }"), Some(Green));
+        assert_eq!(evaluate("{ quorum: 4 }"), Some(Red));
+        assert_eq!(evaluate("{ percent: 75 }"), Some(Green));
+        assert_eq!(evaluate("{ percent: 80 }"), Some(Red));
+        assert_eq!(
+            evaluate("{ rules: { orange: children_red > 0 } }"),
+            Some(Orange)
+        );
+        assert_eq!(evaluate("{ rules: { red: children_red > 1 } }"), None);
+        assert_eq!(
+            rollup("worst")
+                .unwrap()
+                .evaluate(&MonitorChildCounts::default()),
+            None
+        );
+
+        assert_eq!(evaluate("{ rules: { red: red_count > 0 } }"), Some(Red));
+        assert_eq!(
+            evaluate("{ rules: { orange: green_count == 3 and blank_count == 0 } }"),
+            Some(Orange)
+        );
+
+        assert!(rollup("{ percent: 101 }").is_err());
+        let error = rollup("{ rules: { red: purple_count > 2 } }")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("unknown identifier 'purple_count'"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn composite_evaluation_order() {
+        use crate::monitors::composite::evaluation_order;
+
+        let config = |id: &str, yaml: &str| {
+            let mut config =
+                parse_monitor_config_string(Path::new("/tmp/test.yaml"), yaml.to_string()).unwrap();
+            config.id = id.to_string();
+            config
+        };
+        let composite = |id: &str, red: &str| config(id, &format!("composite:\n    red: {red}\n"));
+        let test = config(
+            "wan1",
+            "test:\n    interval: 60s\n    timeout: 30s\n    command: /bin/sleep\n",
+        );
+
+        // Composites come after the composites they depend on
+        let configs = vec![
+            composite("site", "status('network') == 'red'"),
+            test,
+            composite("network", "status('wan1') == 'red'"),
+        ];
+        assert_eq!(evaluation_order(&configs).unwrap(), vec![2, 0]);
+
+        let configs = vec![
+            composite("a", "status('b') == 'red'"),
+            composite("b", "status('a') == 'red'"),
+        ];
+        let error = evaluation_order(&configs).unwrap_err().to_string();
+        assert_eq!(error, "Composite monitors form a cycle: a -> b -> a");
+
+        // Composites can only read monitor statuses
+        assert!(parse_monitor_config_string(
+            Path::new("/tmp/test.yaml"),
+            "composite:\n    red: children_red > 0\n".to_string()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn deserialize_monitor_invalid_expression() {
+        let config = |red: &str| {
+            parse_monitor_config_string(
+                Path::new("/tmp/test.yaml"),
+                format!(
+                    r#"
+id: router
+ping:
+    host: 127.0.0.1
+    interval: 60s
+    timeout: 30s
+    red: "{red}"
+          "#
+                ),
+            )
+        };
+
+        assert!(config("lost == count").is_ok());
+        let error = config("lost == cont").unwrap_err().to_string();
+        assert!(
+            error.contains("Invalid red expression: unknown identifier 'cont' at column 9"),
+            "{error}"
+        );
+        let error = config("lost ==").unwrap_err().to_string();
+        assert!(error.contains("at column 8"), "{error}");
+    }
 }
@@ -5,15 +5,75 @@ use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 
+use crate::expressions::{validate_identifiers, Expression, ExpressionContext};
+use crate::interpolate::CssTemplates;
 use crate::monitor::MonitorMessageProcessor;
+use crate::monitors::composite::CompositeMonitorConfig;
+use crate::monitors::discovery::MonitorDirAxesFromConfig;
+use crate::monitors::file::FileMonitorConfig;
+use crate::monitors::parse::MonitorDirParseConfig;
 use crate::monitors::ping::PingMonitorConfig;
+use crate::monitors::remote::RemoteMonitorConfig;
 use crate::monitors::snmp::SnmpNetworkMonitorConfig;
+use crate::status::{MonitorChildCounts, StatusState};
 
 pub enum OperationMode {
     Run(Config, bool),
     Dump(Config),
+    Validate(PathBuf, bool),
     Init(PathBuf, bool),
-    Test(Config, String),
+    Test(Config, Vec<String>, OutputFormat),
+    Agent(Config, AgentConfig),
+    Status(StatusClientConfig),
+    Trigger(TriggerClientConfig),
+    Export(Config, PathBuf),
+}
+
+/// What `stylus status` fetches from a running server and how it is displayed.
+#[derive(Clone, Debug)]
+pub struct StatusClientConfig {
+    /// The base URL of the server.
+    pub url: String,
+    /// Only monitors and children whose IDs match one of these globs are shown (all if empty).
+    pub monitors: Vec<glob::Pattern>,
+    /// Only monitors and children in one of these states are shown (all if empty).
+    pub states: Vec<StatusState>,
+    /// Refresh on this interval rather than printing once.
+    pub watch: Option<Duration>,
+    /// Print the log of this monitor instead of the table.
+    pub log: Option<String>,
+}
+
+/// The monitor that `stylus trigger` asks a running server to run.
+#[derive(Clone, Debug)]
+pub struct TriggerClientConfig {
+    /// The base URL of the server.
+    pub url: String,
+    pub monitor: String,
+    /// Wait for the run to finish and print its result.
+    pub wait: bool,
+    /// The token configured in the server's `server.trigger`.
+    pub token: Option<String>,
+}
+
+/// How `stylus test` prints its results.
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// Where and how often `stylus agent` pushes its results.
+#[derive(Clone, Debug)]
+pub struct AgentConfig {
+    pub server: String,
+    pub name: String,
+    pub token: String,
+    pub interval: Duration,
+}
+
+fn default_step() -> i64 {
+    1
 }
 
 fn default_server_port() -> u16 {
@@ -32,6 +92,18 @@ fn default_monitor_dir() -> PathBuf {
     "monitor.d".into()
 }
 
+fn default_stale_after() -> Duration {
+    Duration::from_secs(300)
+}
+
+fn default_export_interval() -> Duration {
+    Duration::from_secs(300)
+}
+
+fn default_concurrency() -> usize {
+    4
+}
+
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
@@ -62,6 +134,12 @@ pub struct ServerConfig {
     pub listen_addr: String,
     #[serde(default, rename = "static")]
     pub static_path: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ingest: Option<IngestConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trigger: Option<TriggerConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub export: Option<ExportConfig>,
 }
 
 impl Default for ServerConfig {
@@ -70,10 +148,44 @@ impl Default for ServerConfig {
             port: default_server_port(),
             listen_addr: default_listen_addr(),
             static_path: Some(default_server_static()),
+            ingest: None,
+            trigger: None,
+            export: None,
         }
     }
 }
 
+/// Accepts the results pushed by `stylus agent`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IngestConfig {
+    /// The token shared with the agents. It is never serialized, so it can't leak through `/config.json`.
+    #[serde(skip_serializing)]
+    pub token: String,
+    /// How long an agent may go without pushing before its monitors are shown as stale.
+    #[serde(with = "humantime_serde", default = "default_stale_after")]
+    pub stale_after: Duration,
+}
+
+/// Lets clients that know the token run monitors on demand through `POST /api/monitors/:id/run`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TriggerConfig {
+    /// The token clients must send. It is never serialized, so it can't leak through `/config.json`.
+    #[serde(skip_serializing)]
+    pub token: String,
+}
+
+/// Periodically writes a static snapshot of the status page while running.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExportConfig {
+    /// The directory to write the snapshot to, relative to the stylus directory.
+    pub path: PathBuf,
+    #[serde(with = "humantime_serde", default = "default_export_interval")]
+    pub interval: Duration,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct MonitorConfig {
@@ -94,6 +206,8 @@ impl Default for MonitorConfig {
 pub struct CssConfig {
     pub metadata: CssMetadataConfig,
     pub rules: Vec<CssRule>,
+    #[serde(skip)]
+    pub templates: CssTemplates,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -101,6 +215,30 @@ pub struct CssConfig {
 pub struct CssRule {
     pub selectors: String,
     pub declarations: String,
+    /// Limits the rule to the monitors and group children it matches.
+    #[serde(default, rename = "match", skip_serializing_if = "Option::is_none")]
+    pub matches: Option<CssRuleMatch>,
+}
+
+/// Conditions that must all hold for a CSS rule to apply to a monitor or group child.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CssRuleMatch {
+    /// A glob matched against the monitor or child ID.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// The monitor type (`test`, `group`, `snmp`, `ping`, `file`, `composite`, `remote` or `agent`).
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    /// Tags that the monitor must have.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Whether to match only group children (`true`) or only monitors (`false`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub child: Option<bool>,
+    /// Axis values that a group child must have.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub axes: BTreeMap<String, MonitorDirAxisValue>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -142,6 +280,13 @@ pub struct MonitorDirConfig {
     pub base_path: PathBuf,
     #[serde(default, skip_serializing_if = "default")]
     pub id: String,
+    #[serde(default, skip_serializing_if = "default")]
+    pub tags: Vec<String>,
+    /// CSS rules that only apply to this monitor and its children.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub css: Vec<CssRule>,
+    #[serde(skip)]
+    pub css_templates: CssTemplates,
 }
 
 impl Default for MonitorDirConfig {
@@ -150,6 +295,9 @@ impl Default for MonitorDirConfig {
             root: MonitorDirRootConfig::Test(MonitorDirTestConfig::default()),
             base_path: Default::default(),
             id: Default::default(),
+            tags: Default::default(),
+            css: Default::default(),
+            css_templates: Default::default(),
         }
     }
 }
@@ -162,9 +310,37 @@ pub enum MonitorDirRootConfig {
     Group(MonitorDirGroupConfig),
     Snmp(SnmpNetworkMonitorConfig),
     Ping(PingMonitorConfig),
+    File(FileMonitorConfig),
+    Composite(CompositeMonitorConfig),
+    Remote(RemoteMonitorConfig),
 }
 
+/// The monitor types that CSS rules can match on.
+pub const MONITOR_KINDS: [&str; 8] = [
+    "test",
+    "group",
+    "snmp",
+    "ping",
+    "file",
+    "composite",
+    "remote",
+    "agent",
+];
+
 impl MonitorDirRootConfig {
+    /// The type of this monitor, as named in its configuration.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            MonitorDirRootConfig::Test(_) => "test",
+            MonitorDirRootConfig::Group(_) => "group",
+            MonitorDirRootConfig::Snmp(_) => "snmp",
+            MonitorDirRootConfig::Ping(_) => "ping",
+            MonitorDirRootConfig::File(_) => "file",
+            MonitorDirRootConfig::Composite(_) => "composite",
+            MonitorDirRootConfig::Remote(_) => "remote",
+        }
+    }
+
     /// Get the MonitorDirTestConfig for this.
     pub fn test(&self) -> &MonitorDirTestConfig {
         match self {
@@ -176,6 +352,15 @@ impl MonitorDirRootConfig {
             MonitorDirRootConfig::Ping(ref ping) => {
                 ping.test.as_ref().expect("test_mut was not called")
             }
+            MonitorDirRootConfig::File(ref file) => {
+                file.test.as_ref().expect("test_mut was not called")
+            }
+            MonitorDirRootConfig::Composite(ref composite) => {
+                composite.test.as_ref().expect("test_mut was not called")
+            }
+            MonitorDirRootConfig::Remote(ref remote) => {
+                remote.test.as_ref().expect("test_mut was not called")
+            }
         }
     }
 
@@ -196,6 +381,24 @@ impl MonitorDirRootConfig {
                 }
                 ping.test.as_mut().unwrap()
             }
+            MonitorDirRootConfig::File(ref mut file) => {
+                if file.test.is_none() {
+                    file.test = Some(file.test());
+                }
+                file.test.as_mut().unwrap()
+            }
+            MonitorDirRootConfig::Composite(ref mut composite) => {
+                if composite.test.is_none() {
+                    composite.test = Some(composite.test());
+                }
+                composite.test.as_mut().unwrap()
+            }
+            MonitorDirRootConfig::Remote(ref mut remote) => {
+                if remote.test.is_none() {
+                    remote.test = Some(remote.test());
+                }
+                remote.test.as_mut().unwrap()
+            }
         }
     }
 }
@@ -205,11 +408,95 @@ impl MonitorDirRootConfig {
 pub struct MonitorDirGroupConfig {
     pub id: String,
     pub test: MonitorDirTestConfig,
+    #[serde(default)]
     pub axes: Vec<MonitorDirAxisConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub axes_from: Option<MonitorDirAxesFromConfig>,
+    /// Runs the test once for each child rather than once for the whole group.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub per_child: Option<MonitorDirPerChildConfig>,
+    /// Computes the status of the group from the status of its children.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rollup: Option<MonitorDirRollupConfig>,
     #[serde(skip_deserializing)]
     pub children: BTreeMap<String, MonitorDirChildConfig>,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MonitorDirPerChildConfig {
+    /// How many children may run at once.
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    /// Arguments for the test command, interpolated with the child's axis values.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+}
+
+/// How the status of a group is computed from the status of its children after each run.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub enum MonitorDirRollupConfig {
+    /// The most severe status of any child.
+    Worst,
+    /// The least severe status of any child.
+    Best,
+    /// Green if at least this many children are green, otherwise red.
+    Quorum(usize),
+    /// Green if at least this percentage of the children are green, otherwise red.
+    Percent(f64),
+    /// Status rules over the number of children in each state.
+    Rules(MonitorDirRulesConfig),
+}
+
+impl MonitorDirRollupConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            MonitorDirRollupConfig::Percent(percent) if !(0.0..=100.0).contains(percent) => {
+                Err(format!("Rollup percentage {percent} must be between 0 and 100").into())
+            }
+            MonitorDirRollupConfig::Rules(rules) => {
+                let context = MonitorChildCounts::default().context();
+                let known = context.keys().map(String::as_str).collect::<Vec<_>>();
+                validate_identifiers(&rules.expressions(), &known)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the status of the group, or `None` to keep the status of the test itself.
+    pub fn evaluate(&self, counts: &MonitorChildCounts) -> Option<StatusState> {
+        if counts.children == 0 {
+            return None;
+        }
+        // In the same order as status rules
+        let severity = [
+            StatusState::Red,
+            StatusState::Orange,
+            StatusState::Yellow,
+            StatusState::Blue,
+            StatusState::Green,
+        ];
+        let quorum = |green: bool| {
+            Some(if green {
+                StatusState::Green
+            } else {
+                StatusState::Red
+            })
+        };
+        match self {
+            MonitorDirRollupConfig::Worst => severity.into_iter().find(|s| counts.get(*s) > 0),
+            MonitorDirRollupConfig::Best => severity.into_iter().rev().find(|s| counts.get(*s) > 0),
+            MonitorDirRollupConfig::Quorum(count) => quorum(counts.children_green >= *count),
+            MonitorDirRollupConfig::Percent(percent) => {
+                quorum(counts.children_green as f64 * 100.0 >= percent * counts.children as f64)
+            }
+            MonitorDirRollupConfig::Rules(rules) => rules.evaluate(&counts.context()),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct MonitorDirChildConfig {
@@ -228,8 +515,34 @@ pub enum MonitorDirAxisValue {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct MonitorDirAxisConfig {
+    #[serde(default)]
     pub values: Vec<MonitorDirAxisValue>,
     pub name: String,
+    /// Generates the values from a numeric range.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range: Option<MonitorDirAxisRangeConfig>,
+    /// Generates the values from the lines of a file, relative to the monitor directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+    /// Generates the values from the host addresses of a CIDR block.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cidr: Option<String>,
+    /// Generates the values from the paths matching a glob, relative to the monitor directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub glob: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MonitorDirAxisRangeConfig {
+    pub start: i64,
+    /// The last value of the range, inclusive.
+    pub end: i64,
+    #[serde(default = "default_step")]
+    pub step: i64,
+    /// Zero-pads the values to this many digits, making them strings.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pad: Option<usize>,
 }
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
@@ -240,8 +553,64 @@ pub struct MonitorDirTestConfig {
     #[serde(with = "humantime_serde")]
     pub timeout: Duration,
     pub command: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parse: Option<MonitorDirParseConfig>,
+    #[serde(default, skip_serializing_if = "default")]
+    pub nagios: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rules: Option<MonitorDirRulesConfig>,
     #[serde(skip)]
     pub args: Vec<String>,
     #[serde(skip)]
     pub processor: Option<Arc<dyn MonitorMessageProcessor>>,
 }
+
+/// Expressions that pick the status of a test once it has finished. The first matching rule wins, checked in the
+/// order red, orange, yellow, blue, green.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MonitorDirRulesConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub red: Option<Expression>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub orange: Option<Expression>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub yellow: Option<Expression>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blue: Option<Expression>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub green: Option<Expression>,
+}
+
+impl MonitorDirRulesConfig {
+    /// The configured rules and their names.
+    pub fn expressions(&self) -> Vec<(&str, &Expression)> {
+        [
+            ("red", &self.red),
+            ("orange", &self.orange),
+            ("yellow", &self.yellow),
+            ("blue", &self.blue),
+            ("green", &self.green),
+        ]
+        .into_iter()
+        .filter_map(|(name, rule)| rule.as_ref().map(|rule| (name, rule)))
+        .collect()
+    }
+
+    /// Returns the status selected by the first matching rule, if any.
+    pub fn evaluate(&self, ctx: &impl ExpressionContext) -> Option<StatusState> {
+        [
+            (StatusState::Red, &self.red),
+            (StatusState::Orange, &self.orange),
+            (StatusState::Yellow, &self.yellow),
+            (StatusState::Blue, &self.blue),
+            (StatusState::Green, &self.green),
+        ]
+        .into_iter()
+        .find_map(|(status, rule)| {
+            rule.as_ref()
+                .filter(|rule| rule.evaluate_bool(ctx))
+                .map(|_| status)
+        })
+    }
+}
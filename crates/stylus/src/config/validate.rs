@@ -0,0 +1,340 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use super::*;
+
+/// A problem found by `stylus validate`, along with the file it was found in.
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct ValidationProblem {
+    pub file: PathBuf,
+    pub message: String,
+}
+
+/// The result of validating a stylus directory.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ValidationReport {
+    /// The number of monitors that were loaded successfully.
+    pub monitors: usize,
+    pub problems: Vec<ValidationProblem>,
+}
+
+impl ValidationReport {
+    fn problem(&mut self, file: &Path, message: impl ToString) {
+        self.problems.push(ValidationProblem {
+            file: file.to_owned(),
+            message: message.to_string(),
+        });
+    }
+}
+
+/// Loads a stylus directory the same way `stylus run` would, collecting every problem instead of stopping at the
+/// first.
+pub fn validate(path: &Path) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    let file = match config_file_path(path) {
+        Ok(file) => file,
+        Err(e) => {
+            report.problem(path, e);
+            return report;
+        }
+    };
+    let s = match std::fs::read_to_string(&file) {
+        Ok(s) => s,
+        Err(e) => {
+            report.problem(&file, e);
+            return report;
+        }
+    };
+
+    // Parsing stops at the first bad CSS rule, so check each rule on its own as well
+    let raw: Option<Config> = serde_yaml_ng::from_str(&s).ok();
+    let css_errors = raw
+        .as_ref()
+        .map(|raw| CssTemplates::errors(&trimmed(&raw.css.rules)))
+        .unwrap_or_default();
+    for error in &css_errors {
+        report.problem(&file, error);
+    }
+
+    let monitor_dir = match parse_config_string(&file, s) {
+        Ok(config) => config.monitor.dir,
+        Err(e) => {
+            if !css_errors.contains(&e.to_string()) {
+                report.problem(&file, e);
+            }
+            // Carry on with the monitors if we can still find them
+            match raw {
+                Some(raw) => file
+                    .parent()
+                    .unwrap_or(Path::new("/"))
+                    .join(raw.monitor.dir),
+                None => return report,
+            }
+        }
+    };
+
+    let files = match monitor_config_files(&monitor_dir) {
+        Ok(files) => files,
+        Err(e) => {
+            report.problem(&monitor_dir, e);
+            return report;
+        }
+    };
+    let mut monitors = vec![];
+    for file in files {
+        match parse_monitor_config(&file) {
+            Ok(monitor) => monitors.push((file, monitor)),
+            Err(e) => {
+                let css_errors = std::fs::read_to_string(&file)
+                    .ok()
+                    .and_then(|s| serde_yaml_ng::from_str::<MonitorDirConfig>(&s).ok())
+                    .map(|raw| CssTemplates::errors(&trimmed(&raw.css)))
+                    .unwrap_or_default();
+                for error in &css_errors {
+                    report.problem(&file, error);
+                }
+                if !css_errors.contains(&e.to_string()) {
+                    report.problem(&file, e);
+                }
+            }
+        }
+    }
+    if monitors.is_empty() && report.problems.is_empty() {
+        report.problem(
+            &monitor_dir,
+            "Unable to locate any monitor config.yaml files",
+        );
+    }
+    report.monitors = monitors.len();
+
+    for (file, monitor) in &monitors {
+        check_executables(&mut report, file, monitor);
+    }
+    check_ids(&mut report, &monitors);
+
+    let configs: Vec<_> = monitors.into_iter().map(|(_, monitor)| monitor).collect();
+    if let Err(e) = evaluation_order(&configs) {
+        report.problem(&monitor_dir, e);
+    }
+
+    report
+}
+
+fn trimmed(rules: &[CssRule]) -> Vec<CssRule> {
+    rules
+        .iter()
+        .cloned()
+        .map(|mut rule| {
+            rule.selectors = rule.selectors.trim().to_string();
+            rule.declarations = rule.declarations.trim().to_string();
+            rule
+        })
+        .collect()
+}
+
+/// Commands are resolved when the monitor is loaded, but may still fail to start.
+fn check_executables(report: &mut ValidationReport, file: &Path, monitor: &MonitorDirConfig) {
+    let mut commands = vec![&monitor.root.test().command];
+    if let MonitorDirRootConfig::Group(group) = &monitor.root {
+        if let Some(command) = group.axes_from.as_ref().and_then(|a| a.command.as_ref()) {
+            commands.push(command);
+        }
+    }
+    for command in commands {
+        if !command.is_file() {
+            report.problem(file, format!("Command {} is not a file", command.display()));
+            continue;
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = command.metadata().map(|m| m.permissions().mode());
+            if !matches!(mode, Ok(mode) if mode & 0o111 != 0) {
+                report.problem(
+                    file,
+                    format!("Command {} is not executable", command.display()),
+                );
+            }
+        }
+    }
+}
+
+/// Monitors and group children share one namespace of IDs, and later ones silently replace earlier ones.
+fn check_ids(report: &mut ValidationReport, monitors: &[(PathBuf, MonitorDirConfig)]) {
+    let mut ids: BTreeMap<&str, &Path> = BTreeMap::new();
+    for (file, monitor) in monitors {
+        if let Some(other) = ids.insert(&monitor.id, file) {
+            report.problem(
+                file,
+                format!(
+                    "Duplicate monitor id {:?} (also used by {})",
+                    monitor.id,
+                    other.display()
+                ),
+            );
+        }
+    }
+
+    let mut children: BTreeMap<String, &Path> = BTreeMap::new();
+    for (file, monitor) in monitors {
+        let MonitorDirRootConfig::Group(group) = &monitor.root else {
+            continue;
+        };
+        // Children are keyed by ID, so expand the axes again to find children whose IDs collide
+        let mut seen = BTreeMap::new();
+        for axes in expand_axes(&group.axes) {
+            let Ok(id) = interpolate_id(&axes, &group.id) else {
+                continue;
+            };
+            if let Some(other) = seen.insert(id.clone(), axes.clone()) {
+                report.problem(
+                    file,
+                    format!(
+                        "Group children with axes {} and {} both have id {id:?}",
+                        serde_json::to_string(&other).unwrap_or_default(),
+                        serde_json::to_string(&axes).unwrap_or_default(),
+                    ),
+                );
+            }
+        }
+        for id in group.children.keys() {
+            if let Some(other) = ids.get(id.as_str()).or(children.get(id)) {
+                report.problem(
+                    file,
+                    format!(
+                        "Group child id {id:?} collides with a monitor in {}",
+                        other.display()
+                    ),
+                );
+            } else {
+                children.insert(id.clone(), file);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, contents: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    fn messages(report: &ValidationReport) -> Vec<String> {
+        report
+            .problems
+            .iter()
+            .map(|p| {
+                format!(
+                    "{}: {}",
+                    p.file.file_name().unwrap().to_string_lossy(),
+                    p.message
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn validate_template() {
+        let report = validate(Path::new("src/template"));
+        assert_eq!(report.problems, vec![]);
+        assert_eq!(report.monitors, 3);
+    }
+
+    #[test]
+    fn validate_reports_all_problems() {
+        let dir = std::env::temp_dir().join(format!("stylus-validate-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        write(
+            &dir.join("config.yaml"),
+            r#"
+version: 1
+server:
+  port: 8000
+monitor:
+  dir: monitor.d
+css:
+  metadata: {}
+  rules:
+    - selectors: "{{#if}}"
+      declarations: ""
+    - selectors: ""
+      declarations: "{{/each}}"
+"#,
+        );
+        let test = "test:\n  interval: 60s\n  timeout: 30s\n  command: test.sh\n";
+        write(&dir.join("monitor.d/a/config.yaml"), test);
+        write(&dir.join("monitor.d/a/test.sh"), "#!/bin/sh\n");
+        write(
+            &dir.join("monitor.d/b/config.yaml"),
+            &format!("id: a\n{test}"),
+        );
+        std::fs::copy(
+            dir.join("monitor.d/a/test.sh"),
+            dir.join("monitor.d/b/test.sh"),
+        )
+        .unwrap();
+        write(
+            &dir.join("monitor.d/c/config.yaml"),
+            &format!("{test}  command: missing.sh\n").replace("  command: test.sh\n", ""),
+        );
+        write(
+            &dir.join("monitor.d/group/config.yaml"),
+            r#"
+group:
+  id: a-{{ index }}
+  axes:
+    - values: [1, 2]
+      name: index
+    - values: [x, y]
+      name: other
+  test:
+    interval: 60s
+    timeout: 30s
+    command: /bin/sh
+"#,
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            for monitor in ["a", "b"] {
+                let path = dir.join("monitor.d").join(monitor).join("test.sh");
+                let executable = if monitor == "a" { 0o755 } else { 0o644 };
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(executable))
+                    .unwrap();
+            }
+        }
+
+        let report = validate(&dir);
+        let messages = messages(&report);
+        assert_eq!(report.monitors, 3, "{messages:#?}");
+        let expected = [
+            "config.yaml: Invalid selectors in CSS rule 1",
+            "config.yaml: Invalid declarations in CSS rule 2",
+            "config.yaml: Command missing.sh is not available",
+            "config.yaml: Duplicate monitor id \"a\"",
+            "config.yaml: Group children with axes {\"index\":1,\"other\":\"x\"} and {\"index\":1,\"other\":\"y\"} both have id \"a-1\"",
+        ];
+        for expected in expected {
+            assert!(
+                messages.iter().any(|m| m.starts_with(expected)),
+                "{expected} not in {messages:#?}"
+            );
+        }
+        #[cfg(unix)]
+        assert!(messages
+            .iter()
+            .any(|m| m.ends_with("test.sh is not executable")));
+        // Both the directory and the CSS errors are found despite the config failing to parse
+        assert!(report
+            .problems
+            .iter()
+            .any(|p| p.file == dir.join("monitor.d/c/config.yaml")));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
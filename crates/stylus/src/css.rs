@@ -1,5 +1,5 @@
 use crate::config::*;
-use crate::interpolate::*;
+use crate::interpolate::CssTarget;
 use crate::status::*;
 
 pub fn generate_css_for_state(config: &CssConfig, status: &Status) -> String {
@@ -17,6 +17,15 @@ pub fn generate_css_for_state(config: &CssConfig, status: &Status) -> String {
 }
 
 pub fn generate_css_for_monitor(config: &CssConfig, monitor: &MonitorState) -> String {
+    let mut css = generate_default_css(monitor);
+    for (target, status) in css_targets(monitor) {
+        css += &generate_css_rules(config, monitor, &target, status);
+    }
+    css
+}
+
+/// Renders the CSS variables of a monitor, which don't depend on any rules.
+fn generate_default_css(monitor: &MonitorState) -> String {
     let mut css = format!("/* {} */\n", monitor.id);
 
     css += format!("\n/* Default rules */\n").as_str();
@@ -35,45 +44,212 @@ pub fn generate_css_for_monitor(config: &CssConfig, monitor: &MonitorState) -> S
         monitor.status.description
     )
     .as_str();
+    if let Some(counts) = &monitor.status.child_counts {
+        for (k, v) in counts.variables() {
+            css += format!("  --monitor-{}: {};\n", k.replace('_', "-"), v).as_str();
+        }
+    }
     for (k, v) in monitor.status.metadata.iter() {
         if k.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
             css += format!("  --monitor-metadata-{k}: {};\n", v).as_str();
         }
     }
     css += format!("}}\n").as_str();
+    css
+}
 
-    for rule in &config.rules {
-        css += &interpolate_monitor(
-            &monitor.id,
-            &monitor.config,
-            &monitor.status,
-            &rule.selectors,
-        )
-        .unwrap_or_else(|_| "/* failed */".into());
-        css += " {\n";
-        css += &interpolate_monitor(
-            &monitor.id,
-            &monitor.config,
-            &monitor.status,
-            &rule.declarations,
-        )
-        .unwrap_or_else(|_| "/* failed */".into());
-        css += "\n}\n\n";
-    }
-    for child in monitor.children.iter() {
-        for rule in &config.rules {
-            css += &interpolate_monitor(child.0, &monitor.config, &child.1.status, &rule.selectors)
-                .unwrap_or_else(|_| "/* failed */".into());
-            css += " {\n";
-            css += &interpolate_monitor(
-                child.0,
-                &monitor.config,
-                &child.1.status,
-                &rule.declarations,
-            )
-            .unwrap_or_else(|_| "/* failed */".into());
-            css += "\n}\n\n";
+/// The monitor itself, followed by each of its group children, along with their statuses.
+fn css_targets(monitor: &MonitorState) -> impl Iterator<Item = (CssTarget<'_>, &MonitorStatus)> {
+    let target = |id, axes| CssTarget {
+        id,
+        kind: monitor.kind,
+        tags: &monitor.tags,
+        axes,
+    };
+    std::iter::once((target(&monitor.id, None), &monitor.status)).chain(
+        monitor
+            .children
+            .iter()
+            .map(move |(id, child)| (target(id, Some(&child.axes)), &child.status)),
+    )
+}
+
+/// Renders the global rules, then the monitor's own rules, that match a monitor or group child.
+fn generate_css_rules(
+    config: &CssConfig,
+    monitor: &MonitorState,
+    target: &CssTarget,
+    status: &MonitorStatus,
+) -> String {
+    let mut css = String::new();
+    for (templates, rules) in [
+        (&config.templates, &config.rules),
+        (&monitor.css_templates, &monitor.css_rules),
+    ] {
+        for (index, rule) in rules.iter().enumerate() {
+            if !templates.matches(index, rule.matches.as_ref(), target) {
+                continue;
+            }
+            let (selectors, declarations) = templates
+                .render_monitor(index, target.id, &monitor.config, status)
+                .unwrap_or_else(|_| ("/* failed */".into(), "/* failed */".into()));
+            css += &format!("{selectors} {{\n{declarations}\n}}\n\n");
         }
     }
     css
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpolate::CssTemplates;
+    use std::error::Error;
+    use std::path::Path;
+    use std::time::Instant;
+
+    /// Tests that rules are only generated for the monitors and children they match.
+    #[test]
+    fn scoped_rules() -> Result<(), Box<dyn Error>> {
+        let monitor = parse_monitor_config_string(
+            Path::new("/tmp/test.yaml"),
+            r##"
+id: switch
+tags: [core, network]
+css:
+    - selectors: "#{{monitor.id}} .own"
+      declarations: "color: red;"
+      match: { child: false }
+group:
+    id: port-{{ index }}
+    axes:
+        - name: index
+          values: [1, 2]
+    test:
+        interval: 60s
+        timeout: 30s
+        command: /bin/sleep
+            "##
+            .to_string(),
+        )?;
+        let mut css: CssConfig = serde_yaml_ng::from_str(
+            r##"
+metadata: {}
+rules:
+    - selectors: "#{{monitor.id}} .all"
+      declarations: ""
+    - selectors: "#{{monitor.id}} .ports"
+      declarations: ""
+      match: { id: "port-*" }
+    - selectors: "#{{monitor.id}} .port-2"
+      declarations: ""
+      match: { axes: { index: 2 } }
+    - selectors: "#{{monitor.id}} .core-group"
+      declarations: ""
+      match: { type: group, tags: [core], child: false }
+    - selectors: "#{{monitor.id}} .edge"
+      declarations: ""
+      match: { tags: [edge] }
+    - selectors: "#{{monitor.id}} .snmp"
+      declarations: ""
+      match: { type: snmp }
+        "##,
+        )?;
+        css.templates = CssTemplates::compile(&css.rules)?;
+        let generated = generate_css_for_monitor(&css, &MonitorState::from(&monitor));
+        let selectors: Vec<_> = generated
+            .lines()
+            .filter(|line| line.starts_with('#'))
+            .collect();
+        assert_eq!(
+            selectors,
+            vec![
+                "#switch .all {",
+                "#switch .core-group {",
+                "#switch .own {",
+                "#port-1 .all {",
+                "#port-1 .ports {",
+                "#port-2 .all {",
+                "#port-2 .ports {",
+                "#port-2 .port-2 {",
+            ]
+        );
+        Ok(())
+    }
+
+    /// Times CSS generation for a group with many children, as an SNMP switch would have. Run with
+    /// `just bench-css`. On a single core, generating the CSS of 501 monitors with 3 rules took 11-14ms precompiled
+    /// and 48-56ms compiled per monitor.
+    #[test]
+    #[ignore]
+    fn benchmark() -> Result<(), Box<dyn Error>> {
+        let monitor = parse_monitor_config_string(
+            Path::new("/tmp/test.yaml"),
+            r#"
+id: switch
+group:
+    id: port-{{ index }}
+    axes:
+        - name: index
+          range: { start: 1, end: 500 }
+    test:
+        interval: 60s
+        timeout: 30s
+        command: /bin/sleep
+            "#
+            .to_string(),
+        )?;
+        let mut css: CssConfig = serde_yaml_ng::from_str(
+            r##"
+metadata:
+    red: { color: "#fa897b" }
+    green: { color: "#d0e6a5" }
+rules:
+    - selectors: |
+        #{{monitor.id}},
+        [data-monitor-id="{{monitor.id}}"] > *
+      declarations: |
+        background-color: {{monitor.status.css.metadata.color}} !important;
+    - selectors: "#{{monitor.id}} td:nth-child(2)::after"
+      declarations: |
+        content: "status={{monitor.status.status}} retval={{monitor.status.code}}"
+    - selectors: "#{{monitor.id}} td:nth-child(3)::after"
+      declarations: |
+        content: "{{monitor.status.description}}"
+        "##,
+        )?;
+        css.templates = CssTemplates::compile(&css.rules)?;
+        let state = MonitorState::from(&monitor);
+
+        // The monitor's own rules would need compiling too
+        assert!(state.css_rules.is_empty());
+
+        const RUNS: u32 = 20;
+        let start = Instant::now();
+        let mut precompiled_css = String::new();
+        for _ in 0..RUNS {
+            precompiled_css = generate_css_for_monitor(&css, &state);
+        }
+        let precompiled = start.elapsed() / RUNS;
+
+        // Compiling the templates for every monitor and child, as was done before they were precompiled
+        let start = Instant::now();
+        let mut uncached_css = String::new();
+        for _ in 0..RUNS {
+            let mut css = css.clone();
+            uncached_css = generate_default_css(&state);
+            for (target, status) in css_targets(&state) {
+                css.templates = CssTemplates::compile(&css.rules)?;
+                uncached_css += &generate_css_rules(&css, &state, &target, status);
+            }
+        }
+        let uncached = start.elapsed() / RUNS;
+        assert_eq!(precompiled_css, uncached_css);
+
+        println!(
+            "{} monitors, {} rules: precompiled {precompiled:?}, compiled per monitor {uncached:?}",
+            state.children.len() + 1,
+            css.rules.len()
+        );
+        Ok(())
+    }
+}
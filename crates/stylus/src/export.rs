@@ -0,0 +1,185 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::Path;
+
+use walkdir::WalkDir;
+
+use crate::config::Config;
+use crate::css::generate_css_for_state;
+use crate::status::Status;
+
+/// Writes a self-contained snapshot of the status page to a directory, which works when opened from `file://` or
+/// served by any static web host.
+///
+/// Browsers won't `fetch` from `file://`, so the files that the UI loads are also embedded in `snapshot.js`,
+/// which the UI reads instead when it's opened from disk.
+pub fn write_snapshot(dir: &Path, config: &Config, status: &Status) -> Result<(), Box<dyn Error>> {
+    std::fs::create_dir_all(dir)?;
+    let dir = dir.canonicalize()?;
+    let mut embedded = BTreeMap::new();
+
+    // Static files are copied first, so a custom index.html replaces the UI just like it does when serving
+    let mut custom_index = false;
+    if let Some(static_path) = &config.server.static_path {
+        for entry in WalkDir::new(static_path)
+            .follow_links(true)
+            .into_iter()
+            .filter_entry(|e| !e.path().starts_with(&dir))
+        {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let relative = entry.path().strip_prefix(static_path)?;
+            let contents = std::fs::read(entry.path())?;
+            write_file(&dir.join(relative), &contents)?;
+            custom_index |= relative == Path::new("index.html");
+            // SVG visualizations are fetched by the UI
+            if relative.extension().unwrap_or_default() == "svg" {
+                let name = relative.to_string_lossy().replace('\\', "/");
+                embedded.insert(name, String::from_utf8_lossy(&contents).into_owned());
+            }
+        }
+    }
+
+    embedded.insert(
+        "config.json".into(),
+        serde_json::to_string_pretty(&public_config(config))?,
+    );
+    embedded.insert("status.json".into(), serde_json::to_string_pretty(status)?);
+    embedded.insert(
+        "style.css".into(),
+        generate_css_for_state(&config.css, status),
+    );
+    for monitor in &status.monitors {
+        let monitor = monitor.read();
+        let mut log = String::new();
+        for line in &monitor.status.log {
+            log += line;
+            log += "\n";
+        }
+        embedded.insert(format!("log/{}", monitor.id), log);
+    }
+    for (name, contents) in &embedded {
+        if !name.ends_with(".svg") {
+            write_file(&dir.join(name), contents.as_bytes())?;
+        }
+    }
+    write_file(
+        &dir.join("snapshot.js"),
+        format!(
+            "window.STYLUS_SNAPSHOT = {};\n",
+            serde_json::to_string(&embedded)?
+        )
+        .as_bytes(),
+    )?;
+
+    #[cfg(feature = "builtin-ui")]
+    {
+        write_file(&dir.join("stylus.css"), stylus_ui::STYLUS_CSS.as_bytes())?;
+        write_file(&dir.join("stylus.svg"), stylus_ui::STYLUS_LOGO.as_bytes())?;
+    }
+    if !custom_index {
+        write_file(&dir.join("index.html"), index_html(status)?.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// The configuration as published in a snapshot, without secrets or the paths on this machine.
+fn public_config(config: &Config) -> Config {
+    let mut config = config.clone();
+    config.server.ingest = None;
+    config.server.trigger = None;
+    config.server.export = None;
+    config.server.static_path = None;
+    config.monitor = Default::default();
+    config.base_path = Default::default();
+    config
+}
+
+#[cfg(feature = "builtin-ui")]
+fn index_html(_status: &Status) -> Result<String, Box<dyn Error>> {
+    const SCRIPT: &str = r#"<script type="module" src="stylus.js"></script>"#;
+    if !stylus_ui::STYLUS_HTML.contains(SCRIPT) {
+        return Err("Unable to find the script in the built-in UI".into());
+    }
+    // Browsers refuse to load module scripts from file://, so the UI is inlined
+    let script = stylus_ui::STYLUS_JAVASCRIPT.replace("</script", "<\\/script");
+    Ok(stylus_ui::STYLUS_HTML.replace(
+        SCRIPT,
+        &format!(
+            "<script src=\"snapshot.js\"></script>\n    <script type=\"module\">{script}</script>"
+        ),
+    ))
+}
+
+#[cfg(not(feature = "builtin-ui"))]
+fn index_html(status: &Status) -> Result<String, Box<dyn Error>> {
+    crate::http::default_index_html(status)
+}
+
+/// Replaces a file in one step, so that a web server never serves a partially-written snapshot.
+fn write_file(path: &Path, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    std::fs::write(&temp, contents)?;
+    std::fs::rename(&temp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{parse_config, parse_monitor_configs, IngestConfig};
+    use crate::status::MonitorState;
+    use keepcalm::SharedMut;
+
+    #[test]
+    fn snapshot() -> Result<(), Box<dyn Error>> {
+        let mut config = parse_config(Path::new("src/template"))?;
+        config.server.ingest = Some(IngestConfig {
+            token: "s3cret".into(),
+            stale_after: std::time::Duration::from_secs(60),
+        });
+        let monitors = parse_monitor_configs(&config.monitor.dir)?;
+        let status = Status {
+            monitors: monitors
+                .iter()
+                .map(|monitor| SharedMut::new(MonitorState::from(monitor)))
+                .collect(),
+        };
+
+        let dir = std::env::temp_dir().join(format!("stylus-export-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        write_snapshot(&dir, &config, &status)?;
+        for file in [
+            "index.html",
+            "config.json",
+            "status.json",
+            "style.css",
+            "snapshot.js",
+            "iframe.html",
+            "log/my-monitor",
+        ] {
+            assert!(dir.join(file).is_file(), "{file}");
+        }
+
+        let exported = std::fs::read_to_string(dir.join("config.json"))?;
+        assert!(!exported.contains("s3cret"));
+        assert!(!exported.contains("src/template"), "{exported}");
+        let snapshot = std::fs::read_to_string(dir.join("snapshot.js"))?;
+        assert!(snapshot.starts_with("window.STYLUS_SNAPSHOT = {\"config.json\":"));
+        assert!(snapshot.contains("\"log/my-monitor\":"));
+        if cfg!(feature = "builtin-ui") {
+            let index = std::fs::read_to_string(dir.join("index.html"))?;
+            assert!(index.contains("<script src=\"snapshot.js\"></script>"));
+            assert!(!index.contains("src=\"stylus.js\""));
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+        Ok(())
+    }
+}
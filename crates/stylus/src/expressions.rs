@@ -1,11 +1,18 @@
 use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
 
-#[derive(Clone, PartialEq, Eq)]
+use peg::ParseLiteral;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, PartialEq)]
 pub enum Value {
     Int(i64),
+    Float(f64),
     Str(Cow<'static, str>),
+    List(Vec<Value>),
 }
 
 pub type Result = std::result::Result<Value, Error>;
@@ -17,23 +24,60 @@ impl std::fmt::Debug for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Value::Int(i) => write!(f, "{i:?}"),
+            Value::Float(n) => write!(f, "{n:?}"),
             Value::Str(s) => write!(f, "{s:?}"),
+            Value::List(l) => f.debug_list().entries(l).finish(),
         }
     }
 }
 
 impl Value {
     pub fn as_int(&self) -> i64 {
+        match self.as_number() {
+            Value::Int(i) => i,
+            Value::Float(n) => n as i64,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn as_float(&self) -> f64 {
+        match self.as_number() {
+            Value::Int(i) => i as f64,
+            Value::Float(n) => n,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Coerce this value to an `Int` or `Float`, parsing strings as integers first, then floats.
+    pub fn as_number(&self) -> Value {
         match self {
-            Value::Int(i) => *i,
-            Value::Str(s) => s.parse::<i64>().unwrap_or_default(),
+            Value::Int(i) => Value::Int(*i),
+            Value::Float(n) => Value::Float(*n),
+            Value::Str(s) => {
+                let s = s.trim();
+                if let Ok(i) = s.parse::<i64>() {
+                    Value::Int(i)
+                } else if let Ok(n) = s.parse::<f64>() {
+                    Value::Float(n)
+                } else {
+                    Value::Int(0)
+                }
+            }
+            Value::List(_) => Value::Int(0),
         }
     }
 
     pub fn as_str(&self) -> Cow<'static, str> {
         match self {
             Value::Int(i) => i.to_string().into(),
+            Value::Float(n) => n.to_string().into(),
             Value::Str(s) => s.clone(),
+            Value::List(l) => l
+                .iter()
+                .map(|v| v.as_str())
+                .collect::<Vec<_>>()
+                .join(",")
+                .into(),
         }
     }
 
@@ -41,14 +85,27 @@ impl Value {
         self.is_truthy()
     }
 
-    fn from_bool(b: bool) -> Self {
+    /// Parse a metadata string, keeping numbers numeric so that they can be used in arithmetic.
+    pub fn from_metadata(s: &str) -> Self {
+        if let Ok(i) = s.trim().parse::<i64>() {
+            Value::Int(i)
+        } else if let Ok(n) = s.trim().parse::<f64>() {
+            Value::Float(n)
+        } else {
+            Value::Str(s.to_string().into())
+        }
+    }
+
+    pub fn from_bool(b: bool) -> Self {
         Value::Int(if b { 1 } else { 0 })
     }
 
     fn is_truthy(&self) -> bool {
         match self {
             Value::Int(i) => *i != 0,
+            Value::Float(n) => *n != 0.0,
             Value::Str(s) => !s.is_empty(),
+            Value::List(l) => !l.is_empty(),
         }
     }
 
@@ -68,45 +125,158 @@ impl Value {
         match (self, other) {
             (Value::Int(a), Value::Int(b)) => a.cmp(&b),
             (Value::Str(a), Value::Str(b)) => a.cmp(&b),
-            (a, b) => a.as_int().cmp(&b.as_int()),
+            (Value::List(a), Value::List(b)) => {
+                for (a, b) in a.iter().zip(b.iter()) {
+                    match a.clone().cmp(b.clone()) {
+                        Ordering::Equal => continue,
+                        ordering => return ordering,
+                    }
+                }
+                a.len().cmp(&b.len())
+            }
+            (a, b) => match (a.as_number(), b.as_number()) {
+                (Value::Int(a), Value::Int(b)) => a.cmp(&b),
+                (a, b) => a.as_float().total_cmp(&b.as_float()),
+            },
+        }
+    }
+
+    /// Apply a numeric operation, promoting to float if either side is a float.
+    fn numeric(
+        self,
+        other: Value,
+        int: impl FnOnce(i64, i64) -> Option<i64>,
+        float: impl FnOnce(f64, f64) -> f64,
+    ) -> Result {
+        match (self.as_number(), other.as_number()) {
+            (Value::Int(a), Value::Int(b)) => int(a, b)
+                .map(Value::Int)
+                .ok_or(Error("overflow".to_string())),
+            (a, b) => Ok(Value::Float(float(a.as_float(), b.as_float()))),
         }
     }
 
-    fn add(self, other: Value) -> Value {
+    fn add(self, other: Value) -> Result {
         match (self, other) {
-            (Value::Int(a), Value::Int(b)) => Value::Int(a + b),
-            (Value::Str(a), Value::Str(b)) => Value::Str(format!("{}{}", a, b).into()),
-            (a, b) => Value::Str(format!("{}{}", a.as_str(), b.as_str()).into()),
+            (Value::Str(a), Value::Str(b)) => Ok(Value::Str(format!("{}{}", a, b).into())),
+            (a @ Value::Str(_), b) | (a, b @ Value::Str(_)) => {
+                Ok(Value::Str(format!("{}{}", a.as_str(), b.as_str()).into()))
+            }
+            (a, b) => a.numeric(b, i64::checked_add, |a, b| a + b),
         }
     }
 
-    fn sub(self, other: Value) -> Value {
-        Value::Int(self.as_int() - other.as_int())
+    fn sub(self, other: Value) -> Result {
+        self.numeric(other, i64::checked_sub, |a, b| a - b)
     }
 
-    fn mul(self, other: Value) -> Value {
-        Value::Int(self.as_int() * other.as_int())
+    fn mul(self, other: Value) -> Result {
+        self.numeric(other, i64::checked_mul, |a, b| a * b)
     }
 
     fn div(self, other: Value) -> Result {
-        if other.as_int() == 0 {
+        if other.as_float() == 0.0 {
             Err(Error("division by zero".to_string()))
         } else {
-            Ok(Value::Int(self.as_int() / other.as_int()))
+            // Integers use integer division
+            self.numeric(other, i64::checked_div, |a, b| a / b)
         }
     }
 
     fn pow_val(self, other: Value) -> Result {
-        let a = self.as_int();
-        let b = other.as_int();
-        Ok(Value::Int(
-            a.checked_pow(b.try_into().map_err(|_| Error("overflow".to_string()))?)
-                .ok_or(Error("overflow".to_string()))?,
-        ))
+        match (self.as_number(), other.as_number()) {
+            (Value::Int(a), Value::Int(b)) if b >= 0 => Ok(Value::Int(
+                a.checked_pow(b.try_into().map_err(|_| Error("overflow".to_string()))?)
+                    .ok_or(Error("overflow".to_string()))?,
+            )),
+            (a, b) => Ok(Value::Float(a.as_float().powf(b.as_float()))),
+        }
     }
 
     fn negate(self) -> Value {
-        Value::Int(-self.as_int())
+        match self.as_number() {
+            Value::Float(n) => Value::Float(-n),
+            other => Value::Int(-other.as_int()),
+        }
+    }
+
+    /// Membership: an element of a list, or a substring of a string.
+    fn contained_in(self, other: Value) -> Value {
+        match other {
+            Value::List(l) => Value::from_bool(
+                l.into_iter()
+                    .any(|v| self.clone().cmp(v) == Ordering::Equal),
+            ),
+            other => Value::from_bool(other.as_str().contains(&*self.as_str())),
+        }
+    }
+
+    fn length(self) -> Value {
+        match self {
+            Value::List(l) => Value::Int(l.len() as i64),
+            other => Value::Int(other.as_str().len() as i64),
+        }
+    }
+
+    fn abs(self) -> Value {
+        match self.as_number() {
+            Value::Float(n) => Value::Float(n.abs()),
+            other => Value::Int(other.as_int().abs()),
+        }
+    }
+
+    /// Round a float to an integer using the given function, leaving integers alone.
+    fn round_with(self, f: impl FnOnce(f64) -> f64) -> Value {
+        match self.as_number() {
+            Value::Float(n) => Value::Int(f(n) as i64),
+            other => other,
+        }
+    }
+
+    fn round_digits(self, digits: Value) -> Value {
+        let scale = 10_f64.powi(digits.as_int() as i32);
+        Value::Float((self.as_float() * scale).round() / scale)
+    }
+
+    fn min_max(values: Vec<Value>, ordering: Ordering) -> Result {
+        let mut values = values.into_iter();
+        let mut result = values
+            .next()
+            .ok_or(Error("expected at least one argument".to_string()))?;
+        for value in values {
+            if value.clone().cmp(result.clone()) == ordering {
+                result = value;
+            }
+        }
+        Ok(result)
+    }
+}
+
+fn regex(pattern: &str) -> std::result::Result<Regex, Error> {
+    Regex::new(pattern).map_err(|e| Error(format!("invalid regular expression: {e}")))
+}
+
+/// Extract a capture group by index or name, defaulting to the first group (or the whole match if there are no groups).
+fn capture(s: Value, regex: &Regex, group: Option<Value>) -> Value {
+    let s = s.as_str();
+    let Some(captures) = regex.captures(&s) else {
+        return Value::Str("".into());
+    };
+    let m = match group {
+        Some(Value::Str(name)) => captures.name(&name),
+        Some(index) => captures.get(index.as_int() as usize),
+        None => captures.get(if captures.len() > 1 { 1 } else { 0 }),
+    };
+    Value::Str(m.map(|m| m.as_str().to_string()).unwrap_or_default().into())
+}
+
+/// Create a value from a number with a unit, returning an integer if the value is integral.
+fn unit_value(n: f64, scale: f64) -> Value {
+    let n = n * scale;
+    if n.fract() == 0.0 && n.abs() < i64::MAX as f64 {
+        Value::Int(n as i64)
+    } else {
+        Value::Float(n)
     }
 }
 
@@ -134,6 +304,11 @@ fn unescape_string(input: &str) -> String {
 
 pub trait ExpressionContext {
     fn get(&self, key: &str) -> Option<&Value>;
+
+    /// The status of another monitor, for `status('id')`.
+    fn status(&self, _id: &str) -> Option<Value> {
+        None
+    }
 }
 
 impl ExpressionContext for HashMap<String, Value> {
@@ -148,71 +323,555 @@ impl ExpressionContext for BTreeMap<String, Value> {
     }
 }
 
-peg::parser!( pub grammar expression() for str {
+/// Looks variables up in each of the contexts in turn.
+pub struct Layered<'a>(pub &'a [&'a dyn ExpressionContext]);
+
+impl ExpressionContext for Layered<'_> {
+    fn get(&self, key: &str) -> Option<&Value> {
+        self.0.iter().find_map(|context| context.get(key))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum BinaryOp {
+    Or,
+    And,
+    Ge,
+    Le,
+    Eq,
+    Ne,
+    Gt,
+    Lt,
+    In,
+    NotIn,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Function {
+    Str,
+    Int,
+    Float,
+    Round,
+    Floor,
+    Ceil,
+    Abs,
+    Min,
+    Max,
+    StartsWith,
+    EndsWith,
+    Contains,
+    Length,
+    Lower,
+    Upper,
+    Replace,
+    Split,
+}
+
+/// A regular expression argument, compiled up front if it is a string literal.
+#[derive(Clone, Debug)]
+enum Pattern {
+    Compiled(Regex),
+    Dynamic(Box<Expr>),
+}
+
+impl Pattern {
+    fn new(expr: Expr) -> std::result::Result<Self, &'static str> {
+        match expr {
+            Expr::Literal(Value::Str(s)) => Regex::new(&s)
+                .map(Pattern::Compiled)
+                .map_err(|_| "a valid regular expression"),
+            expr => Ok(Pattern::Dynamic(Box::new(expr))),
+        }
+    }
+
+    fn evaluate(&self, ctx: &dyn ExpressionContext) -> std::result::Result<Cow<'_, Regex>, Error> {
+        match self {
+            Pattern::Compiled(regex) => Ok(Cow::Borrowed(regex)),
+            Pattern::Dynamic(expr) => Ok(Cow::Owned(regex(&expr.evaluate(ctx)?.as_str())?)),
+        }
+    }
+}
+
+/// The parsed form of an expression.
+#[derive(Clone, Debug)]
+enum Expr {
+    Literal(Value),
+    /// An identifier and its offset in the source.
+    Ident(String, usize),
+    List(Vec<Expr>),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    /// `then if condition else otherwise`
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+    Call(Function, Vec<Expr>),
+    Matches(Box<Expr>, Pattern),
+    Capture(Box<Expr>, Pattern, Option<Box<Expr>>),
+    /// The status of a monitor and the offset of its ID in the source.
+    Status(String, usize),
+}
+
+impl Expr {
+    fn binary(op: BinaryOp, a: Expr, b: Expr) -> Expr {
+        Expr::Binary(op, Box::new(a), Box::new(b))
+    }
+
+    fn evaluate(&self, ctx: &dyn ExpressionContext) -> Result {
+        Ok(match self {
+            Expr::Literal(v) => v.clone(),
+            Expr::Ident(id, _) => match ctx.get(id) {
+                Some(v) => v.clone(),
+                None => return Err(Error(format!("unknown identifier: {}", id))),
+            },
+            Expr::List(items) => Value::List(
+                items
+                    .iter()
+                    .map(|e| e.evaluate(ctx))
+                    .collect::<std::result::Result<_, _>>()?,
+            ),
+            Expr::Not(v) => v.evaluate(ctx)?.logical_not(),
+            Expr::Negate(v) => v.evaluate(ctx)?.negate(),
+            Expr::Binary(op, a, b) => {
+                // Both sides are always evaluated, so errors on either side are reported
+                let (a, b) = (a.evaluate(ctx)?, b.evaluate(ctx)?);
+                match op {
+                    BinaryOp::Or => a.logical_or(b),
+                    BinaryOp::And => a.logical_and(b),
+                    BinaryOp::Ge => Value::from_bool(a.cmp(b) >= Ordering::Equal),
+                    BinaryOp::Le => Value::from_bool(a.cmp(b) <= Ordering::Equal),
+                    BinaryOp::Eq => Value::from_bool(a.cmp(b) == Ordering::Equal),
+                    BinaryOp::Ne => Value::from_bool(a.cmp(b) != Ordering::Equal),
+                    BinaryOp::Gt => Value::from_bool(a.cmp(b) == Ordering::Greater),
+                    BinaryOp::Lt => Value::from_bool(a.cmp(b) == Ordering::Less),
+                    BinaryOp::In => a.contained_in(b),
+                    BinaryOp::NotIn => a.contained_in(b).logical_not(),
+                    BinaryOp::Add => a.add(b)?,
+                    BinaryOp::Sub => a.sub(b)?,
+                    BinaryOp::Mul => a.mul(b)?,
+                    BinaryOp::Div => a.div(b)?,
+                    BinaryOp::Pow => a.pow_val(b)?,
+                }
+            }
+            Expr::Conditional(then, condition, otherwise) => {
+                if condition.evaluate(ctx)?.is_truthy() {
+                    then.evaluate(ctx)?
+                } else {
+                    otherwise.evaluate(ctx)?
+                }
+            }
+            Expr::Call(function, args) => {
+                let mut args = args
+                    .iter()
+                    .map(|e| e.evaluate(ctx))
+                    .collect::<std::result::Result<Vec<_>, _>>()?;
+                if matches!(function, Function::Min | Function::Max) {
+                    let ordering = if matches!(function, Function::Min) {
+                        Ordering::Less
+                    } else {
+                        Ordering::Greater
+                    };
+                    return Value::min_max(args, ordering);
+                }
+                let c = args.pop();
+                let b = args.pop();
+                let a = args.pop();
+                match (function, a, b, c) {
+                    (Function::Str, _, _, Some(v)) => Value::Str(v.as_str()),
+                    (Function::Int, _, _, Some(v)) => Value::Int(v.as_int()),
+                    (Function::Float, _, _, Some(v)) => Value::Float(v.as_float()),
+                    (Function::Round, _, Some(v), Some(d)) => v.round_digits(d),
+                    (Function::Round, _, None, Some(v)) => v.round_with(f64::round),
+                    (Function::Floor, _, _, Some(v)) => v.round_with(f64::floor),
+                    (Function::Ceil, _, _, Some(v)) => v.round_with(f64::ceil),
+                    (Function::Abs, _, _, Some(v)) => v.abs(),
+                    (Function::StartsWith, _, Some(a), Some(b)) => {
+                        Value::from_bool(a.as_str().starts_with(&*b.as_str()))
+                    }
+                    (Function::EndsWith, _, Some(a), Some(b)) => {
+                        Value::from_bool(a.as_str().ends_with(&*b.as_str()))
+                    }
+                    (Function::Contains, _, Some(a), Some(b)) => {
+                        Value::from_bool(a.as_str().contains(&*b.as_str()))
+                    }
+                    (Function::Length, _, _, Some(v)) => v.length(),
+                    (Function::Lower, _, _, Some(v)) => {
+                        Value::Str(v.as_str().to_lowercase().into())
+                    }
+                    (Function::Upper, _, _, Some(v)) => {
+                        Value::Str(v.as_str().to_uppercase().into())
+                    }
+                    (Function::Replace, Some(s), Some(from), Some(to)) => {
+                        Value::Str(s.as_str().replace(&*from.as_str(), &to.as_str()).into())
+                    }
+                    (Function::Split, _, Some(s), Some(sep)) => Value::List(
+                        s.as_str()
+                            .split(&*sep.as_str())
+                            .map(|s| Value::Str(s.to_string().into()))
+                            .collect(),
+                    ),
+                    (function, ..) => unreachable!("invalid arguments for {function:?}"),
+                }
+            }
+            Expr::Matches(s, pattern) => {
+                let s = s.evaluate(ctx)?.as_str();
+                Value::from_bool(pattern.evaluate(ctx)?.is_match(&s))
+            }
+            Expr::Capture(s, pattern, group) => {
+                let s = s.evaluate(ctx)?;
+                let group = group.as_ref().map(|g| g.evaluate(ctx)).transpose()?;
+                capture(s, &*pattern.evaluate(ctx)?, group)
+            }
+            Expr::Status(id, _) => match ctx.status(id) {
+                Some(v) => v,
+                None => return Err(Error(format!("unknown monitor: {}", id))),
+            },
+        })
+    }
+
+    /// Visit this expression and everything it contains.
+    fn visit<'a>(&'a self, f: &mut impl FnMut(&'a Expr)) {
+        f(self);
+        match self {
+            Expr::Literal(_) | Expr::Ident(..) | Expr::Status(..) => {}
+            Expr::List(items) | Expr::Call(_, items) => {
+                items.iter().for_each(|e| e.visit(f));
+            }
+            Expr::Not(v) | Expr::Negate(v) => v.visit(f),
+            Expr::Binary(_, a, b) => {
+                a.visit(f);
+                b.visit(f);
+            }
+            Expr::Conditional(a, b, c) => {
+                a.visit(f);
+                b.visit(f);
+                c.visit(f);
+            }
+            Expr::Matches(s, pattern) | Expr::Capture(s, pattern, None) => {
+                s.visit(f);
+                if let Pattern::Dynamic(p) = pattern {
+                    p.visit(f);
+                }
+            }
+            Expr::Capture(s, pattern, Some(g)) => {
+                s.visit(f);
+                if let Pattern::Dynamic(p) = pattern {
+                    p.visit(f);
+                }
+                g.visit(f);
+            }
+        }
+    }
+
+    /// Visit all identifiers referenced by this expression.
+    fn identifiers<'a>(&'a self, f: &mut impl FnMut(&'a str, usize)) {
+        self.visit(&mut |e| {
+            if let Expr::Ident(id, offset) = e {
+                f(id, *offset)
+            }
+        });
+    }
+}
+
+/// An error in the source of an expression, pointing at the offending column.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExpressionError {
+    message: String,
+    source: String,
+    offset: usize,
+}
+
+impl ExpressionError {
+    fn new(source: &str, offset: usize, message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            source: source.to_string(),
+            offset,
+        }
+    }
+}
+
+impl std::fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let offset = self.offset.min(self.source.len());
+        let start = self.source[..offset]
+            .rfind('\n')
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let end = self.source[offset..]
+            .find('\n')
+            .map(|i| i + offset)
+            .unwrap_or(self.source.len());
+        let line = self.source[..start].matches('\n').count() + 1;
+        let column = self.source[start..offset].chars().count() + 1;
+        if line == 1 && end == self.source.len() {
+            writeln!(f, "{} at column {}:", self.message, column)?;
+        } else {
+            writeln!(f, "{} at line {}, column {}:", self.message, line, column)?;
+        }
+        writeln!(f, "    {}", &self.source[start..end])?;
+        write!(f, "    {}^", " ".repeat(column - 1))
+    }
+}
+
+impl std::error::Error for ExpressionError {}
+
+/// An expression that has been parsed once and may be evaluated many times.
+///
+/// Serialized as its source text.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Expression {
+    /// Shared so that monitors can cheaply hand expressions to each run.
+    compiled: Arc<(String, Expr)>,
+}
+
+impl Expression {
+    pub fn parse(source: &str) -> std::result::Result<Self, ExpressionError> {
+        let expr = expression::expression(source).map_err(|e| {
+            ExpressionError::new(
+                source,
+                e.location.offset,
+                format!("expected {}", e.expected),
+            )
+        })?;
+        Ok(Self {
+            compiled: Arc::new((source.to_string(), expr)),
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.compiled.0
+    }
+
+    pub fn evaluate(&self, ctx: &impl ExpressionContext) -> Result {
+        self.compiled.1.evaluate(ctx)
+    }
+
+    /// Evaluate as a condition, logging and treating failures as false.
+    pub fn evaluate_bool(&self, ctx: &impl ExpressionContext) -> bool {
+        match self.evaluate(ctx) {
+            Ok(value) => value.as_bool(),
+            Err(e) => {
+                log::warn!("Failed to evaluate expression {:?}: {:?}", self.source(), e);
+                false
+            }
+        }
+    }
+
+    /// Ensure that this expression only references the given identifiers.
+    pub fn validate_identifiers(&self, known: &[&str]) -> std::result::Result<(), ExpressionError> {
+        let mut result = Ok(());
+        self.compiled.1.identifiers(&mut |id, offset| {
+            if result.is_ok() && !known.contains(&id) {
+                result = Err(ExpressionError::new(
+                    self.source(),
+                    offset,
+                    format!("unknown identifier '{id}'"),
+                ));
+            }
+        });
+        result
+    }
+
+    /// The IDs of the monitors whose status this expression reads, and their offsets in the source.
+    pub fn monitors(&self) -> Vec<(&str, usize)> {
+        let mut monitors = vec![];
+        self.compiled.1.visit(&mut |e| {
+            if let Expr::Status(id, offset) = e {
+                monitors.push((id.as_str(), *offset));
+            }
+        });
+        monitors
+    }
+}
+
+/// Ensure that each named expression only references the given identifiers.
+pub fn validate_identifiers(
+    expressions: &[(&str, &Expression)],
+    known: &[&str],
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    for (name, expression) in expressions {
+        expression
+            .validate_identifiers(known)
+            .map_err(|e| format!("Invalid {name} expression: {e}"))?;
+    }
+    Ok(())
+}
+
+impl std::str::FromStr for Expression {
+    type Err = ExpressionError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl TryFrom<String> for Expression {
+    type Error = ExpressionError;
+
+    fn try_from(s: String) -> std::result::Result<Self, Self::Error> {
+        Self::parse(&s)
+    }
+}
+
+impl From<Expression> for String {
+    fn from(expression: Expression) -> Self {
+        expression.source().to_string()
+    }
+}
+
+peg::parser!( grammar expression() for str {
     rule number() -> i64
-        = n:$(['0'..='9']+) { n.parse().unwrap() }
+        = n:quiet!{$(['0'..='9']+)} {? n.parse().or(Err("a number that fits in 64 bits")) }
+        / expected!("a number")
+
+    rule float() -> f64
+        = n:quiet!{$(['0'..='9']+ "." ['0'..='9']+)} { n.parse().unwrap() }
+
+    /// Durations are converted to seconds, and sizes to bytes.
+    rule unit() -> f64
+        = u:quiet!{$(("ns" / "us" / "ms" / "s" / "m" / "h" / "d" / "w"
+            / "B" / "KB" / "MB" / "GB" / "TB" / "PB" / "KiB" / "MiB" / "GiB" / "TiB" / "PiB")
+            !ident_char())}
+        {
+            match u {
+                "ns" => 1e-9,
+                "us" => 1e-6,
+                "ms" => 1e-3,
+                "s" => 1.0,
+                "m" => 60.0,
+                "h" => 3600.0,
+                "d" => 86400.0,
+                "w" => 604800.0,
+                "B" => 1.0,
+                "KB" => 1e3,
+                "MB" => 1e6,
+                "GB" => 1e9,
+                "TB" => 1e12,
+                "PB" => 1e15,
+                "KiB" => 1024.0,
+                "MiB" => 1024.0 * 1024.0,
+                "GiB" => 1024.0 * 1024.0 * 1024.0,
+                "TiB" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+                "PiB" => 1024.0 * 1024.0 * 1024.0 * 1024.0 * 1024.0,
+                _ => unreachable!(),
+            }
+        }
+
+    rule args() -> Vec<Expr>
+        = args:(expr() ++ (ws() "," ws())) { args }
 
     rule string() -> String
-        = r#"""# s:$( ( r#"\""# / r#"\\"# / r#"\'"# / (!r#"""# [_]) )* ) r#"""# { unescape_string(s) }
-        / r#"'"# s:$( ( r#"\""# / r#"\\"# / r#"\'"# / (!r#"'"# [_]) )* ) r#"'"# { unescape_string(s) }
+        = quiet!{
+            r#"""# s:$( ( r#"\""# / r#"\\"# / r#"\'"# / (!r#"""# [_]) )* ) r#"""# { unescape_string(s) }
+            / r#"'"# s:$( ( r#"\""# / r#"\\"# / r#"\'"# / (!r#"'"# [_]) )* ) r#"'"# { unescape_string(s) }
+        }
+        / expected!("a string")
 
     rule ident() -> String
-        = i:$([ 'a'..='z' | 'A'..='Z' | '_' ][ 'a'..='z' | 'A'..='Z' | '0'..='9' | '_' ]*) { i.to_string() }
+        = quiet!{i:$([ 'a'..='z' | 'A'..='Z' | '_' ] ident_char()*) { i.to_string() }}
+        / expected!("an identifier")
+
+    /// A character that may continue an identifier. Keywords and units must not be followed by one, so that identifiers
+    /// that start with them (eg: `true_count` or `index`) aren't split.
+    rule ident_char() = [ 'a'..='z' | 'A'..='Z' | '0'..='9' | '_' ]
 
     rule ws() = quiet!{[ ' ' | '\t' | '\r' | '\n' ]*}
 
-    pub rule calculate(ctx: &impl ExpressionContext) -> Result
-        = ws() v:expr(ctx) ws() { v }
+    /// A call to a built-in function with a fixed number of arguments.
+    rule call(name: &'static str, function: Function, count: usize) -> Expr
+        = ws() ##parse_string_literal(name) ws() "(" ws() a:args() ws() ")" {?
+            if a.len() == count { Ok(Expr::Call(function, a)) } else { Err("a different number of arguments") }
+        }
+
+    rule round() -> Expr
+        = ws() "round" ws() "(" ws() a:args() ws() ")" {?
+            if (1..=2).contains(&a.len()) { Ok(Expr::Call(Function::Round, a)) } else { Err("a different number of arguments") }
+        }
 
-    rule expr(ctx: &impl ExpressionContext) -> Result = precedence!{
+    rule matches() -> Expr
+        = ws() "matches" ws() "(" ws() a:expr() ws() "," ws() b:expr() ws() ")" {?
+            Ok(Expr::Matches(Box::new(a), Pattern::new(b)?))
+        }
+
+    /// Monitor IDs must be literals so that dependencies between monitors are known up front.
+    rule status() -> Expr
+        = ws() "status" ws() "(" ws() p:position!() id:string() ws() ")" { Expr::Status(id, p) }
+
+    rule capture() -> Expr
+        = ws() "capture" ws() "(" ws() a:expr() ws() "," ws() b:expr() g:(ws() "," ws() g:expr() { g })? ws() ")" {?
+            Ok(Expr::Capture(Box::new(a), Pattern::new(b)?, g.map(Box::new)))
+        }
+
+    pub rule expression() -> Expr
+        = ws() v:expr() ws() { v }
+
+    /// Python-like conditional expression: `a if condition else b`
+    rule expr() -> Expr
+        = a:logic() t:(ws() "if" !ident_char() ws() c:logic() ws() "else" !ident_char() ws() b:expr() { (c, b) })? {
+            match t {
+                Some((c, b)) => Expr::Conditional(Box::new(a), Box::new(c), Box::new(b)),
+                None => a,
+            }
+        }
+
+    rule logic() -> Expr = precedence!{
         // Python-like precedence (low -> high): or, and, not, comparisons, +-, */, ^, atoms
-        x:(@) ws() "or" ws() y:@ { Ok(x?.logical_or(y?)) }
-        x:(@) ws() "and" ws() y:@ { Ok(x?.logical_and(y?)) }
-              ws() "not" ws() v:@ { Ok(v?.logical_not()) }
+        x:(@) ws() "or" !ident_char() ws() y:@ { Expr::binary(BinaryOp::Or, x, y) }
+        x:(@) ws() "and" !ident_char() ws() y:@ { Expr::binary(BinaryOp::And, x, y) }
+              ws() "not" !ident_char() ws() v:@ { Expr::Not(Box::new(v)) }
         --
-        x:(@) ws() ">=" ws() y:@ { Ok(Value::from_bool(x?.cmp(y?) >= Ordering::Equal)) }
-        x:(@) ws() "<=" ws() y:@ { Ok(Value::from_bool(x?.cmp(y?) <= Ordering::Equal)) }
-        x:(@) ws() "==" ws() y:@ { Ok(Value::from_bool(x?.cmp(y?) == Ordering::Equal)) }
-        x:(@) ws() "!=" ws() y:@ { Ok(Value::from_bool(x?.cmp(y?) != Ordering::Equal)) }
-        x:(@) ws() ">" ws() y:@ { Ok(Value::from_bool(x?.cmp(y?) == Ordering::Greater)) }
-        x:(@) ws() "<" ws() y:@ { Ok(Value::from_bool(x?.cmp(y?) == Ordering::Less)) }
+        x:(@) ws() ">=" ws() y:@ { Expr::binary(BinaryOp::Ge, x, y) }
+        x:(@) ws() "<=" ws() y:@ { Expr::binary(BinaryOp::Le, x, y) }
+        x:(@) ws() "==" ws() y:@ { Expr::binary(BinaryOp::Eq, x, y) }
+        x:(@) ws() "!=" ws() y:@ { Expr::binary(BinaryOp::Ne, x, y) }
+        x:(@) ws() ">" ws() y:@ { Expr::binary(BinaryOp::Gt, x, y) }
+        x:(@) ws() "<" ws() y:@ { Expr::binary(BinaryOp::Lt, x, y) }
+        x:(@) ws() "not" !ident_char() ws() "in" !ident_char() ws() y:@ { Expr::binary(BinaryOp::NotIn, x, y) }
+        x:(@) ws() "in" !ident_char() ws() y:@ { Expr::binary(BinaryOp::In, x, y) }
         --
-        x:(@) ws() "+" ws() y:@ { Ok(x?.add(y?)) }
-        x:(@) ws() "-" ws() y:@ { Ok(x?.sub(y?)) }
-              ws() "-" ws() v:@ { Ok(v?.negate()) }
+        x:(@) ws() "+" ws() y:@ { Expr::binary(BinaryOp::Add, x, y) }
+        x:(@) ws() "-" ws() y:@ { Expr::binary(BinaryOp::Sub, x, y) }
+              ws() "-" ws() v:@ { Expr::Negate(Box::new(v)) }
         --
-        x:(@) ws() "*" ws() y:@ { Ok(x?.mul(y?)) }
-        x:(@) ws() "/" ws() y:@ { x?.div(y?) }
+        x:(@) ws() "*" ws() y:@ { Expr::binary(BinaryOp::Mul, x, y) }
+        x:(@) ws() "/" ws() y:@ { Expr::binary(BinaryOp::Div, x, y) }
         --
-        x:@   ws() "^" ws() y:(@) { x?.pow_val(y?) }
+        x:@   ws() "^" ws() y:(@) { Expr::binary(BinaryOp::Pow, x, y) }
         --
-        ws() "str" ws() "(" ws() v:expr(ctx) ws() ")" { Ok(Value::Str(v?.as_str())) }
-        ws() "int" ws() "(" ws() v:expr(ctx) ws() ")" { Ok(Value::Int(v?.as_int())) }
-        ws() "(" ws() v:expr(ctx) ws() ")" { Ok(v?) }
-        ws() s:string() { Ok(Value::Str(s.into())) }
-        ws() n:number() { Ok(Value::Int(n)) }
-        ws() "startswith" ws() "(" ws() a:expr(ctx) ws() "," ws() b:expr(ctx) ws() ")" {
-            let s1 = a?.as_str();
-            let s2 = b?.as_str();
-            Ok(Value::from_bool(s1.starts_with(&*s2)))
-        }
-        ws() "endswith" ws() "(" ws() a:expr(ctx) ws() "," ws() b:expr(ctx) ws() ")" {
-            let s1 = a?.as_str();
-            let s2 = b?.as_str();
-            Ok(Value::from_bool(s1.ends_with(&*s2)))
-        }
-        ws() "contains" ws() "(" ws() a:expr(ctx) ws() "," ws() b:expr(ctx) ws() ")" {
-            let s1 = a?.as_str();
-            let s2 = b?.as_str();
-            Ok(Value::from_bool(s1.contains(&*s2)))
-        }
-        ws() "length" ws() "(" ws() v:expr(ctx) ws() ")" {
-            let s = v?.as_str();
-            Ok(Value::Int(s.len() as i64))
-        }
-        ws() "true" { Ok(Value::from_bool(true)) }
-        ws() "false" { Ok(Value::from_bool(false)) }
-        ws() id:ident() { match ctx.get(&id) { Some(v) => Ok(v.clone()), None => Err(Error(format!("unknown identifier: {}", id))) } }
+        c:call("str", Function::Str, 1) { c }
+        c:call("int", Function::Int, 1) { c }
+        c:call("float", Function::Float, 1) { c }
+        r:round() { r }
+        c:call("floor", Function::Floor, 1) { c }
+        c:call("ceil", Function::Ceil, 1) { c }
+        c:call("abs", Function::Abs, 1) { c }
+        ws() "min" ws() "(" ws() a:args() ws() ")" { Expr::Call(Function::Min, a) }
+        ws() "max" ws() "(" ws() a:args() ws() ")" { Expr::Call(Function::Max, a) }
+        ws() "(" ws() v:expr() ws() ")" { v }
+        ws() s:string() { Expr::Literal(Value::Str(s.into())) }
+        ws() n:float() u:unit()? { Expr::Literal(match u { Some(u) => unit_value(n, u), None => Value::Float(n) }) }
+        ws() n:number() u:unit()? { Expr::Literal(match u { Some(u) => unit_value(n as f64, u), None => Value::Int(n) }) }
+        c:call("startswith", Function::StartsWith, 2) { c }
+        c:call("endswith", Function::EndsWith, 2) { c }
+        c:call("contains", Function::Contains, 2) { c }
+        c:call("length", Function::Length, 1) { c }
+        m:matches() { m }
+        c:capture() { c }
+        s:status() { s }
+        c:call("lower", Function::Lower, 1) { c }
+        c:call("upper", Function::Upper, 1) { c }
+        c:call("replace", Function::Replace, 3) { c }
+        c:call("split", Function::Split, 2) { c }
+        ws() "[" ws() a:args()? ws() "]" { Expr::List(a.unwrap_or_default()) }
+        ws() "true" !ident_char() { Expr::Literal(Value::from_bool(true)) }
+        ws() "false" !ident_char() { Expr::Literal(Value::from_bool(false)) }
+        ws() p:position!() id:ident() { Expr::Ident(id, p) }
     }
 });
 
@@ -220,6 +879,13 @@ peg::parser!( pub grammar expression() for str {
 mod tests {
     use super::*;
 
+    fn calculate(
+        expr: &str,
+        ctx: &impl ExpressionContext,
+    ) -> std::result::Result<Result, ExpressionError> {
+        Ok(Expression::parse(expr)?.evaluate(ctx))
+    }
+
     #[test]
     fn test_expression_with_context() {
         let mut ctx: HashMap<String, Value> = HashMap::new();
@@ -228,17 +894,15 @@ mod tests {
         ctx.insert("s".into(), Value::Str("x".into()));
 
         // 2 + 3 == 5 => 1
-        let v = expression::calculate("a + b == 5", &ctx).unwrap().unwrap();
+        let v = calculate("a + b == 5", &ctx).unwrap().unwrap();
         assert_eq!(v, Value::Int(1));
 
         // logical and/or and prefix not (Python-like truthiness)
-        let v = expression::calculate("(a and b) or not 0", &ctx)
-            .unwrap()
-            .unwrap();
+        let v = calculate("(a and b) or not 0", &ctx).unwrap().unwrap();
         assert_eq!(v, Value::Int(1));
 
         // string concat
-        let v = expression::calculate("s + \"y\"", &ctx).unwrap().unwrap();
+        let v = calculate("s + \"y\"", &ctx).unwrap().unwrap();
         assert_eq!(v, Value::Str("xy".into()));
     }
 
@@ -250,23 +914,21 @@ mod tests {
         ctx.insert("c".into(), Value::Int(3));
 
         // and/or lower precedence than comparisons
-        let v = expression::calculate("a == 1 and b == 2 or c == 0", &ctx)
+        let v = calculate("a == 1 and b == 2 or c == 0", &ctx)
             .unwrap()
             .unwrap();
         assert_eq!(v, Value::Int(1)); // (a==1 and b==2) or (c==0)
 
-        let v = expression::calculate("a == 0 and b == 2 or c == 3", &ctx)
+        let v = calculate("a == 0 and b == 2 or c == 3", &ctx)
             .unwrap()
             .unwrap();
         assert_eq!(v, Value::Int(1)); // (a==0 and b==2) or (c==3)
 
         // not binds tighter than and/or but looser than comparisons
-        let v = expression::calculate("not a == 1", &ctx).unwrap().unwrap();
+        let v = calculate("not a == 1", &ctx).unwrap().unwrap();
         assert_eq!(v, Value::Int(0)); // not (a==1)
 
-        let v = expression::calculate("not a == 0 and b == 2", &ctx)
-            .unwrap()
-            .unwrap();
+        let v = calculate("not a == 0 and b == 2", &ctx).unwrap().unwrap();
         assert_eq!(v, Value::Int(1)); // (not (a==0)) and (b==2)
     }
 
@@ -276,15 +938,13 @@ mod tests {
         ctx.insert("n".into(), Value::Int(42));
         ctx.insert("t".into(), Value::Str("7".into()));
 
-        let v = expression::calculate("str(n)", &ctx).unwrap().unwrap();
+        let v = calculate("str(n)", &ctx).unwrap().unwrap();
         assert_eq!(v, Value::Str("42".into()));
 
-        let v = expression::calculate("int(t) + 1", &ctx).unwrap().unwrap();
+        let v = calculate("int(t) + 1", &ctx).unwrap().unwrap();
         assert_eq!(v, Value::Int(8));
 
-        let v = expression::calculate("str( int(\"5\") + 1 )", &ctx)
-            .unwrap()
-            .unwrap();
+        let v = calculate("str( int(\"5\") + 1 )", &ctx).unwrap().unwrap();
         assert_eq!(v, Value::Str("6".into()));
     }
 
@@ -292,21 +952,19 @@ mod tests {
     fn test_string_quotes_and_escapes() {
         let ctx: HashMap<String, Value> = HashMap::new();
 
-        let v = expression::calculate(r#"'a' + "b""#, &ctx)
-            .unwrap()
-            .unwrap();
+        let v = calculate(r#"'a' + "b""#, &ctx).unwrap().unwrap();
         assert_eq!(v, Value::Str("ab".into()));
 
-        let v = expression::calculate(r#" '\"' "#, &ctx).unwrap().unwrap();
+        let v = calculate(r#" '\"' "#, &ctx).unwrap().unwrap();
         assert_eq!(v, Value::Str(Cow::Borrowed("\"")));
 
-        let v = expression::calculate(r#" '\'' "#, &ctx).unwrap().unwrap();
+        let v = calculate(r#" '\'' "#, &ctx).unwrap().unwrap();
         assert_eq!(v, Value::Str("'".into()));
 
-        let v = expression::calculate(r#" '\\' "#, &ctx).unwrap().unwrap();
+        let v = calculate(r#" '\\' "#, &ctx).unwrap().unwrap();
         assert_eq!(v, Value::Str(r"\".into()));
 
-        let v = expression::calculate(r#" "\\" "#, &ctx).unwrap().unwrap();
+        let v = calculate(r#" "\\" "#, &ctx).unwrap().unwrap();
         assert_eq!(v, Value::Str(r"\".into()));
     }
 
@@ -314,7 +972,7 @@ mod tests {
     fn test_nested_expressions() {
         let mut ctx: HashMap<String, Value> = HashMap::new();
         ctx.insert("ifDescr".into(), Value::Str("eth0".into()));
-        let v = expression::calculate(
+        let v = calculate(
             r#"
         (startswith(ifDescr, 'eth') and not contains(ifDescr, '.'))
           or contains(ifDescr, "10G Ethernet Adapter")
@@ -326,4 +984,283 @@ mod tests {
         .unwrap();
         assert_eq!(v, Value::Int(1));
     }
+
+    #[test]
+    fn test_floats() {
+        let mut ctx: HashMap<String, Value> = HashMap::new();
+        ctx.insert("load".into(), Value::Str("0.5".into()));
+        ctx.insert("pct".into(), Value::Float(99.5));
+
+        let v = calculate("load > 0.25", &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Int(1));
+        let v = calculate("load * 2", &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Float(1.0));
+        let v = calculate("pct >= 99", &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Int(1));
+        let v = calculate("1.5 + 1", &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Float(2.5));
+        let v = calculate("-1.5", &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Float(-1.5));
+        let v = calculate("2 ^ -1", &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Float(0.5));
+        let v = calculate("str(pct)", &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Str("99.5".into()));
+
+        // Integers keep integer division, floats do not
+        let v = calculate("7 / 2", &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Int(3));
+        let v = calculate("7 / 2.0", &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Float(3.5));
+        assert!(calculate("1.0 / 0", &ctx).unwrap().is_err());
+
+        // Existing coercions still truncate
+        let v = calculate("int(load)", &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Int(0));
+        let v = calculate("float('2.25')", &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Float(2.25));
+    }
+
+    #[test]
+    fn test_units() {
+        let mut ctx: HashMap<String, Value> = HashMap::new();
+        ctx.insert("age_seconds".into(), Value::Int(100_000));
+        ctx.insert("size".into(), Value::Int(11 * 1024 * 1024 * 1024));
+
+        let v = calculate("age_seconds > 26h", &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Int(1));
+        let v = calculate("size > 10GiB", &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Int(1));
+        let v = calculate("250ms", &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Float(0.25));
+        let v = calculate("2h", &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Int(7200));
+        let v = calculate("1.5KiB", &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Int(1536));
+        let v = calculate("10GB", &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Int(10_000_000_000));
+        assert!(calculate("10parsecs", &ctx).is_err());
+    }
+
+    #[test]
+    fn test_numeric_functions() {
+        let ctx: HashMap<String, Value> = HashMap::new();
+
+        let v = calculate("round(2.5)", &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Int(3));
+        let v = calculate("round(2.71828, 2)", &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Float(2.72));
+        let v = calculate("floor(2.7) + ceil(2.1)", &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Int(5));
+        let v = calculate("abs(-3) + abs(-0.5)", &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Float(3.5));
+        let v = calculate("min(3, 1.5, 2)", &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Float(1.5));
+        let v = calculate("max(3, 1.5, '4')", &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Str("4".into()));
+    }
+
+    #[test]
+    fn test_regex_and_strings() {
+        let mut ctx: HashMap<String, Value> = HashMap::new();
+        ctx.insert("ifDescr".into(), Value::Str("ge-0/0/1.100".into()));
+
+        let v = calculate(r#"matches(ifDescr, '^(eth|ge-)')"#, &ctx)
+            .unwrap()
+            .unwrap();
+        assert_eq!(v, Value::Int(1));
+        let v = calculate(r#"capture(ifDescr, '\\.(\\d+)$')"#, &ctx)
+            .unwrap()
+            .unwrap();
+        assert_eq!(v, Value::Str("100".into()));
+        let v = calculate(
+            r#"capture(ifDescr, '^(?P<kind>[a-z]+)-(?P<slot>\\d)', 'slot')"#,
+            &ctx,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(v, Value::Str("0".into()));
+        let v = calculate(r#"capture(ifDescr, 'xyz')"#, &ctx)
+            .unwrap()
+            .unwrap();
+        assert_eq!(v, Value::Str("".into()));
+        assert!(calculate(r#"matches(ifDescr, '(')"#, &ctx).is_err());
+
+        let v = calculate(r#"upper(lower('AbC'))"#, &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Str("ABC".into()));
+        let v = calculate(r#"replace(ifDescr, '/', '_')"#, &ctx)
+            .unwrap()
+            .unwrap();
+        assert_eq!(v, Value::Str("ge-0_0_1.100".into()));
+        let v = calculate(r#"split(ifDescr, '/')"#, &ctx).unwrap().unwrap();
+        assert_eq!(
+            v,
+            Value::List(vec![
+                Value::Str("ge-0".into()),
+                Value::Str("0".into()),
+                Value::Str("1.100".into())
+            ])
+        );
+        let v = calculate(r#"length(split(ifDescr, '/'))"#, &ctx)
+            .unwrap()
+            .unwrap();
+        assert_eq!(v, Value::Int(3));
+    }
+
+    #[test]
+    fn test_lists_and_membership() {
+        let mut ctx: HashMap<String, Value> = HashMap::new();
+        ctx.insert("ifType".into(), Value::Str("ieee8023adLag".into()));
+        ctx.insert("ifDescr".into(), Value::Str("eth0".into()));
+        ctx.insert("vlan".into(), Value::Str("20".into()));
+
+        let v = calculate(
+            r#"matches(ifDescr, '^(eth|ge-)') and ifType in ['ethernetCsmacd', 'ieee8023adLag']"#,
+            &ctx,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(v, Value::Int(1));
+        let v = calculate(r#"ifType not in ['ethernetCsmacd']"#, &ctx)
+            .unwrap()
+            .unwrap();
+        assert_eq!(v, Value::Int(1));
+        let v = calculate(r#"vlan in [10, 20, 30]"#, &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Int(1));
+        let v = calculate(r#"'th' in ifDescr"#, &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Int(1));
+        let v = calculate(r#"[]"#, &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::List(vec![]));
+        let v = calculate(r#"[1, 2] == [1, 2]"#, &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Int(1));
+    }
+
+    #[test]
+    fn test_keyword_prefixes() {
+        let mut ctx: HashMap<String, Value> = HashMap::new();
+        for (name, value) in [
+            ("true_count", 2),
+            ("false_x", 3),
+            ("index", 1),
+            ("notify", 0),
+            ("orange", 4),
+            ("android", 5),
+            ("ifx", 1),
+            ("elsewhere", 6),
+        ] {
+            ctx.insert(name.into(), Value::Int(value));
+        }
+
+        for (expr, expected) in [
+            ("true_count + false_x", 5),
+            ("index", 1),
+            ("index in [1, 2]", 1),
+            ("notify", 0),
+            ("not notify", 1),
+            ("true_count or orange", 1),
+            ("index and android", 1),
+            ("orange if ifx else elsewhere", 4),
+            ("index not in [2]", 1),
+        ] {
+            let v = calculate(expr, &ctx).unwrap().unwrap();
+            assert_eq!(v, Value::Int(expected), "{expr}");
+        }
+    }
+
+    #[test]
+    fn test_conditional() {
+        let mut ctx: HashMap<String, Value> = HashMap::new();
+        ctx.insert("a".into(), Value::Int(0));
+
+        let v = calculate("'yes' if a == 0 else 'no'", &ctx)
+            .unwrap()
+            .unwrap();
+        assert_eq!(v, Value::Str("yes".into()));
+        let v = calculate("1 if a else 2 if a == 1 else 3", &ctx)
+            .unwrap()
+            .unwrap();
+        assert_eq!(v, Value::Int(3));
+        // The branch that isn't taken may fail
+        let v = calculate("10 / a if a != 0 else -1", &ctx)
+            .unwrap()
+            .unwrap();
+        assert_eq!(v, Value::Int(-1));
+        let v = calculate("(1 if a else 2) + 1", &ctx).unwrap().unwrap();
+        assert_eq!(v, Value::Int(3));
+    }
+
+    #[test]
+    fn test_error_positions() {
+        let error = Expression::parse("a == 1 and (b > 2").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "expected one of \"!=\", \")\", \"*\", \"+\", \"-\", \"/\", \"<\", \"<=\", \"==\", \">\", \">=\", \"^\", \"and\", \"if\", \"in\", \"not\", \"or\" at column 18:\n    a == 1 and (b > 2\n                     ^"
+        );
+
+        let error = Expression::parse("round(1, 2, 3)").unwrap_err();
+        assert!(error.to_string().starts_with("expected"), "{error}");
+        assert!(Expression::parse("matches(s, '(')").is_err());
+        assert!(Expression::parse("matches(s, pattern)").is_ok());
+
+        let expression = Expression::parse("count > 0\nand age < 1h").unwrap();
+        assert!(expression.validate_identifiers(&["count", "age"]).is_ok());
+        let error = expression.validate_identifiers(&["count"]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "unknown identifier 'age' at line 2, column 5:\n    and age < 1h\n        ^"
+        );
+    }
+
+    #[test]
+    fn test_compiled_expression() {
+        let expression: Expression = "x * 2 if matches(s, '^a') else -1".parse().unwrap();
+        let mut ctx: HashMap<String, Value> = HashMap::new();
+        for (x, s, expected) in [(1, "abc", 2), (2, "abc", 4), (3, "xyz", -1)] {
+            ctx.insert("x".into(), Value::Int(x));
+            ctx.insert("s".into(), Value::Str(s.into()));
+            assert_eq!(expression.evaluate(&ctx), Ok(Value::Int(expected)));
+        }
+        assert!(!expression.evaluate_bool(&HashMap::new()));
+
+        // Serialized as the source text
+        let json = serde_json::to_string(&expression).unwrap();
+        assert_eq!(json, r#""x * 2 if matches(s, '^a') else -1""#);
+        let expression: Expression = serde_json::from_str(&json).unwrap();
+        assert_eq!(expression.source(), "x * 2 if matches(s, '^a') else -1");
+        assert!(serde_json::from_str::<Expression>(r#""x *""#).is_err());
+    }
+
+    #[test]
+    fn test_monitor_status() {
+        struct Monitors;
+        impl ExpressionContext for Monitors {
+            fn get(&self, _key: &str) -> Option<&Value> {
+                None
+            }
+
+            fn status(&self, id: &str) -> Option<Value> {
+                match id {
+                    "wan1" => Some(Value::Str("red".into())),
+                    "wan2" => Some(Value::Str("green".into())),
+                    _ => None,
+                }
+            }
+        }
+
+        let expression: Expression = "status('wan1') == 'green' or status(\"wan2\") == 'green'"
+            .parse()
+            .unwrap();
+        assert_eq!(expression.monitors(), vec![("wan1", 7), ("wan2", 36)]);
+        assert!(expression.evaluate_bool(&Monitors));
+        assert!(calculate("status('lan') == 'red'", &Monitors)
+            .unwrap()
+            .is_err());
+        assert!(!expression.evaluate_bool(&HashMap::new()));
+
+        // The monitor ID must be a literal
+        assert!(Expression::parse("status(id)").is_err());
+        // But `status` is still a valid identifier
+        let mut ctx: HashMap<String, Value> = HashMap::new();
+        ctx.insert("status".into(), Value::Str("red".into()));
+        assert_eq!(calculate("status == 'red'", &ctx), Ok(Ok(Value::Int(1))));
+    }
 }
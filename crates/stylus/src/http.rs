@@ -1,24 +1,37 @@
 use std::collections::hash_map::DefaultHasher;
+use std::error::Error;
 use std::hash::{Hash, Hasher};
 use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
 use std::sync::Arc;
 
 use axum::{
-    extract::{Path, State},
+    body::Body,
+    extract::{rejection::QueryRejection, Path, Query, State},
     http::{HeaderMap, HeaderValue, StatusCode},
     response::{IntoResponse, Json},
-    routing::get,
+    routing::{get, post},
     Router,
 };
+use futures_util::{stream, StreamExt};
+use keepcalm::SharedMut;
+use serde::Serialize;
 use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
 
-use crate::config::Config;
+use crate::api::{self, ApiError, MonitorQuery, TriggerQuery};
+use crate::badge::render_badge;
+use crate::config::{Config, CONFIG_ERROR_EXIT};
 use crate::css::generate_css_for_state;
+use crate::export::write_snapshot;
 use crate::monitor::Monitor;
+use crate::status::{MonitorState, Status};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// How long `/api/monitors/:id/run?wait=true` waits for the run to finish.
+const TRIGGER_WAIT: std::time::Duration = std::time::Duration::from_secs(300);
+
 #[derive(Clone)]
 struct AppState {
     monitor: Arc<Monitor>,
@@ -52,6 +65,45 @@ async fn config_request(State(state): State<AppState>) -> impl IntoResponse {
     Json(config)
 }
 
+async fn ingest_request(
+    State(state): State<AppState>,
+    Path(agent): Path<String>,
+    headers: HeaderMap,
+    body: String,
+) -> impl IntoResponse {
+    let Some(ingest) = &state.config.server.ingest else {
+        return (StatusCode::NOT_FOUND, "Not found".to_string());
+    };
+    if !authorized(&headers, &ingest.token) {
+        return (StatusCode::UNAUTHORIZED, "Unauthorized".to_string());
+    }
+    let remote = match serde_json::from_str(&body) {
+        Ok(remote) => remote,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("Invalid status: {e}")),
+    };
+    match state.monitor.ingest(&agent, remote) {
+        Ok(()) => (StatusCode::NO_CONTENT, String::new()),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()),
+    }
+}
+
+/// Checks that a request carries `Authorization: Bearer <token>`.
+fn authorized(headers: &HeaderMap, token: &str) -> bool {
+    let authorization = headers
+        .get("authorization")
+        .and_then(|value| value.to_str().ok());
+    let expected = format!("Bearer {token}");
+    authorization.is_some_and(|authorization| {
+        constant_time_eq(authorization.as_bytes(), expected.as_bytes())
+    })
+}
+
+/// Compares two secrets in a time that only depends on their lengths, so that timing doesn't reveal how much of a
+/// guessed token is right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
 async fn log_request(
     State(state): State<AppState>,
     Path(monitor_id): Path<String>,
@@ -74,43 +126,191 @@ async fn log_request(
     )
 }
 
-async fn default_index(state: AppState) -> impl IntoResponse {
-    use crate::status::MonitorState;
-    use handlebars::Handlebars;
-    use keepcalm::SharedMut;
-    use serde::Serialize;
-    use std::convert::identity;
+async fn log_stream_request(
+    State(state): State<AppState>,
+    Path(monitor_id): Path<String>,
+) -> impl IntoResponse {
+    let Some((logged, receiver)) = state.monitor.follow_log(&monitor_id) else {
+        return not_found();
+    };
+    // Monitors that don't run here end the stream with their current log
+    let followed = stream::unfold(receiver, |receiver| async move {
+        let mut receiver = receiver?;
+        match receiver.recv().await {
+            Ok(line) => Some((line, Some(receiver))),
+            Err(RecvError::Lagged(skipped)) => {
+                Some((format!("[{skipped} lines skipped]"), Some(receiver)))
+            }
+            Err(RecvError::Closed) => None,
+        }
+    });
+    let lines = stream::iter(logged)
+        .chain(followed)
+        .map(|line| Ok::<_, std::convert::Infallible>(line + "\n"));
+    (
+        StatusCode::OK,
+        [
+            ("Content-Type", "text/plain; charset=utf-8"),
+            ("Cache-Control", "no-cache"),
+            // Ask reverse proxies to pass each line through as it arrives
+            ("X-Accel-Buffering", "no"),
+        ],
+        Body::from_stream(lines),
+    )
+        .into_response()
+}
 
-    let mut handlebars = Handlebars::new();
-    if let Err(e) = handlebars.register_template_string("t", include_str!("./index.html")) {
-        return (
+async fn badge_request(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Path(badge): Path<String>,
+) -> impl IntoResponse {
+    let Some(monitor_id) = badge.strip_suffix(".svg") else {
+        return not_found();
+    };
+    for monitor in state.monitor.status().monitors {
+        let monitor = monitor.read();
+        if monitor.id == monitor_id {
+            let badge = render_badge(&monitor.id, &monitor.status);
+            return handle_dynamic_content_with_etag(
+                headers,
+                "image/svg+xml; charset=utf-8",
+                badge,
+            );
+        }
+    }
+    not_found()
+}
+
+async fn child_badge_request(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Path((monitor_id, badge)): Path<(String, String)>,
+) -> impl IntoResponse {
+    let Some(child_id) = badge.strip_suffix(".svg") else {
+        return not_found();
+    };
+    for monitor in state.monitor.status().monitors {
+        let monitor = monitor.read();
+        if monitor.id == monitor_id {
+            if let Some(child) = monitor.children.get(child_id) {
+                let badge = render_badge(child_id, &child.status);
+                return handle_dynamic_content_with_etag(
+                    headers,
+                    "image/svg+xml; charset=utf-8",
+                    badge,
+                );
+            }
+        }
+    }
+    not_found()
+}
+
+async fn api_monitors_request(
+    State(state): State<AppState>,
+    query: Result<Query<MonitorQuery>, QueryRejection>,
+) -> impl IntoResponse {
+    let Query(query) = query.map_err(|e| ApiError::bad_request(e.body_text()))?;
+    api::list_monitors(&state.monitor.status(), &query).map(Json)
+}
+
+async fn api_monitor_request(
+    State(state): State<AppState>,
+    Path(monitor_id): Path<String>,
+) -> impl IntoResponse {
+    api::get_monitor(&state.monitor.status(), &monitor_id).map(Json)
+}
+
+async fn api_child_request(
+    State(state): State<AppState>,
+    Path((monitor_id, child_id)): Path<(String, String)>,
+) -> impl IntoResponse {
+    api::get_child(&state.monitor.status(), &monitor_id, &child_id).map(Json)
+}
+
+async fn trigger_request(
+    State(state): State<AppState>,
+    Path(monitor_id): Path<String>,
+    headers: HeaderMap,
+    query: Result<Query<TriggerQuery>, QueryRejection>,
+) -> Result<axum::response::Response, ApiError> {
+    let Some(trigger) = &state.config.server.trigger else {
+        return Err(ApiError::not_found("Not found"));
+    };
+    if !authorized(&headers, &trigger.token) {
+        return Err(ApiError::unauthorized("Unauthorized"));
+    }
+    let Query(query) = query.map_err(|e| ApiError::bad_request(e.body_text()))?;
+    let wait = query.wait.then_some(TRIGGER_WAIT);
+    let id = monitor_id.clone();
+    let result = tokio::task::spawn_blocking(move || state.monitor.trigger(&id, wait))
+        .await
+        .expect("Trigger failed")?;
+    Ok(match result {
+        Some(state) => Json(state).into_response(),
+        None => (
+            StatusCode::ACCEPTED,
+            Json(serde_json::json!({ "id": monitor_id, "triggered": true })),
+        )
+            .into_response(),
+    })
+}
+
+async fn api_openapi_request() -> impl IntoResponse {
+    Json(api::openapi(VERSION))
+}
+
+async fn api_not_found() -> impl IntoResponse {
+    ApiError::not_found("Not found")
+}
+
+fn not_found() -> axum::response::Response {
+    (
+        StatusCode::NOT_FOUND,
+        [("Content-Type", "text/plain")],
+        "Not found".to_string(),
+    )
+        .into_response()
+}
+
+async fn default_index(state: AppState) -> impl IntoResponse {
+    match default_index_html(&state.monitor.status()) {
+        Ok(html) => (
+            StatusCode::OK,
+            [("Content-Type", "text/html; charset=utf-8")],
+            html,
+        ),
+        Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             [("Content-Type", "text/html")],
             e.to_string(),
-        );
+        ),
     }
+}
 
-    let mut monitors = vec![];
-    for monitor in state.monitor.status().monitors {
-        monitors.push(monitor);
-    }
+/// Renders the simple status page that is served when the built-in UI isn't available.
+pub fn default_index_html(status: &Status) -> Result<String, Box<dyn Error>> {
+    use handlebars::Handlebars;
+    use std::convert::identity;
+
+    let mut handlebars = Handlebars::new();
+    handlebars.register_template_string("t", include_str!("./index.html"))?;
 
     #[derive(Serialize)]
-    struct Model {
-        monitors: Vec<SharedMut<MonitorState>>,
+    struct Model<'a> {
+        monitors: &'a Vec<SharedMut<MonitorState>>,
     }
 
-    let html = handlebars
-        .render("t", &Model { monitors })
+    Ok(handlebars
+        .render(
+            "t",
+            &Model {
+                monitors: &status.monitors,
+            },
+        )
         .map_or_else(|e| e.to_string(), identity)
         .trim()
-        .to_owned();
-
-    (
-        StatusCode::OK,
-        [("Content-Type", "text/html; charset=utf-8")],
-        html,
-    )
+        .to_owned())
 }
 
 /// Generate an ETag from file content hash
@@ -255,6 +455,38 @@ fn handle_static_content_with_etag(
     return res.into_response();
 }
 
+/// Handle ETag cache validation for generated content, which clients must revalidate as it changes with the
+/// status of the monitors
+fn handle_dynamic_content_with_etag(
+    headers: HeaderMap,
+    content_type: &'static str,
+    content: String,
+) -> axum::response::Response {
+    let etag = generate_etag_from_string(&content);
+    if etag_matches(&headers, &etag) {
+        return (
+            StatusCode::NOT_MODIFIED,
+            [
+                ("ETag", HeaderValue::from_str(&etag).unwrap()),
+                ("Cache-Control", HeaderValue::from_static("no-cache")),
+            ],
+            "",
+        )
+            .into_response();
+    }
+
+    (
+        StatusCode::OK,
+        [
+            ("Content-Type", HeaderValue::from_static(content_type)),
+            ("Cache-Control", HeaderValue::from_static("no-cache")),
+            ("ETag", HeaderValue::from_str(&etag).unwrap()),
+        ],
+        content,
+    )
+        .into_response()
+}
+
 /// Custom static file handler with ETag support
 async fn static_files_handler(
     headers: HeaderMap,
@@ -325,7 +557,15 @@ async fn index_handler(headers: HeaderMap, State(state): State<AppState>) -> imp
 
 pub async fn run(config: Config, dry_run: bool) {
     let config = Arc::new(config);
-    let monitor = Arc::new(Monitor::new(&config).expect("Unable to create monitor"));
+    let monitor = match Monitor::new(&config) {
+        Ok(monitor) => Arc::new(monitor),
+        Err(e) => {
+            eprintln!();
+            eprintln!("Fatal error parsing monitor configuration:");
+            eprintln!("{e}");
+            std::process::exit(CONFIG_ERROR_EXIT);
+        }
+    };
     let state = AppState { monitor, config };
 
     // Build the router
@@ -334,6 +574,19 @@ pub async fn run(config: Config, dry_run: bool) {
         .route("/status.json", get(status_request))
         .route("/config.json", get(config_request))
         .route("/log/:monitor_id", get(log_request))
+        .route("/log/:monitor_id/stream", get(log_stream_request))
+        .route("/badge/:badge", get(badge_request))
+        .route("/badge/:monitor_id/:badge", get(child_badge_request))
+        .route("/ingest/:agent", post(ingest_request))
+        .route("/api/v1/openapi.json", get(api_openapi_request))
+        .route("/api/v1/monitors", get(api_monitors_request))
+        .route("/api/v1/monitors/:monitor_id", get(api_monitor_request))
+        .route(
+            "/api/v1/monitors/:monitor_id/children/:child_id",
+            get(api_child_request),
+        )
+        .route("/api/monitors/:monitor_id/run", post(trigger_request))
+        .route("/api/*path", get(api_not_found))
         .route("/", get(index_handler));
 
     #[cfg(feature = "builtin-ui")]
@@ -409,6 +662,17 @@ pub async fn run(config: Config, dry_run: bool) {
         return;
     }
 
+    if let Some(export) = state.config.server.export.clone() {
+        let monitor = state.monitor.clone();
+        let config = state.config.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(export.interval);
+            if let Err(e) = write_snapshot(&export.path, &config, &monitor.status()) {
+                error!("Failed to export to {}: {e}", export.path.display());
+            }
+        });
+    }
+
     // Run the server
     let listener = TcpListener::bind(&addr)
         .await
@@ -1,32 +1,156 @@
 use std::collections::BTreeMap;
 use std::error::Error;
+use std::sync::Arc;
 
 use handlebars::*;
 use serde::Serialize;
 use serde_json::value::*;
 
-use crate::config::{MonitorDirAxisValue, MonitorDirTestConfig};
+use crate::config::{
+    CssRule, CssRuleMatch, MonitorDirAxisValue, MonitorDirTestConfig, MONITOR_KINDS,
+};
 use crate::status::*;
 
-pub fn interpolate_monitor(
-    id: &str,
-    config: &MonitorDirTestConfig,
-    status: &MonitorStatus,
-    s: &str,
-) -> Result<String, Box<dyn Error>> {
-    // TODO: avoid creating this handlebars registry every time
-    let mut handlebars = Handlebars::new();
-    handlebars.register_template_string("t", s)?;
+/// The handlebars templates and ID patterns of the CSS rules, compiled once when the configuration is loaded.
+#[derive(Clone, Debug, Default)]
+pub struct CssTemplates {
+    handlebars: Arc<Handlebars<'static>>,
+    ids: Arc<Vec<Option<glob::Pattern>>>,
+}
+
+/// A monitor or group child that CSS rules are generated for.
+pub struct CssTarget<'a> {
+    pub id: &'a str,
+    pub kind: &'a str,
+    pub tags: &'a [String],
+    /// The axis values of a group child, or `None` for the monitor itself.
+    pub axes: Option<&'a BTreeMap<String, MonitorDirAxisValue>>,
+}
+
+impl CssTemplates {
+    pub fn compile(rules: &[CssRule]) -> Result<Self, Box<dyn Error>> {
+        let mut handlebars = Handlebars::new();
+        let mut ids = vec![];
+        for (index, rule) in rules.iter().enumerate() {
+            ids.push(Self::compile_rule(&mut handlebars, index, rule)?);
+        }
+        Ok(CssTemplates {
+            handlebars: Arc::new(handlebars),
+            ids: Arc::new(ids),
+        })
+    }
 
-    let mut map = BTreeMap::new();
-    #[derive(Clone, Debug, Serialize)]
-    struct Monitor<'a> {
-        id: &'a str,
-        config: &'a MonitorDirTestConfig,
-        status: &'a MonitorStatus,
+    /// Compiles every rule, returning all of the errors rather than stopping at the first.
+    pub fn errors(rules: &[CssRule]) -> Vec<String> {
+        let mut handlebars = Handlebars::new();
+        rules
+            .iter()
+            .enumerate()
+            .filter_map(|(index, rule)| Self::compile_rule(&mut handlebars, index, rule).err())
+            .map(|e| e.to_string())
+            .collect()
+    }
+
+    fn compile_rule(
+        handlebars: &mut Handlebars<'static>,
+        index: usize,
+        rule: &CssRule,
+    ) -> Result<Option<glob::Pattern>, Box<dyn Error>> {
+        for (part, template) in [
+            ("selectors", &rule.selectors),
+            ("declarations", &rule.declarations),
+        ] {
+            handlebars
+                .register_template_string(&format!("{index}.{part}"), template)
+                .map_err(|e| format!("Invalid {part} in CSS rule {}: {e}", index + 1))?;
+        }
+
+        let matches = rule.matches.as_ref();
+        if let Some(kind) = matches.and_then(|m| m.kind.as_ref()) {
+            if !MONITOR_KINDS.contains(&kind.as_str()) {
+                return Err(format!(
+                    "Invalid type {kind:?} in CSS rule {} (expected one of {})",
+                    index + 1,
+                    MONITOR_KINDS.join(", ")
+                )
+                .into());
+            }
+        }
+        Ok(match matches.and_then(|m| m.id.as_ref()) {
+            Some(id) => Some(glob::Pattern::new(id).map_err(|e| {
+                format!("Invalid id pattern {id:?} in CSS rule {}: {e}", index + 1)
+            })?),
+            None => None,
+        })
+    }
+
+    /// Whether a compiled CSS rule applies to a monitor or group child.
+    pub fn matches(&self, rule: usize, matches: Option<&CssRuleMatch>, target: &CssTarget) -> bool {
+        let Some(matches) = matches else {
+            return true;
+        };
+        if let Some(Some(id)) = self.ids.get(rule) {
+            if !id.matches(target.id) {
+                return false;
+            }
+        }
+        if matches
+            .kind
+            .as_ref()
+            .is_some_and(|kind| kind != target.kind)
+        {
+            return false;
+        }
+        if !matches.tags.iter().all(|tag| target.tags.contains(tag)) {
+            return false;
+        }
+        if matches
+            .child
+            .is_some_and(|child| child != target.axes.is_some())
+        {
+            return false;
+        }
+        if !matches.axes.is_empty() {
+            let Some(axes) = target.axes else {
+                return false;
+            };
+            if !matches
+                .axes
+                .iter()
+                .all(|(name, value)| axes.get(name) == Some(value))
+            {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Renders the selectors and declarations of a compiled CSS rule for a monitor or group child.
+    pub fn render_monitor(
+        &self,
+        rule: usize,
+        id: &str,
+        config: &MonitorDirTestConfig,
+        status: &MonitorStatus,
+    ) -> Result<(String, String), Box<dyn Error>> {
+        let mut map = BTreeMap::new();
+        #[derive(Clone, Debug, Serialize)]
+        struct Monitor<'a> {
+            id: &'a str,
+            config: &'a MonitorDirTestConfig,
+            status: &'a MonitorStatus,
+        }
+        map.insert("monitor", Monitor { id, config, status });
+        let render = |part: &str| {
+            Ok::<_, Box<dyn Error>>(
+                self.handlebars
+                    .render(&format!("{rule}.{part}"), &map)?
+                    .trim()
+                    .to_owned(),
+            )
+        };
+        Ok((render("selectors")?, render("declarations")?))
     }
-    map.insert("monitor", Monitor { id, config, status });
-    Ok(handlebars.render("t", &map)?.trim().to_owned())
 }
 
 pub fn interpolate_id(
@@ -53,9 +177,22 @@ pub fn interpolate_modify<'a>(
     match path.next() {
         Some("status") => {}
         Some("group") => {
-            let part = path.next().ok_or("Missing group child")?;
+            // Child ids may contain `.` (eg: an IP address), so prefer the longest known child that ends before a
+            // `.status` in the path
+            let rest = raw_path
+                .strip_prefix("group.")
+                .ok_or("Missing group child")?;
+            let part = rest
+                .rmatch_indices(".status")
+                .map(|(i, _)| &rest[..i])
+                .find(|id| children.contains_key(*id))
+                .or_else(|| rest.split('.').next().filter(|part| !part.is_empty()))
+                .ok_or("Missing group child")?
+                .to_owned();
+            path = rest[part.len()..].split('.');
+            path.next();
             status = &mut children
-                .entry(part.to_owned())
+                .entry(part.clone())
                 .or_insert_with(|| {
                     let mut status = MonitorChildStatus::default();
                     if let Some((_, index)) = part.rsplit_once('-') {
@@ -103,7 +240,6 @@ pub fn interpolate_modify<'a>(
 mod tests {
     use super::*;
     use std::iter::FromIterator;
-    use std::sync::Arc;
 
     fn update(s: &'static str) -> Result<MonitorStatus, Box<dyn Error>> {
         let mut status = Default::default();
@@ -128,6 +264,19 @@ mod tests {
         Ok(())
     }
 
+    fn interpolate_monitor(
+        config: &MonitorDirTestConfig,
+        status: &MonitorStatus,
+        s: &str,
+    ) -> Result<String, Box<dyn Error>> {
+        let templates = CssTemplates::compile(&[CssRule {
+            selectors: s.to_owned(),
+            declarations: String::new(),
+            matches: None,
+        }])?;
+        Ok(templates.render_monitor(0, "id", config, status)?.0)
+    }
+
     #[test]
     fn test_replace() -> Result<(), Box<dyn Error>> {
         let config = Default::default();
@@ -137,17 +286,53 @@ mod tests {
             "blue".to_owned(),
         )]));
         assert_eq!(
-            interpolate_monitor(
-                "id",
-                &config,
-                &status,
-                "{{monitor.status.css.metadata.color}}"
-            )?,
+            interpolate_monitor(&config, &status, "{{monitor.status.css.metadata.color}}")?,
             "blue"
         );
+        status.last_change = Some("2025-01-02T03:04:05Z".parse()?);
+        assert_eq!(
+            interpolate_monitor(&config, &status, "{{monitor.status.last_change}}")?,
+            "2025-01-02T03:04:05Z"
+        );
+        status.child_counts = Some(MonitorChildCounts {
+            children: 3,
+            children_red: 2,
+            ..Default::default()
+        });
+        assert_eq!(
+            interpolate_monitor(&config, &status, "{{monitor.status.children_red}}")?,
+            "2"
+        );
+        assert_eq!(
+            interpolate_monitor(&config, &status, "{{monitor.id}}")?,
+            "id"
+        );
         Ok(())
     }
 
+    #[test]
+    fn test_compile_error() {
+        let rule = |selectors: &str, declarations: &str| CssRule {
+            selectors: selectors.to_owned(),
+            declarations: declarations.to_owned(),
+            matches: None,
+        };
+        assert!(CssTemplates::compile(&[rule("[data-monitor-id=\"{{monitor.id}}\"]", "")]).is_ok());
+        let error = CssTemplates::compile(&[
+            rule("a", "b"),
+            rule(
+                "[data-monitor-id=\"{{monitor.id}}\"]",
+                "{{#if monitor.status}}",
+            ),
+        ])
+        .unwrap_err()
+        .to_string();
+        assert!(
+            error.starts_with("Invalid declarations in CSS rule 2:"),
+            "{error}"
+        );
+    }
+
     #[test]
     fn test_modify() -> Result<(), Box<dyn Error>> {
         let status = update("status.status=\"red\"")?;
@@ -160,4 +345,52 @@ mod tests {
         assert_eq!(status.pending.unwrap().metadata.unwrap(), map);
         Ok(())
     }
+    #[test]
+    fn test_modify_child() -> Result<(), Box<dyn Error>> {
+        let mut status = MonitorStatus::default();
+        let mut children = BTreeMap::new();
+        for id in ["10.0.0.1", "a", "a.status"] {
+            children.insert(id.to_owned(), MonitorChildStatus::default());
+        }
+        let pending = |children: &BTreeMap<String, MonitorChildStatus>, id: &str| {
+            children[id].status.pending.clone().unwrap_or_default()
+        };
+
+        // Child ids may contain `.`, even `.status`
+        interpolate_modify(
+            &mut status,
+            &mut children,
+            "group.10.0.0.1.status.status=\"red\"",
+        )?;
+        assert_eq!(
+            pending(&children, "10.0.0.1").status,
+            Some(StatusState::Red)
+        );
+        interpolate_modify(
+            &mut status,
+            &mut children,
+            "group.a.status.status.status=\"red\"",
+        )?;
+        assert_eq!(
+            pending(&children, "a.status").status,
+            Some(StatusState::Red)
+        );
+        assert_eq!(pending(&children, "a").status, None);
+        interpolate_modify(
+            &mut status,
+            &mut children,
+            "group.a.status.metadata.status=\"x\"",
+        )?;
+        assert!(pending(&children, "a").metadata.is_some());
+
+        // Unknown children are added with the first segment as their id
+        interpolate_modify(
+            &mut status,
+            &mut children,
+            "group.new.status.status=\"green\"",
+        )?;
+        assert_eq!(pending(&children, "new").status, Some(StatusState::Green));
+        assert_eq!(children.len(), 4);
+        Ok(())
+    }
 }
@@ -5,8 +5,14 @@ use env_logger::Env;
 use include_directory::{include_directory, Dir};
 use serde::Serialize;
 
+mod agent;
+mod api;
+mod badge;
+mod batch;
+mod client;
 mod config;
 mod css;
+mod export;
 mod expressions;
 mod http;
 mod interpolate;
@@ -21,10 +27,12 @@ extern crate log;
 extern crate derive_more;
 
 use crate::config::{
-    parse_config_from_args, parse_monitor_configs, Config, MonitorDirConfig, OperationMode,
+    parse_config_from_args, parse_monitor_configs, validate, Config, MonitorDirConfig,
+    MonitorDirRootConfig, OperationMode, OutputFormat, CONFIG_ERROR_EXIT,
 };
-use crate::status::MonitorState;
-use crate::worker::monitor_run;
+use crate::monitor::with_imported;
+use crate::status::{MonitorState, Status, StatusState};
+use crate::worker::{monitor_run, WorkerMessage};
 
 #[tokio::main]
 async fn main() {
@@ -47,14 +55,85 @@ async fn run() {
             eprintln!();
             eprintln!("Fatal error parsing configuration:");
             eprintln!("{e}");
-            return;
+            std::process::exit(CONFIG_ERROR_EXIT);
         }
     };
     match operation {
         OperationMode::Run(config, dry_run) => crate::http::run(config, dry_run).await,
+        OperationMode::Agent(config, agent) => {
+            tokio::task::spawn_blocking(move || crate::agent::run(config, agent))
+                .await
+                .expect("Agent failed")
+        }
+        OperationMode::Status(client) => {
+            let result = tokio::task::spawn_blocking(move || {
+                crate::client::run(&client).map_err(|e| e.to_string())
+            })
+            .await
+            .expect("Status client failed");
+            if let Err(e) = result {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
+        OperationMode::Trigger(trigger) => {
+            let result = tokio::task::spawn_blocking(move || {
+                crate::client::trigger(&trigger).map_err(|e| e.to_string())
+            })
+            .await
+            .expect("Trigger client failed");
+            match result {
+                Ok(true) => {}
+                Ok(false) => std::process::exit(1),
+                Err(e) => {
+                    eprintln!("{e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        OperationMode::Export(config, output) => {
+            let monitors = match parse_monitor_configs(&config.monitor.dir) {
+                Ok(monitors) => monitors,
+                Err(e) => {
+                    eprintln!();
+                    eprintln!("Fatal error parsing monitor configuration:");
+                    eprintln!("{e}");
+                    std::process::exit(CONFIG_ERROR_EXIT);
+                }
+            };
+            let runs: Vec<_> = (0..monitors.len())
+                .filter(|index| {
+                    !matches!(monitors[*index].root, MonitorDirRootConfig::Composite(_))
+                })
+                .collect();
+            let result = crate::batch::run_states(&config, &monitors, &runs).and_then(|runs| {
+                let status = Status {
+                    monitors: with_imported(runs.states),
+                };
+                crate::export::write_snapshot(&output, &config, &status)
+            });
+            match result {
+                Ok(()) => println!(
+                    "Exported {} monitors to {}",
+                    monitors.len(),
+                    output.display()
+                ),
+                Err(e) => {
+                    eprintln!("Failed to export: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
         OperationMode::Dump(config) => {
-            let monitors = parse_monitor_configs(&config.monitor.dir)
-                .expect("Unable to parse monitor configurations");
+            let monitors = match parse_monitor_configs(&config.monitor.dir) {
+                Ok(monitors) => monitors,
+                Err(e) => {
+                    eprintln!();
+                    eprintln!("Fatal error parsing monitor configuration:");
+                    eprintln!("{e}");
+                    std::process::exit(CONFIG_ERROR_EXIT);
+                }
+            };
 
             #[derive(Serialize)]
             struct CombinedState {
@@ -70,50 +149,123 @@ async fn run() {
                     .expect("Unable to pretty-print configuration")
             );
         }
-        OperationMode::Test(config, id) => {
-            let monitors = parse_monitor_configs(&config.monitor.dir)
-                .expect("Unable to parse monitor configurations");
-            for monitor in monitors.iter() {
-                if monitor.id == id {
-                    let mut state: MonitorState = monitor.into();
-                    println!("Monitor Log");
-                    println!("-----------");
+        OperationMode::Validate(path, json) => {
+            let report = validate(&path);
+            if json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&report).expect("Unable to pretty-print report")
+                );
+            } else {
+                for problem in &report.problems {
+                    println!("{}: {}", problem.file.display(), problem.message);
+                }
+                if report.problems.is_empty() {
+                    println!("OK: {} monitors validated", report.monitors);
+                } else {
                     println!();
-                    monitor_run(&monitor, &mut |_, msg| {
-                        state
-                            .process_message(&monitor.id, msg, &config.css.metadata, &mut |m| {
+                    println!("Found {} problem(s)", report.problems.len());
+                }
+            }
+            if !report.problems.is_empty() {
+                std::process::exit(1);
+            }
+        }
+        OperationMode::Test(config, patterns, format) => {
+            let monitors = match parse_monitor_configs(&config.monitor.dir) {
+                Ok(monitors) => monitors,
+                Err(e) => {
+                    eprintln!();
+                    eprintln!("Fatal error parsing monitor configuration:");
+                    eprintln!("{e}");
+                    std::process::exit(CONFIG_ERROR_EXIT);
+                }
+            };
+            let selected = match crate::batch::select(&monitors, &patterns) {
+                Ok(selected) => selected,
+                Err(e) => {
+                    eprintln!("{e}");
+                    std::process::exit(CONFIG_ERROR_EXIT);
+                }
+            };
+
+            // A single monitor is run in the foreground with its full output
+            let single = match selected.as_slice() {
+                [index] if format == OutputFormat::Text => match &monitors[*index].root {
+                    MonitorDirRootConfig::Composite(_) => None,
+                    _ => Some(&monitors[*index]),
+                },
+                _ => None,
+            };
+            let green = if let Some(monitor) = single {
+                let mut state: MonitorState = monitor.into();
+                println!("Monitor Log");
+                println!("-----------");
+                println!();
+                let (_, result) = monitor_run(&monitor, &Default::default(), &mut |_, msg| {
+                    state
+                        .process_message(&monitor.id, msg, &config.css.metadata, &mut |m| {
+                            println!("{}", m);
+                        })
+                        .expect("Failed to process message");
+                    Ok(())
+                });
+                if let Err(e) = result {
+                    state
+                        .process_message(
+                            &monitor.id,
+                            WorkerMessage::AbnormalTermination(e.to_string()),
+                            &config.css.metadata,
+                            &mut |m| {
                                 println!("{}", m);
-                            })
-                            .expect("Failed to process message");
-                        Ok(())
-                    })
-                    .1
-                    .expect("Failed to run the monitor");
+                            },
+                        )
+                        .expect("Failed to process message");
+                }
 
-                    println!();
-                    println!("State");
-                    println!("-----");
-                    println!();
-                    println!(
-                        "{}",
-                        serde_json::to_string_pretty(&state)
-                            .expect("Unable to pretty-print configuration")
-                    );
+                println!();
+                println!("State");
+                println!("-----");
+                println!();
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&state)
+                        .expect("Unable to pretty-print configuration")
+                );
 
-                    println!();
-                    println!("CSS");
-                    println!("---");
-                    println!();
+                println!();
+                println!("CSS");
+                println!("---");
+                println!();
 
-                    println!(
+                println!(
+                    "{}",
+                    crate::css::generate_css_for_monitor(&config.css, &state)
+                );
+                std::iter::once(&state.status)
+                    .chain(state.children.values().map(|child| &child.status))
+                    .all(|status| status.status == Some(StatusState::Green))
+            } else {
+                let results = match crate::batch::run_monitors(&config, &monitors, &selected) {
+                    Ok(results) => results,
+                    Err(e) => {
+                        eprintln!("{e}");
+                        std::process::exit(2);
+                    }
+                };
+                match format {
+                    OutputFormat::Text => crate::batch::print_table(&results),
+                    OutputFormat::Json => println!(
                         "{}",
-                        crate::css::generate_css_for_monitor(&config.css, &state)
-                    );
-                    return;
+                        serde_json::to_string_pretty(&results)
+                            .expect("Unable to pretty-print results")
+                    ),
                 }
+                results.iter().all(|result| result.is_green())
+            };
+            if !green {
+                std::process::exit(1);
             }
-
-            panic!("Unable to locate monitor with id '{}'", id)
         }
         OperationMode::Init(path, docker) => {
             println!("Initializing directory: {path:?}...");
@@ -1,11 +1,17 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::error::Error;
+use std::sync::{mpsc, Arc, Condvar, Mutex};
 use std::thread;
+use std::time::Duration;
 
 use keepcalm::SharedMut;
+use tokio::sync::broadcast;
 
 use crate::config::*;
+use crate::monitors::composite::{evaluation_order, CompositeMonitorConfig};
+use crate::monitors::remote::RemoteStatus;
 use crate::status::*;
-use crate::worker::{monitor_thread, ShuttingDown};
+use crate::worker::{monitor_thread, LogStream, ShuttingDown, WorkerMessage};
 
 #[derive(Debug)]
 struct MonitorThread {
@@ -13,46 +19,86 @@ struct MonitorThread {
     #[allow(unused)]
     drop_detect: SharedMut<()>,
     state: SharedMut<MonitorState>,
+    /// Wakes the thread for an early run, or `None` for monitors without a thread.
+    trigger: Option<mpsc::SyncSender<()>>,
+    /// Notified each time a run starts or finishes. The state itself tracks whether a run is in progress.
+    runs: Arc<(Mutex<()>, Condvar)>,
+}
+
+/// Why a monitor couldn't be run with [`Monitor::trigger`].
+#[derive(Debug, Display, Error, PartialEq)]
+pub enum TriggerError {
+    #[display("No monitor with id '{_0}'")]
+    NotFound(#[error(not(source))] String),
+    #[display("Monitor '{_0}' cannot be run on demand")]
+    NotRunnable(#[error(not(source))] String),
+    #[display("Monitor '{_0}' is already running")]
+    Running(#[error(not(source))] String),
+    #[display("Timed out waiting for monitor '{_0}' to finish")]
+    TimedOut(#[error(not(source))] String),
 }
 
 #[derive(Debug)]
 pub struct Monitor {
     pub config: Config,
     monitors: Vec<MonitorThread>,
+    /// The IDs of the local monitors and group children, which agents may not use.
+    local_ids: BTreeSet<String>,
+    /// The agents that have pushed their results, by name.
+    agents: SharedMut<BTreeMap<String, SharedMut<MonitorState>>>,
+    /// Notified when monitors change outside of the monitor threads.
+    changed: mpsc::Sender<()>,
 }
 
 pub trait MonitorMessageProcessor: Send + Sync + std::fmt::Debug + 'static {
     /// Process a message from a monitor thread, potentially generating internal
-    /// messages from it.
-    fn new(&self) -> Box<dyn MonitorMessageProcessorInstance>;
+    /// messages from it. The `history` is the state of the monitor before the run.
+    fn new(&self, history: &MonitorHistory) -> Box<dyn MonitorMessageProcessorInstance>;
 }
 
 pub trait MonitorMessageProcessorInstance: Send + Sync + std::fmt::Debug + 'static {
     /// Process a message from a monitor thread, potentially generating internal
     /// messages from it.
-    fn process_message(&self, input: &str) -> Vec<String>;
+    fn process_message(&self, stream: &LogStream, input: &str) -> Vec<String>;
 
     fn finalize(&self) -> Vec<String>;
 }
 
 impl MonitorThread {
-    /// Create a new monitor thread and release it
+    /// Create a new monitor thread and release it. Each time the monitor finishes a run, `changed` is notified.
     fn create(
         monitor: MonitorDirConfig,
         mut state: MonitorState,
         css_config: CssMetadataConfig,
+        changed: mpsc::Sender<()>,
     ) -> Result<Self, Box<dyn Error>> {
         state.status.initialize(&css_config);
         for state in &mut state.children {
             state.1.status.initialize(&css_config);
         }
         let state = SharedMut::new(state);
+        let drop_detect = SharedMut::new(());
+        let runs = Arc::new((Mutex::new(()), Condvar::new()));
 
+        // Composite monitors are updated from the other monitors instead
+        if let MonitorDirRootConfig::Composite(_) = monitor.root {
+            return Ok(MonitorThread {
+                state,
+                drop_detect,
+                trigger: None,
+                runs,
+            });
+        }
+
+        // Triggers that arrive while one is already pending are coalesced
+        let (trigger, triggers) = mpsc::sync_channel(1);
         let monitor_state = state.clone();
-        let drop_detect = SharedMut::new(());
+        let monitor_runs = runs.clone();
         let mut drop_detect_clone = Some(drop_detect.clone());
         let _thread = thread::spawn(move || {
-            monitor_thread(&monitor, move |id, m| {
+            let history_state = monitor_state.clone();
+            let history = move || history_state.read().history();
+            monitor_thread(&monitor, triggers, history, move |id, m| {
                 drop_detect_clone = if let Some(drop_detect) = drop_detect_clone.take() {
                     drop_detect.try_unwrap().err()
                 } else {
@@ -62,13 +108,33 @@ impl MonitorThread {
                 if drop_detect_clone.is_none() {
                     return Err(ShuttingDown::default().into());
                 }
-                monitor_state
+                let starting = matches!(m, WorkerMessage::Starting);
+                let finished = matches!(
+                    m,
+                    WorkerMessage::Termination(_) | WorkerMessage::AbnormalTermination(_)
+                );
+                let result = monitor_state
                     .write()
-                    .process_message(id, m, &css_config, &mut |_| {})
+                    .process_message(id, m, &css_config, &mut |_| {});
+                if starting || finished {
+                    // Notify while holding the lock, so a waiting trigger can't miss the change
+                    let (lock, condvar) = &*monitor_runs;
+                    let _runs = lock.lock().expect("Poisoned lock");
+                    condvar.notify_all();
+                }
+                if finished {
+                    let _ = changed.send(());
+                }
+                result
             });
         });
 
-        let thread = MonitorThread { state, drop_detect };
+        let thread = MonitorThread {
+            state,
+            drop_detect,
+            trigger: Some(trigger),
+            runs,
+        };
 
         Ok(thread)
     }
@@ -77,22 +143,220 @@ impl MonitorThread {
 impl Monitor {
     pub fn new(config: &Config) -> Result<Monitor, Box<dyn Error>> {
         let config = config.clone();
+        let monitor_configs = parse_monitor_configs(&config.monitor.dir)?;
+        let (changed, changes) = mpsc::channel();
         let mut monitors = Vec::new();
-        for monitor_config in &parse_monitor_configs(&config.monitor.dir)? {
+        for monitor_config in &monitor_configs {
             monitors.push(MonitorThread::create(
                 monitor_config.clone(),
                 monitor_config.into(),
                 config.css.metadata.clone(),
+                changed.clone(),
             )?);
         }
-        Ok(Monitor { config, monitors })
+        let agents = SharedMut::new(BTreeMap::new());
+
+        let mut composites = vec![];
+        for index in evaluation_order(&monitor_configs)? {
+            if let MonitorDirRootConfig::Composite(composite) = &monitor_configs[index].root {
+                composites.push((monitors[index].state.clone(), composite.clone()));
+            }
+        }
+        if !composites.is_empty() {
+            let states: Vec<_> = monitors.iter().map(|m| m.state.clone()).collect();
+            let agents = agents.clone();
+            let css_config = config.css.metadata.clone();
+            thread::spawn(move || loop {
+                let mut all = states.clone();
+                all.extend(agents.read().values().cloned());
+                evaluate_composites(&all, &composites, &css_config);
+                // Stop once the monitor is dropped, and coalesce bursts of changes
+                if changes.recv().is_err() {
+                    break;
+                }
+                while changes.try_recv().is_ok() {}
+            });
+        }
+
+        Ok(Monitor {
+            config,
+            monitors,
+            local_ids: local_ids(&monitor_configs),
+            agents,
+            changed,
+        })
     }
 
     pub fn status(&self) -> Status {
+        self.expire_agents();
+        let agents: Vec<_> = self.agents.read().values().cloned().collect();
         Status {
-            monitors: self.monitors.iter().map(|m| m.state.clone()).collect(),
+            monitors: with_imported(self.monitors.iter().map(|m| m.state.clone()).chain(agents)),
+        }
+    }
+
+    /// Wakes a monitor for an early run, refusing if it is already running. If `wait` is given, blocks until the
+    /// run finishes (or the wait times out) and returns the resulting state.
+    pub fn trigger(
+        &self,
+        id: &str,
+        wait: Option<Duration>,
+    ) -> Result<Option<MonitorState>, TriggerError> {
+        let monitor = self
+            .monitors
+            .iter()
+            .find(|monitor| monitor.state.read().id == id)
+            .ok_or_else(|| TriggerError::NotFound(id.to_string()))?;
+        let trigger = monitor
+            .trigger
+            .as_ref()
+            .ok_or_else(|| TriggerError::NotRunnable(id.to_string()))?;
+
+        let (lock, condvar) = &*monitor.runs;
+        let runs = lock.lock().expect("Poisoned lock");
+        let (running, finished) = {
+            let state = monitor.state.read();
+            (state.running, state.finished)
+        };
+        if running {
+            return Err(TriggerError::Running(id.to_string()));
+        }
+        // A full channel means a trigger is already pending, and this one joins it
+        let _ = trigger.try_send(());
+
+        let Some(wait) = wait else {
+            return Ok(None);
+        };
+        let (runs, timeout) = condvar
+            .wait_timeout_while(runs, wait, |_| monitor.state.read().finished == finished)
+            .expect("Poisoned lock");
+        drop(runs);
+        if timeout.timed_out() {
+            return Err(TriggerError::TimedOut(id.to_string()));
+        }
+        Ok(Some(monitor.state.read().clone()))
+    }
+
+    /// Follows the log of a monitor with [`MonitorState::follow_log`]. Composites, agents and imported monitors
+    /// never run here, so their current log is returned without a receiver instead.
+    pub fn follow_log(
+        &self,
+        id: &str,
+    ) -> Option<(Vec<String>, Option<broadcast::Receiver<String>>)> {
+        if let Some(monitor) = self
+            .monitors
+            .iter()
+            .find(|monitor| monitor.trigger.is_some() && monitor.state.read().id == id)
+        {
+            let (logged, receiver) = monitor.state.write().follow_log();
+            return Some((logged, Some(receiver)));
+        }
+        self.status()
+            .monitors
+            .into_iter()
+            .find(|monitor| monitor.read().id == id)
+            .map(|monitor| (monitor.read().status.log.iter().cloned().collect(), None))
+    }
+
+    /// Materializes the monitors pushed by an agent, prefixed with its name.
+    pub fn ingest(&self, agent: &str, mut remote: RemoteStatus) -> Result<(), Box<dyn Error>> {
+        let ingest = self
+            .config
+            .server
+            .ingest
+            .as_ref()
+            .ok_or("Ingest is not enabled")?;
+        validate_agent_name(agent)?;
+        if self.local_ids.contains(agent) {
+            return Err(format!("Agent name '{agent}' is used by a local monitor").into());
+        }
+        remote.add_prefix(&format!("{agent}-"));
+        let colliding = remote.remove_reserved(&self.local_ids);
+        if !colliding.is_empty() {
+            return Err(format!(
+                "Agent '{agent}' pushed monitors with local IDs: {}",
+                colliding.join(", ")
+            )
+            .into());
+        }
+
+        let css_config = &self.config.css.metadata;
+        let state = self
+            .agents
+            .write()
+            .entry(agent.to_string())
+            .or_insert_with(|| {
+                SharedMut::new(MonitorState::new_agent(
+                    agent.to_string(),
+                    ingest.stale_after,
+                    css_config,
+                ))
+            })
+            .clone();
+        let mut state = state.write();
+        for message in [
+            WorkerMessage::Starting,
+            WorkerMessage::Remote(Box::new(remote)),
+            WorkerMessage::Termination(0),
+        ] {
+            state.process_message(agent, message, css_config, &mut |_| {})?;
+        }
+        let _ = self.changed.send(());
+        Ok(())
+    }
+
+    /// Marks the agents that have stopped pushing their results as stale.
+    fn expire_agents(&self) {
+        let Some(ingest) = &self.config.server.ingest else {
+            return;
+        };
+        let mut expired = false;
+        for state in self.agents.read().values() {
+            expired |= state
+                .write()
+                .expire_agent(ingest.stale_after, &self.config.css.metadata);
+        }
+        if expired {
+            let _ = self.changed.send(());
+        }
+    }
+}
+
+/// Follows each monitor with the monitors it has imported from a remote instance.
+pub fn with_imported(
+    states: impl IntoIterator<Item = SharedMut<MonitorState>>,
+) -> Vec<SharedMut<MonitorState>> {
+    let mut monitors = vec![];
+    for state in states {
+        let imported = state.read().imported.clone();
+        monitors.push(state);
+        monitors.extend(imported);
+    }
+    monitors
+}
+
+/// Re-evaluates the composite monitors, in dependency order, from the current status of every monitor and
+/// group child.
+pub fn evaluate_composites(
+    states: &[SharedMut<MonitorState>],
+    composites: &[(SharedMut<MonitorState>, CompositeMonitorConfig)],
+    css_config: &CssMetadataConfig,
+) {
+    let mut statuses = BTreeMap::new();
+    for state in with_imported(states.iter().cloned()) {
+        let state = state.read();
+        let status = |status: &MonitorStatus| status.status.unwrap_or(StatusState::Blank);
+        statuses.insert(state.id.clone(), status(&state.status));
+        for (id, child) in &state.children {
+            statuses.insert(id.clone(), status(&child.status));
         }
     }
+    for (state, composite) in composites {
+        let (status, description) = composite.evaluate(&statuses);
+        let mut state = state.write();
+        state.update_composite(status, description, css_config);
+        statuses.insert(state.id.clone(), status);
+    }
 }
 
 #[cfg(test)]
@@ -122,7 +386,7 @@ mod test {
             parse_monitor_config(Path::new(&format!("src/testcases/{}/config.yaml", test)))?;
         let mut state: MonitorState = (&config).into();
         let metadata = CssMetadataConfig::default();
-        monitor_run(&config, &mut |id, m| {
+        monitor_run(&config, &Default::default(), &mut |id, m| {
             state.process_message(id, m, &metadata, &mut |_| {})
         })
         .1?;
@@ -186,6 +450,490 @@ mod test {
         Ok(())
     }
 
+    /// Tests whether a JSON document on stdout updates the monitor and its children.
+    #[test]
+    fn parse_json_test() -> Result<(), Box<dyn Error>> {
+        use StatusState::*;
+        let state = run_test("parse_json")?;
+        assert_eq!(
+            extract_status(&state.status),
+            (Yellow, "Custom (yellow)".into(), 0)
+        );
+        assert_eq!(state.status.metadata.get("ports").unwrap(), "2");
+        assert_eq!(
+            extract_child_results(state),
+            vec![(Green, "Success".into(), 0), (Red, "Port down".into(), 0)]
+        );
+        Ok(())
+    }
+
+    /// Tests whether a Monitoring Plugins warning maps to orange with its description and perfdata.
+    #[test]
+    fn nagios_warning_test() -> Result<(), Box<dyn Error>> {
+        use StatusState::*;
+        let state = run_test("nagios_warning")?;
+        assert_eq!(
+            extract_status(&state.status),
+            (
+                Orange,
+                "DISK WARNING - free space: /boot 68 MB (69%);".into(),
+                1
+            )
+        );
+        assert_eq!(state.status.metadata.get("boot").unwrap(), "68");
+        assert_eq!(state.status.metadata.get("boot_unit").unwrap(), "MB");
+        assert_eq!(state.status.metadata.get("boot_crit").unwrap(), "93");
+        Ok(())
+    }
+
+    /// Tests whether status rules pick the status from the script's metadata.
+    #[test]
+    fn rules_metadata_test() -> Result<(), Box<dyn Error>> {
+        use StatusState::*;
+        let state = run_test("rules_metadata")?;
+        assert_eq!(extract_status(&state.status), (Orange, "Success".into(), 0));
+        assert_eq!(state.status.metadata.get("temperature").unwrap(), "91.5");
+        Ok(())
+    }
+
+    /// Tests whether status rules see the metadata reported by a script that fails.
+    #[test]
+    fn rules_failure_test() -> Result<(), Box<dyn Error>> {
+        use StatusState::*;
+        let state = run_test("rules_failure")?;
+        assert_eq!(extract_status(&state.status), (Orange, "Failed".into(), 1));
+        // The metadata of a failed run is still discarded
+        assert!(state.status.metadata.is_empty());
+        Ok(())
+    }
+
+    /// Tests whether status rules see the metadata reported by a script that times out.
+    #[test]
+    fn rules_timeout_test() -> Result<(), Box<dyn Error>> {
+        use StatusState::*;
+        let state = run_test("rules_timeout")?;
+        assert_eq!(
+            extract_status(&state.status),
+            (Red, "Process timed out".into(), -1)
+        );
+        Ok(())
+    }
+
+    /// Tests whether status rules can see the previous status and how long ago it changed.
+    #[test]
+    fn rules_time_test() -> Result<(), Box<dyn Error>> {
+        use StatusState::*;
+        let config = parse_monitor_config(Path::new("src/testcases/rules_time/config.yaml"))?;
+        let mut state: MonitorState = (&config).into();
+        let metadata = CssMetadataConfig::default();
+        state.status.initialize(&metadata);
+
+        let mut last_change = None;
+        for expected in [Orange, Red, Green] {
+            monitor_run(&config, &Default::default(), &mut |id, m| {
+                state.process_message(id, m, &metadata, &mut |_| {})
+            })
+            .1?;
+            assert_eq!(state.status.status, Some(expected));
+            assert!(state.status.last_change > last_change);
+            last_change = state.status.last_change;
+        }
+        assert!(state.status.last_failure.is_some());
+        assert_eq!(state.status.last_success, state.status.last_change);
+        Ok(())
+    }
+
+    /// Tests whether group children are discovered at runtime, keeping those without a status blank.
+    #[test]
+    fn group_discovery_test() -> Result<(), Box<dyn Error>> {
+        use StatusState::*;
+        let state = run_test("group_discovery")?;
+        assert_eq!(extract_status(&state.status), (Green, "Success".into(), 0));
+        assert_eq!(
+            state.children.keys().collect::<Vec<_>>(),
+            vec!["disk-sda", "disk-sdb", "disk-sdc"]
+        );
+        assert_eq!(
+            extract_child_results(state),
+            vec![
+                (Green, "Success".into(), 0),
+                (Red, "Success".into(), 0),
+                (Blank, "Unknown (initializing)".into(), 0),
+            ]
+        );
+        Ok(())
+    }
+
+    /// Tests whether discovered children are removed once they have been missing for the grace period.
+    #[test]
+    fn group_discovery_grace_test() -> Result<(), Box<dyn Error>> {
+        use crate::worker::WorkerMessage;
+        use std::collections::BTreeMap;
+        use std::time::Duration;
+
+        let config = parse_monitor_config(Path::new("src/testcases/group_discovery/config.yaml"))?;
+        let mut state: MonitorState = (&config).into();
+        let metadata = CssMetadataConfig::default();
+        let discover = |state: &mut MonitorState, names: &[&str], grace| {
+            let children = names
+                .iter()
+                .map(|name| {
+                    let axes = BTreeMap::from([(
+                        "name".to_string(),
+                        MonitorDirAxisValue::String(name.to_string()),
+                    )]);
+                    (format!("disk-{name}"), axes)
+                })
+                .collect();
+            state.process_message(
+                &config.id,
+                WorkerMessage::Discovered(children, grace),
+                &metadata,
+                &mut |_| {},
+            )
+        };
+
+        discover(&mut state, &["sda", "sdb"], Duration::from_secs(60))?;
+        discover(&mut state, &["sda"], Duration::from_secs(60))?;
+        assert_eq!(state.children.len(), 2);
+        assert!(state.children["disk-sdb"].missing_since.is_some());
+
+        discover(&mut state, &["sda", "sdb"], Duration::from_secs(60))?;
+        assert!(state.children["disk-sdb"].missing_since.is_none());
+
+        discover(&mut state, &["sda"], Duration::ZERO)?;
+        assert_eq!(state.children.keys().collect::<Vec<_>>(), vec!["disk-sda"]);
+        Ok(())
+    }
+
+    /// Tests whether each child of a per-child group gets its status from its own run.
+    #[test]
+    fn group_per_child_test() -> Result<(), Box<dyn Error>> {
+        use StatusState::*;
+        let state = run_test("group_per_child")?;
+        assert_eq!(extract_status(&state.status), (Green, "Success".into(), 0));
+        assert_eq!(
+            extract_child_results(state),
+            vec![
+                (Green, "Success".into(), 0),
+                (Red, "Failed".into(), 1),
+                (Yellow, "Port 3 is slow".into(), 0),
+            ]
+        );
+        Ok(())
+    }
+
+    /// Tests whether children with `.` in their ids can be updated.
+    #[test]
+    fn group_cidr_test() -> Result<(), Box<dyn Error>> {
+        use StatusState::*;
+        let state = run_test("group_cidr")?;
+        assert_eq!(
+            state.children.keys().collect::<Vec<_>>(),
+            vec!["host-10.0.0.1", "host-10.0.0.10"]
+        );
+        let child = |id: &str| &state.children[id].status;
+        assert_eq!(child("host-10.0.0.1").status, Some(Green));
+        assert_eq!(child("host-10.0.0.10").status, Some(Red));
+        assert_eq!(child("host-10.0.0.10").metadata["rtt"], "0.5");
+        Ok(())
+    }
+
+    /// Tests whether a rollup policy sets the group's status from its children.
+    #[test]
+    fn group_rollup_test() -> Result<(), Box<dyn Error>> {
+        use StatusState::*;
+        let state = run_test("group_rollup")?;
+        assert_eq!(
+            extract_status(&state.status),
+            (Orange, "1 red, 2 yellow, 1 green".into(), 0)
+        );
+        let counts = state.status.child_counts.as_ref().unwrap();
+        assert_eq!((counts.children, counts.children_yellow), (4, 2));
+        Ok(())
+    }
+
+    /// Tests whether composite monitors are evaluated, in order, from the status of other monitors.
+    #[test]
+    fn composite_test() -> Result<(), Box<dyn Error>> {
+        use StatusState::*;
+        let config = |id: &str, yaml: &str| -> Result<_, Box<dyn Error>> {
+            let mut config =
+                parse_monitor_config_string(Path::new("/tmp/test.yaml"), yaml.to_string())?;
+            config.id = id.to_string();
+            Ok(config)
+        };
+        let test = "test:\n    interval: 60s\n    timeout: 30s\n    command: /bin/true\n";
+        let configs = vec![
+            config(
+                "site",
+                "composite:\n    red: status('network') == 'red'\n    green: \"true\"\n",
+            )?,
+            config(
+                "network",
+                "composite:\n    red: status('wan1') == 'red' and status('wan2') == 'red'\n    \
+                 yellow: status('wan1') == 'red' or status('wan2') == 'red'\n    default: green\n",
+            )?,
+            config("wan1", test)?,
+            config("wan2", test)?,
+        ];
+
+        let metadata = CssMetadataConfig::default();
+        let states: Vec<_> = configs
+            .iter()
+            .map(|config| SharedMut::new(MonitorState::from(config)))
+            .collect();
+        let composites: Vec<_> = evaluation_order(&configs)?
+            .into_iter()
+            .map(|index| match &configs[index].root {
+                MonitorDirRootConfig::Composite(composite) => {
+                    (states[index].clone(), composite.clone())
+                }
+                _ => panic!("Expected a composite"),
+            })
+            .collect();
+        let finish = |index: usize, code: i64| {
+            states[index].write().process_message(
+                "",
+                WorkerMessage::Termination(code),
+                &metadata,
+                &mut |_| {},
+            )
+        };
+
+        finish(2, 0)?;
+        finish(3, 1)?;
+        evaluate_composites(&states, &composites, &metadata);
+        assert_eq!(
+            extract_status(&states[1].read().status),
+            (Yellow, "wan2 is red".into(), 0)
+        );
+        assert_eq!(
+            extract_status(&states[0].read().status),
+            (Green, "true".into(), 0)
+        );
+
+        finish(2, 1)?;
+        evaluate_composites(&states, &composites, &metadata);
+        assert_eq!(
+            extract_status(&states[1].read().status),
+            (Red, "wan1 is red, wan2 is red".into(), 0)
+        );
+        assert_eq!(
+            extract_status(&states[0].read().status),
+            (Red, "network is red".into(), 0)
+        );
+        Ok(())
+    }
+
+    /// Serves a fixed status and config over HTTP on localhost, as another Stylus instance would.
+    fn serve_status(status: String, config: String) -> Result<u16, Box<dyn Error>> {
+        use std::io::{Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else {
+                    break;
+                };
+                let mut request = vec![];
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let body = if request.starts_with(b"GET /config.json") {
+                    &config
+                } else {
+                    &status
+                };
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+            }
+        });
+        Ok(port)
+    }
+
+    /// Tests whether the monitors of a remote instance are imported under a prefix, and blanked out when the
+    /// remote is unreachable.
+    #[test]
+    fn remote_test() -> Result<(), Box<dyn Error>> {
+        use StatusState::*;
+        let group = run_test("group_complete")?;
+        let group_id = group.id.clone();
+        let status = Status {
+            monitors: vec![SharedMut::new(group)],
+        };
+        let status = serde_json::to_string(&status)?;
+        let port = serve_status(status.clone(), r#"{"version":1}"#.to_string())?;
+        let config = |url: String| {
+            parse_monitor_config_string(
+                Path::new("/tmp/test.yaml"),
+                format!("id: home\nremote:\n    url: {url}\n    interval: 60s\n    timeout: 5s\n"),
+            )
+        };
+
+        let remote = config(format!("http://127.0.0.1:{port}"))?;
+        let mut state: MonitorState = (&remote).into();
+        let metadata = CssMetadataConfig::default();
+        monitor_run(&remote, &Default::default(), &mut |id, m| {
+            state.process_message(id, m, &metadata, &mut |_| {})
+        })
+        .1?;
+        assert_eq!(
+            extract_status(&state.status),
+            (Green, "Imported 1 monitors".into(), 0)
+        );
+        assert_eq!(state.imported.len(), 1);
+        let imported = state.imported[0].read().clone();
+        assert_eq!(imported.id, format!("home-{group_id}"));
+        assert_eq!(imported.kind, "remote");
+        assert_eq!(
+            imported.children.keys().collect::<Vec<_>>(),
+            vec!["home-port-0", "home-port-1", "home-port-2", "home-port-3"]
+        );
+        assert_eq!(imported.status.status, Some(Green));
+        // The imported monitor describes the remote's test rather than the local curl command
+        assert!(imported.config.command.ends_with("group_complete/test.sh"));
+        assert!(imported.config.args.is_empty());
+
+        // Imported monitors that collide with local IDs are skipped
+        let mut colliding = remote.clone();
+        if let MonitorDirRootConfig::Remote(remote) = &mut colliding.root {
+            remote.reserved = ["home-port-1".to_string()].into();
+        }
+        let mut colliding_state: MonitorState = (&colliding).into();
+        monitor_run(&colliding, &Default::default(), &mut |id, m| {
+            colliding_state.process_message(id, m, &metadata, &mut |_| {})
+        })
+        .1?;
+        assert_eq!(
+            colliding_state.status.description,
+            "Imported 1 monitors, skipped 1 with local IDs: home-port-1"
+        );
+        assert_eq!(colliding_state.imported[0].read().children.len(), 3);
+
+        // Instances with an unknown config version aren't imported
+        let port = serve_status(status, r#"{"version":2}"#.to_string())?;
+        let unsupported = config(format!("http://127.0.0.1:{port}"))?;
+        let error = monitor_run(&unsupported, &Default::default(), &mut |_, _| Ok(()))
+            .1
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Unsupported config version 2"));
+
+        // Nothing listens on the port once the listener is dropped
+        let closed = std::net::TcpListener::bind("127.0.0.1:0")?
+            .local_addr()?
+            .port();
+        let unreachable = config(format!("http://127.0.0.1:{closed}"))?;
+        let error = monitor_run(&unreachable, &Default::default(), &mut |id, m| {
+            state.process_message(id, m, &metadata, &mut |_| {})
+        })
+        .1
+        .unwrap_err();
+        // The monitor thread reports failed runs as abnormal terminations
+        let message = WorkerMessage::AbnormalTermination(error.to_string());
+        state.process_message("home", message, &metadata, &mut |_| {})?;
+        assert_eq!(state.status.status, Some(Yellow));
+        assert!(state.status.description.starts_with("Remote fetch failed"));
+        let imported = state.imported[0].read();
+        assert_eq!(
+            extract_status(&imported.status),
+            (Blank, "Stale (remote unreachable)".into(), 0)
+        );
+        assert!(imported
+            .children
+            .values()
+            .all(|child| child.status.status == Some(Blank)));
+        Ok(())
+    }
+
+    /// Tests whether the monitors pushed by an agent are materialized under its name, and go stale once it stops
+    /// pushing.
+    #[test]
+    fn ingest_test() -> Result<(), Box<dyn Error>> {
+        use StatusState::*;
+        let group = run_test("group_complete")?;
+        let group_id = group.id.clone();
+        let pushed = serde_json::to_string(&Status {
+            monitors: vec![SharedMut::new(group)],
+        })?;
+
+        let mut config = Config::default();
+        config.server.ingest = Some(IngestConfig {
+            token: "token".to_string(),
+            stale_after: std::time::Duration::from_secs(60),
+        });
+        let mut monitor = Monitor {
+            config,
+            monitors: vec![],
+            local_ids: ["garage".to_string(), "cabin-port-2".to_string()].into(),
+            agents: SharedMut::new(BTreeMap::new()),
+            changed: mpsc::channel().0,
+        };
+        assert!(monitor
+            .ingest("bad/name", serde_json::from_str(&pushed)?)
+            .is_err());
+        // Agents can't take over local monitors, either by name or with the monitors they push
+        let error = monitor
+            .ingest("garage", serde_json::from_str(&pushed)?)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Agent name 'garage' is used by a local monitor"
+        );
+        let error = monitor
+            .ingest("cabin", serde_json::from_str(&pushed)?)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Agent 'cabin' pushed monitors with local IDs: cabin-port-2"
+        );
+        assert!(monitor.agents.read().is_empty());
+
+        monitor.local_ids.clear();
+        monitor.ingest("cabin", serde_json::from_str(&pushed)?)?;
+
+        let ids: Vec<_> = monitor
+            .status()
+            .monitors
+            .iter()
+            .map(|state| state.read().id.clone())
+            .collect();
+        assert_eq!(ids, vec!["cabin".to_string(), format!("cabin-{group_id}")]);
+        let agent = monitor.agents.read()["cabin"].clone();
+        assert_eq!(
+            extract_status(&agent.read().status),
+            (Green, "Imported 1 monitors".into(), 0)
+        );
+        assert_eq!(agent.read().imported[0].read().kind, "agent");
+
+        // Agents and their monitors don't run here, so following their log ends with the current log
+        let (logged, receiver) = monitor.follow_log("cabin").unwrap();
+        assert!(logged.last().unwrap().ends_with("[exec  ] Termination: 0"));
+        assert!(receiver.is_none());
+        let (_, receiver) = monitor.follow_log(&format!("cabin-{group_id}")).unwrap();
+        assert!(receiver.is_none());
+        assert!(monitor.follow_log("missing").is_none());
+
+        // An agent that hasn't pushed within the stale period is marked as stale
+        let metadata = CssMetadataConfig::default();
+        assert!(!agent
+            .write()
+            .expire_agent(std::time::Duration::from_secs(60), &metadata));
+        assert!(agent.write().expire_agent(Default::default(), &metadata));
+        assert_eq!(agent.read().status.status, Some(Yellow));
+        assert_eq!(agent.read().imported[0].read().status.status, Some(Blank));
+        Ok(())
+    }
+
     /// Tests whether the incomplete members of a group are correctly blanked out.
     #[test]
     fn group_incomplete_test() -> Result<(), Box<dyn Error>> {
@@ -202,4 +950,51 @@ mod test {
         );
         Ok(())
     }
+
+    /// Tests whether a triggered run can be waited on, and that a run can't be triggered while one is in progress.
+    #[test]
+    fn trigger_test() -> Result<(), Box<dyn Error>> {
+        let monitor = Monitor::new(&parse_config(Path::new("src/template"))?)?;
+        let wait = Some(Duration::from_secs(30));
+        assert_eq!(
+            monitor.trigger("missing", None).unwrap_err(),
+            TriggerError::NotFound("missing".into())
+        );
+
+        // The first run starts as soon as the monitor is created
+        let state = loop {
+            match monitor.trigger("my-monitor", wait) {
+                Err(TriggerError::Running(_)) => thread::sleep(Duration::from_millis(10)),
+                result => break result?.expect("Missing state"),
+            }
+        };
+        assert_eq!(state.status.status, Some(StatusState::Green));
+        assert!(!state.status.log.is_empty());
+        Ok(())
+    }
+
+    /// Tests whether a log stream follows the next run and closes after its termination line.
+    #[test]
+    fn follow_log_test() -> Result<(), Box<dyn Error>> {
+        use tokio::sync::broadcast::error::TryRecvError;
+        let config = parse_monitor_config(Path::new("src/testcases/success/config.yaml"))?;
+        let mut state: MonitorState = (&config).into();
+        let metadata = CssMetadataConfig::default();
+
+        let (logged, mut receiver) = state.follow_log();
+        assert!(logged.is_empty());
+        monitor_run(&config, &Default::default(), &mut |id, m| {
+            state.process_message(id, m, &metadata, &mut |_| {})
+        })
+        .1?;
+        let mut lines = vec![];
+        while let Ok(line) = receiver.try_recv() {
+            lines.push(line);
+        }
+        assert_eq!(lines, Vec::from(state.status.log.clone()));
+        assert!(lines[0].ends_with("[exec  ] Starting"));
+        assert!(lines.last().unwrap().ends_with("[exec  ] Termination: 0"));
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Closed));
+        Ok(())
+    }
 }
@@ -0,0 +1,216 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{MonitorDirConfig, MonitorDirRootConfig, MonitorDirRulesConfig, MonitorDirTestConfig},
+    expressions::{self, Expression, ExpressionContext, Value},
+    status::StatusState,
+};
+
+/// A monitor that runs nothing, taking its status from the status of other monitors.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct CompositeMonitorConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub red: Option<Expression>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub orange: Option<Expression>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub yellow: Option<Expression>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blue: Option<Expression>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub green: Option<Expression>,
+    /// The status when no expression matches.
+    #[serde(default = "default_status")]
+    pub default: StatusState,
+    #[serde(skip_deserializing)]
+    pub test: Option<MonitorDirTestConfig>,
+}
+
+fn default_status() -> StatusState {
+    StatusState::Blank
+}
+
+/// The status of every monitor and group child, by ID.
+struct CompositeContext<'a>(&'a BTreeMap<String, StatusState>);
+
+impl ExpressionContext for CompositeContext<'_> {
+    fn get(&self, _key: &str) -> Option<&Value> {
+        None
+    }
+
+    fn status(&self, id: &str) -> Option<Value> {
+        self.0
+            .get(id)
+            .map(|status| Value::Str(status.to_string().to_lowercase().into()))
+    }
+}
+
+impl CompositeMonitorConfig {
+    fn rules(&self) -> MonitorDirRulesConfig {
+        MonitorDirRulesConfig {
+            red: self.red.clone(),
+            orange: self.orange.clone(),
+            yellow: self.yellow.clone(),
+            blue: self.blue.clone(),
+            green: self.green.clone(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        // Composite expressions can only read the status of other monitors
+        expressions::validate_identifiers(&self.rules().expressions(), &[])
+    }
+
+    /// The IDs of the monitors this composite depends on.
+    pub fn dependencies(&self) -> BTreeSet<&str> {
+        [
+            &self.red,
+            &self.orange,
+            &self.yellow,
+            &self.blue,
+            &self.green,
+        ]
+        .into_iter()
+        .flatten()
+        .flat_map(|expression| expression.monitors())
+        .map(|(id, _)| id)
+        .collect()
+    }
+
+    /// Returns the status and a description of the inputs that selected it.
+    pub fn evaluate(&self, statuses: &BTreeMap<String, StatusState>) -> (StatusState, String) {
+        let rules = self.rules();
+        let context = CompositeContext(statuses);
+        match rules.evaluate(&context) {
+            Some(status) => {
+                let (_, expression) = rules
+                    .expressions()
+                    .into_iter()
+                    .find(|(name, _)| *name == status.to_string().to_lowercase())
+                    .expect("The matching rule must exist");
+                (status, describe(expression, status, statuses))
+            }
+            None => (self.default, "No rule matched".to_string()),
+        }
+    }
+
+    pub fn test(&self) -> MonitorDirTestConfig {
+        // Composite monitors never run, but every monitor carries a test configuration
+        MonitorDirTestConfig {
+            command: PathBuf::from("/usr/bin/env"),
+            args: vec!["true".to_string()],
+            ..Default::default()
+        }
+    }
+}
+
+/// Describes the inputs of the expression that decided `status`: the green inputs for a green status, and the
+/// others for any other status (eg: `wan1 is red`). Falls back to every input if none fit, and to the expression
+/// itself if it reads no monitors.
+fn describe(
+    expression: &Expression,
+    status: StatusState,
+    statuses: &BTreeMap<String, StatusState>,
+) -> String {
+    let mut inputs: Vec<(&str, StatusState)> = vec![];
+    for (id, _) in expression.monitors() {
+        if let Some(input) = statuses.get(id) {
+            if !inputs.iter().any(|(known, _)| *known == id) {
+                inputs.push((id, *input));
+            }
+        }
+    }
+    if inputs.is_empty() {
+        return expression.source().to_string();
+    }
+
+    let deciding: Vec<_> = inputs
+        .iter()
+        .filter(|(_, input)| (*input == StatusState::Green) == (status == StatusState::Green))
+        .collect();
+    let deciding = if deciding.is_empty() {
+        inputs.iter().collect()
+    } else {
+        deciding
+    };
+    deciding
+        .into_iter()
+        .map(|(id, input)| format!("{id} is {}", input.to_string().to_lowercase()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Orders the composite monitors so that each is evaluated after the composites it depends on, failing if
+/// composites depend on each other in a cycle.
+pub fn evaluation_order(configs: &[MonitorDirConfig]) -> Result<Vec<usize>, Box<dyn Error>> {
+    let composites: BTreeMap<&str, (usize, &CompositeMonitorConfig)> = configs
+        .iter()
+        .enumerate()
+        .filter_map(|(index, config)| match &config.root {
+            MonitorDirRootConfig::Composite(composite) => {
+                Some((config.id.as_str(), (index, composite)))
+            }
+            _ => None,
+        })
+        .collect();
+
+    let mut known = BTreeSet::new();
+    let mut discovers = false;
+    for config in configs {
+        known.insert(config.id.as_str());
+        match &config.root {
+            MonitorDirRootConfig::Group(group) => {
+                known.extend(group.children.keys().map(String::as_str));
+                discovers |= group.axes_from.is_some();
+            }
+            MonitorDirRootConfig::Remote(_) => discovers = true,
+            _ => {}
+        }
+    }
+
+    fn visit<'a>(
+        id: &'a str,
+        composites: &BTreeMap<&'a str, (usize, &'a CompositeMonitorConfig)>,
+        path: &mut Vec<&'a str>,
+        order: &mut Vec<usize>,
+    ) -> Result<(), Box<dyn Error>> {
+        let Some((index, composite)) = composites.get(id) else {
+            return Ok(());
+        };
+        if order.contains(index) {
+            return Ok(());
+        }
+        if let Some(start) = path.iter().position(|p| *p == id) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(id);
+            return Err(format!("Composite monitors form a cycle: {}", cycle.join(" -> ")).into());
+        }
+        path.push(id);
+        for dependency in composite.dependencies() {
+            visit(dependency, composites, path, order)?;
+        }
+        path.pop();
+        order.push(*index);
+        Ok(())
+    }
+
+    let mut order = vec![];
+    for (id, (_, composite)) in &composites {
+        for dependency in composite.dependencies() {
+            // Children that are discovered and monitors that are imported at runtime can't be checked up front
+            if !known.contains(dependency) && !discovers {
+                warn!("Composite monitor {id} refers to unknown monitor {dependency}");
+            }
+        }
+        visit(id, &composites, &mut vec![], &mut order)?;
+    }
+    Ok(order)
+}
@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use subprocess::{Exec, Redirection};
+
+use crate::config::MonitorDirAxisValue;
+
+/// Discovers the axis values of a group at the start of each run.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct MonitorDirAxesFromConfig {
+    /// A command whose output lists the axis values, relative to the monitor directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<PathBuf>,
+    /// A file listing the axis values, relative to the monitor directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+    #[serde(default)]
+    pub format: MonitorDirAxesFromFormat,
+    /// The axis name for plain values.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// How long a child may be missing from the discovered values before it is removed.
+    #[serde(with = "humantime_serde", default)]
+    pub grace: Duration,
+    #[serde(skip)]
+    pub args: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MonitorDirAxesFromFormat {
+    /// One value per line, ignoring blank lines and `#` comments.
+    #[default]
+    Lines,
+    /// A JSON array of values, or of objects mapping axis names to values.
+    Json,
+}
+
+impl MonitorDirAxesFromConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.command.is_some() == self.file.is_some() {
+            return Err("axes_from requires exactly one of command or file".into());
+        }
+        if matches!(self.format, MonitorDirAxesFromFormat::Lines) && self.name.is_none() {
+            return Err("axes_from requires a name when the format is lines".into());
+        }
+        Ok(())
+    }
+
+    /// Runs the command or reads the file, returning one set of axis values per discovered entry.
+    pub fn discover(
+        &self,
+        base_path: &Path,
+        timeout: Duration,
+    ) -> Result<Vec<BTreeMap<String, MonitorDirAxisValue>>, Box<dyn Error>> {
+        let output = if let Some(command) = &self.command {
+            run_command(command, &self.args, base_path, timeout)?
+        } else if let Some(file) = &self.file {
+            std::fs::read_to_string(base_path.join(file))
+                .map_err(|e| format!("Failed to read {}: {e}", file.display()))?
+        } else {
+            return Err("axes_from requires a command or file".into());
+        };
+
+        match self.format {
+            MonitorDirAxesFromFormat::Lines => Ok(parse_lines(
+                self.name.as_deref().unwrap_or_default(),
+                &output,
+            )),
+            MonitorDirAxesFromFormat::Json => parse_json(self.name.as_deref(), &output),
+        }
+    }
+}
+
+fn run_command(
+    command: &Path,
+    args: &[String],
+    base_path: &Path,
+    timeout: Duration,
+) -> Result<String, Box<dyn Error>> {
+    let mut popen = Exec::cmd(command)
+        .args(args)
+        .cwd(base_path)
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .popen()?;
+    let result = popen.communicate_start(None).limit_time(timeout).read();
+    let (stdout, _) = match result {
+        Ok(output) => output,
+        Err(e) => {
+            let _ = popen.kill();
+            return Err(format!("Axis discovery failed: {}", e.error).into());
+        }
+    };
+    match popen.wait_timeout(Duration::from_millis(250))? {
+        Some(status) if status.success() => {}
+        Some(status) => return Err(format!("Axis discovery failed: {:?}", status).into()),
+        None => {
+            let _ = popen.kill();
+            return Err("Axis discovery timed out".into());
+        }
+    }
+    Ok(String::from_utf8_lossy(&stdout.unwrap_or_default()).into_owned())
+}
+
+fn parse_lines(name: &str, output: &str) -> Vec<BTreeMap<String, MonitorDirAxisValue>> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| BTreeMap::from([(name.to_string(), MonitorDirAxisValue::parse(line))]))
+        .collect()
+}
+
+fn json_axis_value(value: &Value) -> MonitorDirAxisValue {
+    match value {
+        Value::Number(n) if n.is_i64() => MonitorDirAxisValue::Number(n.as_i64().unwrap()),
+        Value::String(s) => MonitorDirAxisValue::String(s.clone()),
+        value => MonitorDirAxisValue::String(value.to_string()),
+    }
+}
+
+fn parse_json(
+    name: Option<&str>,
+    output: &str,
+) -> Result<Vec<BTreeMap<String, MonitorDirAxisValue>>, Box<dyn Error>> {
+    let Value::Array(values) = serde_json::from_str(output)? else {
+        return Err("Expected a JSON array of axis values".into());
+    };
+    let mut result = vec![];
+    for value in values {
+        result.push(match (value, name) {
+            (Value::Object(object), _) => object
+                .iter()
+                .map(|(k, v)| (k.clone(), json_axis_value(v)))
+                .collect(),
+            (value, Some(name)) => BTreeMap::from([(name.to_string(), json_axis_value(&value))]),
+            (value, None) => {
+                return Err(format!("Expected an object of axis values, got: {value}").into())
+            }
+        });
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_lines() {
+        assert_eq!(
+            parse_lines("host", "# Hosts\nweb-1\n\n  db-1  \n42\n"),
+            vec![
+                BTreeMap::from([("host".into(), MonitorDirAxisValue::String("web-1".into()))]),
+                BTreeMap::from([("host".into(), MonitorDirAxisValue::String("db-1".into()))]),
+                BTreeMap::from([("host".into(), MonitorDirAxisValue::Number(42))]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_json() {
+        assert_eq!(
+            parse_json(Some("index"), "[1, \"two\"]").unwrap(),
+            vec![
+                BTreeMap::from([("index".into(), MonitorDirAxisValue::Number(1))]),
+                BTreeMap::from([("index".into(), MonitorDirAxisValue::String("two".into()))]),
+            ]
+        );
+        assert_eq!(
+            parse_json(None, r#"[{"host": "web", "port": 80}]"#).unwrap(),
+            vec![BTreeMap::from([
+                ("host".into(), MonitorDirAxisValue::String("web".into())),
+                ("port".into(), MonitorDirAxisValue::Number(80)),
+            ])]
+        );
+        assert!(parse_json(None, "[1]").is_err());
+        assert!(parse_json(None, "{}").is_err());
+    }
+}
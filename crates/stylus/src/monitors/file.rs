@@ -0,0 +1,511 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use chrono::Utc;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::MonitorDirTestConfig,
+    expressions::{self, Expression, Layered, Value},
+    monitor::{MonitorMessageProcessor, MonitorMessageProcessorInstance},
+    status::MonitorHistory,
+    worker::LogStream,
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct FileMonitorConfig {
+    /// A path or glob, relative to the monitor directory.
+    pub path: String,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+    /// If set, the SHA-256 hash of the newest file is made available as `hash`.
+    #[serde(default)]
+    pub hash: bool,
+    /// If set, the newest file's contents are matched against this regular expression.
+    #[serde(default, rename = "match")]
+    pub pattern: Option<String>,
+    #[serde(default = "default_red")]
+    pub red: Expression,
+    #[serde(default = "default_green")]
+    pub green: Expression,
+    #[serde(default = "default_blue")]
+    pub blue: Expression,
+    #[serde(default = "default_orange")]
+    pub orange: Expression,
+    #[serde(default = "default_yellow")]
+    pub yellow: Expression,
+    #[serde(skip)]
+    pub base_path: PathBuf,
+    #[serde(skip_deserializing)]
+    pub test: Option<MonitorDirTestConfig>,
+}
+
+fn default_red() -> Expression {
+    "count == 0".parse().unwrap()
+}
+
+fn default_green() -> Expression {
+    "count > 0".parse().unwrap()
+}
+
+fn default_blue() -> Expression {
+    "false".parse().unwrap()
+}
+
+fn default_orange() -> Expression {
+    "false".parse().unwrap()
+}
+
+fn default_yellow() -> Expression {
+    "false".parse().unwrap()
+}
+
+impl FileMonitorConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let mut known = vec!["count", "newest", "age_seconds", "size"];
+        known.extend(MonitorHistory::VARIABLES);
+        if self.hash {
+            known.push("hash");
+        }
+        if let Some(pattern) = &self.pattern {
+            Regex::new(pattern).map_err(|e| format!("Invalid match {pattern:?}: {e}"))?;
+            known.push("matches");
+        }
+        expressions::validate_identifiers(
+            &[
+                ("red", &self.red),
+                ("green", &self.green),
+                ("blue", &self.blue),
+                ("orange", &self.orange),
+                ("yellow", &self.yellow),
+            ],
+            &known,
+        )
+    }
+
+    pub fn test(&self) -> MonitorDirTestConfig {
+        // All of the work happens in the processor, so we just need a process that exits cleanly
+        MonitorDirTestConfig {
+            interval: self.interval,
+            timeout: self.timeout,
+            command: PathBuf::from("/usr/bin/env"),
+            args: vec!["true".to_string()],
+            processor: Some(Arc::new(FileMonitorMessageProcessor {
+                base_path: self.base_path.clone(),
+                path: self.path.clone(),
+                timeout: self.timeout,
+                hash: self.hash,
+                hash_cache: Default::default(),
+                pattern: self.pattern.clone(),
+                red: self.red.clone(),
+                green: self.green.clone(),
+                blue: self.blue.clone(),
+                orange: self.orange.clone(),
+                yellow: self.yellow.clone(),
+            })),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FileMonitorMessageProcessor {
+    base_path: PathBuf,
+    path: String,
+    timeout: Duration,
+    hash: bool,
+    /// The hash of the newest file from an earlier run, shared by every run of the monitor.
+    hash_cache: Arc<Mutex<Option<HashedFile>>>,
+    pattern: Option<String>,
+    red: Expression,
+    green: Expression,
+    blue: Expression,
+    orange: Expression,
+    yellow: Expression,
+}
+
+#[derive(Debug)]
+pub struct FileMonitorMessageProcessorInstance {
+    config: FileMonitorMessageProcessor,
+    history: BTreeMap<String, Value>,
+    deadline: Instant,
+}
+
+/// A file's hash, along with the modification time and size it was computed for.
+#[derive(Debug, PartialEq)]
+struct HashedFile {
+    path: PathBuf,
+    modified: SystemTime,
+    size: u64,
+    hash: String,
+}
+
+impl MonitorMessageProcessor for FileMonitorMessageProcessor {
+    fn new(&self, history: &MonitorHistory) -> Box<dyn MonitorMessageProcessorInstance> {
+        Box::new(FileMonitorMessageProcessorInstance {
+            config: self.clone(),
+            history: history.variables(Utc::now()),
+            deadline: Instant::now() + self.timeout,
+        })
+    }
+}
+
+/// The result of scanning the files matching a path or glob.
+#[derive(Debug, Default, PartialEq)]
+struct FileScan {
+    count: usize,
+    newest: Option<(PathBuf, SystemTime, u64)>,
+}
+
+fn scan_files(base_path: &Path, path: &str) -> Result<FileScan, String> {
+    let pattern = base_path.join(path);
+    let pattern = pattern.to_string_lossy();
+    let paths = glob::glob(&pattern).map_err(|e| format!("Invalid path {path:?}: {e}"))?;
+
+    let mut scan = FileScan::default();
+    for path in paths.flatten() {
+        let Ok(metadata) = std::fs::metadata(&path) else {
+            continue;
+        };
+        if !metadata.is_file() {
+            continue;
+        }
+        let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        scan.count += 1;
+        if scan
+            .newest
+            .as_ref()
+            .map(|(_, newest, _)| modified > *newest)
+            .unwrap_or(true)
+        {
+            scan.newest = Some((path, modified, metadata.len()));
+        }
+    }
+
+    Ok(scan)
+}
+
+/// Hashes a file, giving up if it takes longer than the deadline.
+fn hash_file(path: &Path, deadline: Instant) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0; 64 * 1024];
+    loop {
+        if Instant::now() > deadline {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "timed out",
+            ));
+        }
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// How much of the end of the newest file is matched against `match`.
+const MATCH_LIMIT: u64 = 1024 * 1024;
+
+/// Reads at most the last `limit` bytes of a file, starting at a line boundary if the file is longer than that.
+fn read_tail(path: &Path, limit: u64) -> Result<Vec<u8>, std::io::Error> {
+    let mut file = std::fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    let start = len.saturating_sub(limit);
+    file.seek(SeekFrom::Start(start))?;
+    let mut contents = Vec::new();
+    file.take(limit).read_to_end(&mut contents)?;
+    if start > 0 {
+        // Skip the partial first line so that anchored patterns only match whole lines
+        let line = contents
+            .iter()
+            .position(|b| *b == b'\n')
+            .map_or(0, |i| i + 1);
+        contents.drain(..line);
+    }
+    Ok(contents)
+}
+
+impl FileMonitorMessageProcessorInstance {
+    /// Hashes the newest file, reusing the hash from an earlier run if the file's modification time and size haven't
+    /// changed since.
+    fn hash(&self, path: &Path, modified: SystemTime, size: u64) -> Result<String, String> {
+        let mut cache = self.config.hash_cache.lock().unwrap();
+        if let Some(cached) = cache.as_ref() {
+            if cached.path == path && cached.modified == modified && cached.size == size {
+                return Ok(cached.hash.clone());
+            }
+        }
+        let hash =
+            hash_file(path, self.deadline).map_err(|e| format!("Failed to hash {path:?}: {e}"))?;
+        *cache = Some(HashedFile {
+            path: path.to_owned(),
+            modified,
+            size,
+            hash: hash.clone(),
+        });
+        Ok(hash)
+    }
+
+    fn metadata(&self) -> Result<BTreeMap<String, Value>, String> {
+        let config = &self.config;
+        let scan = scan_files(&config.base_path, &config.path)?;
+
+        let mut metadata = BTreeMap::new();
+        metadata.insert("count".to_string(), Value::Int(scan.count as i64));
+        if let Some((path, modified, size)) = &scan.newest {
+            let age = SystemTime::now()
+                .duration_since(*modified)
+                .unwrap_or_default();
+            let newest = path.strip_prefix(&config.base_path).unwrap_or(path);
+            metadata.insert(
+                "newest".to_string(),
+                Value::Str(newest.to_string_lossy().to_string().into()),
+            );
+            metadata.insert("age_seconds".to_string(), Value::Int(age.as_secs() as i64));
+            metadata.insert("size".to_string(), Value::Int(*size as i64));
+        } else {
+            // Placeholders if nothing matched
+            metadata.insert("newest".to_string(), Value::Str("".into()));
+            metadata.insert("age_seconds".to_string(), Value::Int(-1));
+            metadata.insert("size".to_string(), Value::Int(0));
+        }
+
+        if config.hash {
+            let hash = match &scan.newest {
+                Some((path, modified, size)) => self.hash(path, *modified, *size)?,
+                None => "".to_string(),
+            };
+            metadata.insert("hash".to_string(), Value::Str(hash.into()));
+        }
+
+        if let Some(pattern) = &config.pattern {
+            let regex =
+                Regex::new(pattern).map_err(|e| format!("Invalid match {pattern:?}: {e}"))?;
+            let matches = match &scan.newest {
+                Some((path, ..)) => {
+                    let contents = read_tail(path, MATCH_LIMIT)
+                        .map_err(|e| format!("Failed to read {path:?}: {e}"))?;
+                    regex.is_match(&String::from_utf8_lossy(&contents))
+                }
+                None => false,
+            };
+            metadata.insert("matches".to_string(), Value::from_bool(matches));
+        }
+
+        Ok(metadata)
+    }
+}
+
+impl MonitorMessageProcessorInstance for FileMonitorMessageProcessorInstance {
+    fn process_message(&self, _stream: &LogStream, _input: &str) -> Vec<String> {
+        vec![]
+    }
+
+    fn finalize(&self) -> Vec<String> {
+        let mut result = vec![];
+
+        let metadata = match self.metadata() {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                log::warn!("File monitor failed: {}", e);
+                result.push(format!("status.description={:?}", e));
+                result.push("status.status=\"red\"".to_string());
+                return result;
+            }
+        };
+
+        let context = Layered(&[&metadata, &self.history]);
+        let red = self.config.red.evaluate_bool(&context);
+        let green = self.config.green.evaluate_bool(&context);
+        let blue = self.config.blue.evaluate_bool(&context);
+        let orange = self.config.orange.evaluate_bool(&context);
+        let yellow = self.config.yellow.evaluate_bool(&context);
+
+        // Add metadata to result
+        for (key, value) in &metadata {
+            result.push(format!("status.metadata.{}={:?}", key, value.as_str()));
+        }
+
+        // Determine status based on conditions
+        if red {
+            result.push("status.status=\"red\"".to_string());
+        } else if orange {
+            result.push("status.status=\"orange\"".to_string());
+        } else if yellow {
+            result.push("status.status=\"yellow\"".to_string());
+        } else if blue {
+            result.push("status.status=\"blue\"".to_string());
+        } else if green {
+            result.push("status.status=\"green\"".to_string());
+        } else {
+            result.push("status.status=\"blank\"".to_string());
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn processor(base_path: &Path, path: &str) -> FileMonitorMessageProcessor {
+        FileMonitorMessageProcessor {
+            base_path: base_path.to_owned(),
+            path: path.to_string(),
+            timeout: Duration::from_secs(30),
+            hash: true,
+            hash_cache: Default::default(),
+            pattern: Some("(?m)^done$".to_string()),
+            red: default_red(),
+            green: default_green(),
+            blue: default_blue(),
+            orange: "age_seconds > 3600".parse().unwrap(),
+            yellow: default_yellow(),
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("stylus-file-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_file_scan() {
+        let dir = temp_dir("scan");
+        std::fs::write(dir.join("backup-1.tar"), "old").unwrap();
+        std::fs::write(dir.join("backup-2.tar"), "done\n").unwrap();
+        std::fs::write(dir.join("other.txt"), "ignored").unwrap();
+        let old = SystemTime::now() - Duration::from_secs(7200);
+        std::fs::File::options()
+            .write(true)
+            .open(dir.join("backup-1.tar"))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        let instance = processor(&dir, "backup-*.tar").new(&Default::default());
+        let result = instance.finalize();
+        assert!(result.contains(&"status.metadata.count=\"2\"".to_string()));
+        assert!(result.contains(&"status.metadata.newest=\"backup-2.tar\"".to_string()));
+        assert!(result.contains(&"status.metadata.size=\"5\"".to_string()));
+        assert!(result.contains(&"status.metadata.matches=\"1\"".to_string()));
+        assert!(result.contains(&format!(
+            "status.metadata.hash={:?}",
+            hash_file(
+                &dir.join("backup-2.tar"),
+                Instant::now() + Duration::from_secs(30)
+            )
+            .unwrap()
+        )));
+        assert_eq!(result.last().unwrap(), "status.status=\"green\"");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_missing() {
+        let dir = temp_dir("missing");
+
+        let instance = processor(&dir, "backup-*.tar").new(&Default::default());
+        let result = instance.finalize();
+        assert!(result.contains(&"status.metadata.count=\"0\"".to_string()));
+        assert!(result.contains(&"status.metadata.matches=\"0\"".to_string()));
+        assert_eq!(result.last().unwrap(), "status.status=\"red\"");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_stale() {
+        let dir = temp_dir("stale");
+        std::fs::write(dir.join("backup.tar"), "done").unwrap();
+        let old = SystemTime::now() - Duration::from_secs(7200);
+        std::fs::File::options()
+            .write(true)
+            .open(dir.join("backup.tar"))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        let instance = processor(&dir, "backup.tar").new(&Default::default());
+        let result = instance.finalize();
+        assert_eq!(result.last().unwrap(), "status.status=\"orange\"");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_hash_cache() {
+        let dir = temp_dir("hash");
+        let path = dir.join("backup.tar");
+        std::fs::write(&path, "one").unwrap();
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+
+        let processor = processor(&dir, "backup.tar");
+        let instance = processor.new(&Default::default());
+        let hash = format!(
+            "status.metadata.hash={:?}",
+            hash_file(&path, Instant::now() + Duration::from_secs(30)).unwrap()
+        );
+        assert!(instance.finalize().contains(&hash));
+
+        // The same contents are reported until the modification time or size changes
+        std::fs::write(&path, "two").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert!(processor
+            .new(&Default::default())
+            .finalize()
+            .contains(&hash));
+        std::fs::write(&path, "three").unwrap();
+        assert!(!processor
+            .new(&Default::default())
+            .finalize()
+            .contains(&hash));
+
+        // Hashing gives up once the deadline passes
+        assert_eq!(
+            hash_file(&path, Instant::now() - Duration::from_secs(1))
+                .unwrap_err()
+                .kind(),
+            std::io::ErrorKind::TimedOut
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_tail() {
+        let dir = temp_dir("tail");
+        let path = dir.join("log.txt");
+        std::fs::write(&path, "first\nsecond\nthird\n").unwrap();
+        assert_eq!(read_tail(&path, 1024).unwrap(), b"first\nsecond\nthird\n");
+        // The partial line at the start of the tail is skipped
+        assert_eq!(read_tail(&path, 9).unwrap(), b"third\n");
+        assert_eq!(read_tail(&path, 4).unwrap(), b"");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,2 +1,8 @@
+pub mod composite;
+pub mod discovery;
+pub mod file;
+pub mod nagios;
+pub mod parse;
 pub mod ping;
+pub mod remote;
 pub mod snmp;
@@ -0,0 +1,279 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use crate::monitor::{MonitorMessageProcessor, MonitorMessageProcessorInstance};
+use crate::status::{MonitorHistory, StatusState};
+use crate::worker::LogStream;
+
+/// Maps a Monitoring Plugins exit code to a status and a default description.
+pub fn nagios_status(code: i64) -> (StatusState, &'static str) {
+    match code {
+        0 => (StatusState::Green, "OK"),
+        1 => (StatusState::Orange, "WARNING"),
+        2 => (StatusState::Red, "CRITICAL"),
+        3 => (StatusState::Blue, "UNKNOWN"),
+        _ => (StatusState::Red, "Failed"),
+    }
+}
+
+/// A single performance data item: `'label'=value[UOM];[warn];[crit];[min];[max]`.
+#[derive(Debug, Default, PartialEq)]
+struct PerfData {
+    label: String,
+    value: String,
+    unit: String,
+    warn: String,
+    crit: String,
+    min: String,
+    max: String,
+}
+
+/// Splits perfdata into its items, respecting single-quoted labels.
+fn split_perfdata(input: &str) -> Vec<String> {
+    let mut items = vec![];
+    let mut item = String::new();
+    let mut quoted = false;
+    for c in input.chars() {
+        match c {
+            '\'' => {
+                quoted = !quoted;
+                item.push(c);
+            }
+            c if c.is_whitespace() && !quoted => {
+                if !item.is_empty() {
+                    items.push(std::mem::take(&mut item));
+                }
+            }
+            c => item.push(c),
+        }
+    }
+    if !item.is_empty() {
+        items.push(item);
+    }
+    items
+}
+
+fn parse_perfdata(input: &str) -> Vec<PerfData> {
+    let mut result = vec![];
+    for item in split_perfdata(input) {
+        let Some((label, data)) = item.rsplit_once('=') else {
+            log::warn!("Invalid perfdata: {}", item);
+            continue;
+        };
+        let label = label.trim_matches('\'').replace("''", "'");
+        let mut fields = data.split(';').map(str::to_string);
+        let value = fields.next().unwrap_or_default();
+        let split = value
+            .find(|c: char| !c.is_ascii_digit() && c != '.' && c != '-')
+            .unwrap_or(value.len());
+        let (value, unit) = value.split_at(split);
+        result.push(PerfData {
+            label,
+            value: value.to_string(),
+            unit: unit.to_string(),
+            warn: fields.next().unwrap_or_default(),
+            crit: fields.next().unwrap_or_default(),
+            min: fields.next().unwrap_or_default(),
+            max: fields.next().unwrap_or_default(),
+        });
+    }
+    result
+}
+
+/// The metadata key suffixes of each perfdata item.
+const SUFFIXES: [&str; 6] = ["", "_unit", "_warn", "_crit", "_min", "_max"];
+
+/// Metadata keys are restricted so that they can be used as paths and CSS variables.
+fn metadata_key(label: &str) -> String {
+    label
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct NagiosMessageProcessor {}
+
+#[derive(Debug, Default)]
+pub struct NagiosMessageProcessorInstance {
+    /// Whether we have seen the first line of output yet.
+    first_line: Mutex<bool>,
+    /// The metadata key of each perfdata label seen so far.
+    keys: Mutex<BTreeMap<String, String>>,
+}
+
+impl NagiosMessageProcessorInstance {
+    /// Finds the metadata key of a perfdata label. Labels that map to the same key as an earlier label, or whose keys
+    /// would overlap with its suffixed keys (eg: `a.b` and `a/b`, or `time_max` and `time`), are numbered from `_2`.
+    fn metadata_key(&self, label: &str) -> String {
+        let mut keys = self.keys.lock().unwrap();
+        if let Some(key) = keys.get(label) {
+            return key.clone();
+        }
+        let taken = |key: &str| {
+            keys.values().any(|other| {
+                SUFFIXES.iter().any(|a| {
+                    SUFFIXES
+                        .iter()
+                        .any(|b| format!("{key}{a}") == format!("{other}{b}"))
+                })
+            })
+        };
+        let base = metadata_key(label);
+        let mut key = base.clone();
+        let mut n = 2;
+        while taken(&key) {
+            key = format!("{base}_{n}");
+            n += 1;
+        }
+        if key != base {
+            log::warn!(
+                "Perfdata label {label:?} collides with another label, using metadata key {key:?}"
+            );
+        }
+        keys.insert(label.to_owned(), key.clone());
+        key
+    }
+}
+
+impl MonitorMessageProcessor for NagiosMessageProcessor {
+    fn new(&self, _history: &MonitorHistory) -> Box<dyn MonitorMessageProcessorInstance> {
+        Box::new(NagiosMessageProcessorInstance::default())
+    }
+}
+
+impl MonitorMessageProcessorInstance for NagiosMessageProcessorInstance {
+    fn process_message(&self, stream: &LogStream, input: &str) -> Vec<String> {
+        let mut result = vec![];
+        if *stream != LogStream::StdOut {
+            return result;
+        }
+
+        // The first line is the description, and perfdata may follow a pipe on any line
+        let (text, perfdata) = input.split_once('|').unwrap_or((input, ""));
+        let mut first_line = self.first_line.lock().unwrap();
+        if !*first_line {
+            *first_line = true;
+            result.push(format!(
+                "status.description={}",
+                serde_json::to_string(text.trim()).expect("Failed to serialize string")
+            ));
+        }
+
+        for perfdata in parse_perfdata(perfdata) {
+            let key = self.metadata_key(&perfdata.label);
+            for (suffix, value) in SUFFIXES.into_iter().zip([
+                &perfdata.value,
+                &perfdata.unit,
+                &perfdata.warn,
+                &perfdata.crit,
+                &perfdata.min,
+                &perfdata.max,
+            ]) {
+                if !value.is_empty() {
+                    result.push(format!("status.metadata.{key}{suffix}={:?}", value));
+                }
+            }
+        }
+
+        result
+    }
+
+    fn finalize(&self) -> Vec<String> {
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_perfdata() {
+        assert_eq!(
+            parse_perfdata("/=2643MB;5948;5958;0;5968 'inode usage'=12% time=0.01s;;;0"),
+            vec![
+                PerfData {
+                    label: "/".into(),
+                    value: "2643".into(),
+                    unit: "MB".into(),
+                    warn: "5948".into(),
+                    crit: "5958".into(),
+                    min: "0".into(),
+                    max: "5968".into(),
+                },
+                PerfData {
+                    label: "inode usage".into(),
+                    value: "12".into(),
+                    unit: "%".into(),
+                    ..Default::default()
+                },
+                PerfData {
+                    label: "time".into(),
+                    value: "0.01".into(),
+                    unit: "s".into(),
+                    min: "0".into(),
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nagios_output() {
+        let processor = NagiosMessageProcessor {}.new(&Default::default());
+        let mut result = vec![];
+        for line in [
+            "DISK WARNING - free space: / 3326 MB (56%); | /=2643MB;5948;5958;0;5968\n",
+            "/ 15272 MB (77%);\n",
+            "/boot 68 MB (69%); | /boot=68MB;88;93;0;98\n",
+        ] {
+            result.extend(processor.process_message(&LogStream::StdOut, line));
+        }
+        assert_eq!(
+            result,
+            vec![
+                r#"status.description="DISK WARNING - free space: / 3326 MB (56%);""#,
+                r#"status.metadata._="2643""#,
+                r#"status.metadata.__unit="MB""#,
+                r#"status.metadata.__warn="5948""#,
+                r#"status.metadata.__crit="5958""#,
+                r#"status.metadata.__min="0""#,
+                r#"status.metadata.__max="5968""#,
+                r#"status.metadata._boot="68""#,
+                r#"status.metadata._boot_unit="MB""#,
+                r#"status.metadata._boot_warn="88""#,
+                r#"status.metadata._boot_crit="93""#,
+                r#"status.metadata._boot_min="0""#,
+                r#"status.metadata._boot_max="98""#,
+            ]
+        );
+    }
+    #[test]
+    fn test_nagios_key_collisions() {
+        let processor = NagiosMessageProcessor {}.new(&Default::default());
+        let result = processor.process_message(
+            &LogStream::StdOut,
+            "OK | a.b=1 a/b=2 a.b=3 time=4s time_max=5 time_2=6\n",
+        );
+        assert_eq!(
+            result,
+            vec![
+                r#"status.description="OK""#,
+                r#"status.metadata.a_b="1""#,
+                r#"status.metadata.a_b_2="2""#,
+                r#"status.metadata.a_b="3""#,
+                r#"status.metadata.time="4""#,
+                r#"status.metadata.time_unit="s""#,
+                r#"status.metadata.time_max_2="5""#,
+                r#"status.metadata.time_2="6""#,
+            ]
+        );
+    }
+}
@@ -0,0 +1,375 @@
+use std::sync::{Arc, Mutex};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::monitor::{MonitorMessageProcessor, MonitorMessageProcessorInstance};
+use crate::status::MonitorHistory;
+use crate::worker::LogStream;
+
+/// Built-in parsers for the output of a standard test script.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub enum MonitorDirParseConfig {
+    /// The final JSON document written to stdout describes the status, description, metadata and group children.
+    Json,
+    /// Each `key=value` pair written to stdout sets the status, description or a metadata key.
+    Logfmt,
+    /// Each named capture of these regular expressions sets the status, description or a metadata key.
+    Regex(Vec<String>),
+}
+
+impl MonitorDirParseConfig {
+    pub fn processor(&self) -> Result<Arc<dyn MonitorMessageProcessor>, regex::Error> {
+        Ok(match self {
+            MonitorDirParseConfig::Json => Arc::new(JsonMessageProcessor {}),
+            MonitorDirParseConfig::Logfmt => Arc::new(LogfmtMessageProcessor {}),
+            MonitorDirParseConfig::Regex(patterns) => Arc::new(RegexMessageProcessor {
+                patterns: patterns
+                    .iter()
+                    .map(|p| Regex::new(p))
+                    .collect::<Result<_, _>>()?,
+            }),
+        })
+    }
+}
+
+/// Generates the `@@STYLUS@@`-style update for a key, where the special keys `status` and `description`
+/// update the status and all other keys update the metadata.
+fn update(child: Option<&str>, key: &str, value: &str) -> String {
+    let prefix = match child {
+        Some(child) => format!("group.{child}.status"),
+        None => "status".to_string(),
+    };
+    let value = serde_json::to_string(value).expect("Failed to serialize string");
+    match key {
+        "status" | "description" => format!("{prefix}.{key}={value}"),
+        _ => format!("{prefix}.metadata.{key}={value}"),
+    }
+}
+
+fn json_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => "".to_string(),
+        value => value.to_string(),
+    }
+}
+
+fn json_updates(child: Option<&str>, value: &Value, result: &mut Vec<String>) {
+    let Value::Object(object) = value else {
+        log::warn!("Expected a JSON object, got: {}", value);
+        return;
+    };
+    for (key, value) in object {
+        match (key.as_str(), value) {
+            ("status" | "description", value) => {
+                result.push(update(child, key, &json_to_string(value)))
+            }
+            ("metadata", Value::Object(metadata)) => {
+                for (key, value) in metadata {
+                    result.push(update(child, key, &json_to_string(value)));
+                }
+            }
+            ("children" | "group", Value::Object(children)) if child.is_none() => {
+                for (child, value) in children {
+                    json_updates(Some(child), value, result);
+                }
+            }
+            _ => log::warn!("Unexpected key in JSON output: {}", key),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct JsonMessageProcessor {}
+
+#[derive(Debug, Default)]
+pub struct JsonMessageProcessorInstance {
+    stdout: Mutex<String>,
+}
+
+impl MonitorMessageProcessor for JsonMessageProcessor {
+    fn new(&self, _history: &MonitorHistory) -> Box<dyn MonitorMessageProcessorInstance> {
+        Box::new(JsonMessageProcessorInstance::default())
+    }
+}
+
+impl MonitorMessageProcessorInstance for JsonMessageProcessorInstance {
+    fn process_message(&self, stream: &LogStream, input: &str) -> Vec<String> {
+        if *stream == LogStream::StdOut {
+            self.stdout.lock().unwrap().push_str(input);
+        }
+        vec![]
+    }
+
+    fn finalize(&self) -> Vec<String> {
+        let stdout = std::mem::take(&mut *self.stdout.lock().unwrap());
+
+        // Use the last document in the output if there are multiple. When something that isn't JSON is printed (eg: a
+        // banner or progress line), skip to the next line that could start a document and keep going from there.
+        let mut last = None;
+        let mut start = 0;
+        while start < stdout.len() {
+            let mut values =
+                serde_json::Deserializer::from_str(&stdout[start..]).into_iter::<Value>();
+            let failed = loop {
+                match values.next() {
+                    Some(Ok(value)) => last = Some(value),
+                    Some(Err(e)) => break Some(e),
+                    None => break None,
+                }
+            };
+            let Some(e) = failed else {
+                break;
+            };
+            let failed_at = start + values.byte_offset();
+            log::debug!("Skipping output that isn't JSON: {}", e);
+            start = stdout[failed_at..]
+                .match_indices('\n')
+                .map(|(i, _)| failed_at + i + 1)
+                .find(|&i| stdout[i..].trim_start().starts_with(['{', '[']))
+                .unwrap_or(stdout.len());
+        }
+        if last.is_none() && !stdout.trim().is_empty() {
+            log::warn!("No JSON document found in output");
+        }
+
+        let mut result = vec![];
+        if let Some(value) = last {
+            json_updates(None, &value, &mut result);
+        }
+        result
+    }
+}
+
+/// Parses a line of logfmt-style `key=value key="quoted value"` pairs.
+fn parse_logfmt(input: &str) -> Vec<(String, String)> {
+    let mut pairs = vec![];
+    let mut chars = input.trim().chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let mut key = String::new();
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != '=') {
+            key.push(c);
+        }
+        if key.is_empty() && chars.peek().is_none() {
+            break;
+        }
+        let mut value = String::new();
+        if chars.next_if_eq(&'=').is_some() {
+            if chars.next_if_eq(&'"').is_some() {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => value.extend(chars.next()),
+                        c => value.push(c),
+                    }
+                }
+            } else {
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    value.push(c);
+                }
+            }
+        }
+        if !key.is_empty() {
+            pairs.push((key, value));
+        }
+    }
+    pairs
+}
+
+#[derive(Debug)]
+pub struct LogfmtMessageProcessor {}
+
+#[derive(Debug)]
+pub struct LogfmtMessageProcessorInstance {}
+
+impl MonitorMessageProcessor for LogfmtMessageProcessor {
+    fn new(&self, _history: &MonitorHistory) -> Box<dyn MonitorMessageProcessorInstance> {
+        Box::new(LogfmtMessageProcessorInstance {})
+    }
+}
+
+impl MonitorMessageProcessorInstance for LogfmtMessageProcessorInstance {
+    fn process_message(&self, stream: &LogStream, input: &str) -> Vec<String> {
+        if *stream != LogStream::StdOut {
+            return vec![];
+        }
+        parse_logfmt(input)
+            .into_iter()
+            .map(|(key, value)| update(None, &key, &value))
+            .collect()
+    }
+
+    fn finalize(&self) -> Vec<String> {
+        vec![]
+    }
+}
+
+#[derive(Debug)]
+pub struct RegexMessageProcessor {
+    patterns: Vec<Regex>,
+}
+
+#[derive(Debug)]
+pub struct RegexMessageProcessorInstance {
+    patterns: Vec<Regex>,
+}
+
+impl MonitorMessageProcessor for RegexMessageProcessor {
+    fn new(&self, _history: &MonitorHistory) -> Box<dyn MonitorMessageProcessorInstance> {
+        Box::new(RegexMessageProcessorInstance {
+            patterns: self.patterns.clone(),
+        })
+    }
+}
+
+impl MonitorMessageProcessorInstance for RegexMessageProcessorInstance {
+    fn process_message(&self, stream: &LogStream, input: &str) -> Vec<String> {
+        let mut result = vec![];
+        if *stream != LogStream::StdOut {
+            return result;
+        }
+        let input = input.trim_end();
+        for pattern in &self.patterns {
+            let Some(captures) = pattern.captures(input) else {
+                continue;
+            };
+            // The special `child` capture routes the update to a group child
+            let child = captures.name("child").map(|m| m.as_str());
+            for name in pattern.capture_names().flatten() {
+                if name == "child" {
+                    continue;
+                }
+                if let Some(value) = captures.name(name) {
+                    result.push(update(child, name, value.as_str()));
+                }
+            }
+        }
+        result
+    }
+
+    fn finalize(&self) -> Vec<String> {
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(config: MonitorDirParseConfig, output: &[&str]) -> Vec<String> {
+        let processor = config.processor().unwrap().new(&Default::default());
+        let mut result = vec![];
+        for line in output {
+            result.extend(processor.process_message(&LogStream::StdOut, line));
+            result.extend(processor.process_message(&LogStream::StdErr, "+ set -x noise=1\n"));
+        }
+        result.extend(processor.finalize());
+        result
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let result = run(
+            MonitorDirParseConfig::Json,
+            &[
+                "{\"status\": \"red\"}\n",
+                "{\"status\": \"yellow\", \"description\": \"Disk \\\"almost\\\" full\",\n",
+                " \"metadata\": {\"used\": 91, \"mount\": \"/\"},\n",
+                " \"children\": {\"disk-1\": {\"status\": \"green\"}}}\n",
+            ],
+        );
+        assert_eq!(
+            result,
+            vec![
+                r#"group.disk-1.status.status="green""#,
+                r#"status.description="Disk \"almost\" full""#,
+                r#"status.metadata.mount="/""#,
+                r#"status.metadata.used="91""#,
+                r#"status.status="yellow""#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_json_after_log_lines() {
+        let result = run(
+            MonitorDirParseConfig::Json,
+            &[
+                "Connecting to backup server...\n",
+                "{\"status\": \"red\"}\n",
+                "[1/2] {not json}\n",
+                "{\n",
+                "  \"status\": \"green\",\n",
+                "  \"children\": {\"a\": {\"metadata\": {\"items\": [\n",
+                "    {\"x\": 1}\n",
+                "  ]}}}\n",
+                "}\n",
+                "done\n",
+            ],
+        );
+        assert_eq!(
+            result,
+            vec![
+                r#"group.a.status.metadata.items="[{\"x\":1}]""#,
+                r#"status.status="green""#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_logfmt() {
+        assert_eq!(
+            parse_logfmt("a=1 b=\"two words\" c= d e=\"\\\"q\\\"\"\n"),
+            vec![
+                ("a".to_string(), "1".to_string()),
+                ("b".to_string(), "two words".to_string()),
+                ("c".to_string(), "".to_string()),
+                ("d".to_string(), "".to_string()),
+                ("e".to_string(), "\"q\"".to_string()),
+            ]
+        );
+
+        let result = run(
+            MonitorDirParseConfig::Logfmt,
+            &["status=orange temp=71\n", "description=\"Running hot\"\n"],
+        );
+        assert_eq!(
+            result,
+            vec![
+                r#"status.status="orange""#,
+                r#"status.metadata.temp="71""#,
+                r#"status.description="Running hot""#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_regex() {
+        let result = run(
+            MonitorDirParseConfig::Regex(vec![
+                r"^Temperature: (?P<temp>\d+)C$".to_string(),
+                r"^(?P<child>port-\d+) is (?P<status>red|green)$".to_string(),
+            ]),
+            &["Temperature: 42C\n", "port-1 is red\n", "unrelated\n"],
+        );
+        assert_eq!(
+            result,
+            vec![
+                r#"status.metadata.temp="42""#,
+                r#"group.port-1.status.status="red""#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_regex() {
+        assert!(MonitorDirParseConfig::Regex(vec!["(".to_string()])
+            .processor()
+            .is_err());
+    }
+}
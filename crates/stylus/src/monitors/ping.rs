@@ -1,16 +1,20 @@
 use std::{
     collections::BTreeMap,
+    error::Error,
     path::PathBuf,
     sync::{Arc, RwLock},
     time::Duration,
 };
 
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
 
 use crate::{
     config::MonitorDirTestConfig,
-    expressions::{self, Value},
+    expressions::{self, Expression, Layered, Value},
     monitor::{MonitorMessageProcessor, MonitorMessageProcessorInstance},
+    status::MonitorHistory,
+    worker::LogStream,
 };
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -27,15 +31,15 @@ pub struct PingMonitorConfig {
     #[serde(default = "default_count")]
     pub count: u32,
     #[serde(default = "default_red")]
-    pub red: String,
+    pub red: Expression,
     #[serde(default = "default_green")]
-    pub green: String,
+    pub green: Expression,
     #[serde(default = "default_blue")]
-    pub blue: String,
+    pub blue: Expression,
     #[serde(default = "default_orange")]
-    pub orange: String,
+    pub orange: Expression,
     #[serde(default = "default_yellow")]
-    pub yellow: String,
+    pub yellow: Expression,
     #[serde(skip_deserializing)]
     pub test: Option<MonitorDirTestConfig>,
 }
@@ -48,27 +52,56 @@ fn default_count() -> u32 {
     1
 }
 
-fn default_red() -> String {
-    "lost == count".to_string()
+fn default_red() -> Expression {
+    "lost == count".parse().unwrap()
 }
 
-fn default_green() -> String {
-    "lost == 0".to_string()
+fn default_green() -> Expression {
+    "lost == 0".parse().unwrap()
 }
 
-fn default_blue() -> String {
-    "false".to_string()
+fn default_blue() -> Expression {
+    "false".parse().unwrap()
 }
 
-fn default_orange() -> String {
-    "lost > 0 or (lost == 0 and rtt_max > warning_timeout)".to_string()
+fn default_orange() -> Expression {
+    "lost > 0 or (lost == 0 and rtt_max > warning_timeout)"
+        .parse()
+        .unwrap()
 }
 
-fn default_yellow() -> String {
-    "false".to_string()
+fn default_yellow() -> Expression {
+    "false".parse().unwrap()
 }
 
 impl PingMonitorConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        expressions::validate_identifiers(
+            &[
+                ("red", &self.red),
+                ("green", &self.green),
+                ("blue", &self.blue),
+                ("orange", &self.orange),
+                ("yellow", &self.yellow),
+            ],
+            &[
+                "count",
+                "lost",
+                "rtt_avg",
+                "rtt_min",
+                "rtt_max",
+                "warning_timeout",
+                "rtt_avg_seconds",
+                "rtt_min_seconds",
+                "rtt_max_seconds",
+                "warning_timeout_seconds",
+            ]
+            .into_iter()
+            .chain(MonitorHistory::VARIABLES)
+            .collect::<Vec<_>>(),
+        )
+    }
+
     pub fn test(&self) -> MonitorDirTestConfig {
         let args = vec![
             "ping".to_string(),
@@ -91,6 +124,7 @@ impl PingMonitorConfig {
                 orange: self.orange.clone(),
                 yellow: self.yellow.clone(),
             })),
+            ..Default::default()
         }
     }
 }
@@ -99,27 +133,28 @@ impl PingMonitorConfig {
 pub struct PingMonitorMessageProcessor {
     count: u32,
     warning_timeout: Duration,
-    red: String,
-    green: String,
-    blue: String,
-    orange: String,
-    yellow: String,
+    red: Expression,
+    green: Expression,
+    blue: Expression,
+    orange: Expression,
+    yellow: Expression,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct PingMonitorMessageProcessorInstance {
     count: u32,
     warning_timeout: Duration,
-    red: String,
-    green: String,
-    blue: String,
-    orange: String,
-    yellow: String,
+    red: Expression,
+    green: Expression,
+    blue: Expression,
+    orange: Expression,
+    yellow: Expression,
+    history: BTreeMap<String, Value>,
     ping_output: RwLock<Vec<usize>>,
 }
 
 impl MonitorMessageProcessor for PingMonitorMessageProcessor {
-    fn new(&self) -> Box<dyn MonitorMessageProcessorInstance> {
+    fn new(&self, history: &MonitorHistory) -> Box<dyn MonitorMessageProcessorInstance> {
         Box::new(PingMonitorMessageProcessorInstance {
             count: self.count,
             warning_timeout: self.warning_timeout,
@@ -128,13 +163,14 @@ impl MonitorMessageProcessor for PingMonitorMessageProcessor {
             blue: self.blue.clone(),
             orange: self.orange.clone(),
             yellow: self.yellow.clone(),
+            history: history.variables(Utc::now()),
             ping_output: RwLock::new(Vec::new()),
         })
     }
 }
 
 impl MonitorMessageProcessorInstance for PingMonitorMessageProcessorInstance {
-    fn process_message(&self, input: &str) -> Vec<String> {
+    fn process_message(&self, _stream: &LogStream, input: &str) -> Vec<String> {
         // Store ping output lines for processing in finalize
         if let Ok(mut output) = self.ping_output.write() {
             if let Some(rtt) = parse_ping_output(input) {
@@ -149,25 +185,23 @@ impl MonitorMessageProcessorInstance for PingMonitorMessageProcessorInstance {
 
         // Parse the actual ping output
         let output = &*self.ping_output.read().unwrap();
-        let rtt_us_avg;
-        let rtt_us_min;
-        let rtt_us_max;
         let lost = self.count.saturating_sub(output.len() as u32) as _;
-        if output.is_empty() {
+        let (rtt_us_avg, rtt_us_min, rtt_us_max) = if output.is_empty() {
             // Placeholder RTT if all pings timed out
-            rtt_us_avg = Duration::from_secs(60).as_micros() as _;
-            rtt_us_min = Duration::from_secs(60).as_micros();
-            rtt_us_max = Duration::from_secs(60).as_micros();
+            let placeholder = Duration::from_secs(60).as_micros() as usize;
+            (placeholder, placeholder, placeholder)
         } else {
-            rtt_us_avg = output.iter().sum::<usize>() / output.len();
-            rtt_us_min = *output.iter().min().unwrap() as u128;
-            rtt_us_max = *output.iter().max().unwrap() as u128;
-        }
+            (
+                output.iter().sum::<usize>() / output.len(),
+                *output.iter().min().unwrap(),
+                *output.iter().max().unwrap(),
+            )
+        };
 
         let mut metadata = BTreeMap::new();
         metadata.insert("count".to_string(), Value::Int(self.count as i64));
         metadata.insert("lost".to_string(), Value::Int(lost));
-        // Convert RTT to integer milliseconds for comparison
+        // Times are in integer microseconds
         metadata.insert("rtt_avg".to_string(), Value::Int(rtt_us_avg as i64));
         metadata.insert("rtt_min".to_string(), Value::Int(rtt_us_min as i64));
         metadata.insert("rtt_max".to_string(), Value::Int(rtt_us_max as i64));
@@ -175,12 +209,22 @@ impl MonitorMessageProcessorInstance for PingMonitorMessageProcessorInstance {
             "warning_timeout".to_string(),
             Value::Int(self.warning_timeout.as_micros() as i64),
         );
+        // ...and in seconds, the same as duration literals like `250ms`
+        let seconds = |us: usize| Value::Float(us as f64 / 1_000_000.0);
+        metadata.insert("rtt_avg_seconds".to_string(), seconds(rtt_us_avg));
+        metadata.insert("rtt_min_seconds".to_string(), seconds(rtt_us_min));
+        metadata.insert("rtt_max_seconds".to_string(), seconds(rtt_us_max));
+        metadata.insert(
+            "warning_timeout_seconds".to_string(),
+            Value::Float(self.warning_timeout.as_secs_f64()),
+        );
 
-        let red = calculate_bool(&self.red, &metadata);
-        let green = calculate_bool(&self.green, &metadata);
-        let blue = calculate_bool(&self.blue, &metadata);
-        let orange = calculate_bool(&self.orange, &metadata);
-        let yellow = calculate_bool(&self.yellow, &metadata);
+        let context = Layered(&[&metadata, &self.history]);
+        let red = self.red.evaluate_bool(&context);
+        let green = self.green.evaluate_bool(&context);
+        let blue = self.blue.evaluate_bool(&context);
+        let orange = self.orange.evaluate_bool(&context);
+        let yellow = self.yellow.evaluate_bool(&context);
 
         // Add metadata to result
         for (key, value) in &metadata {
@@ -231,23 +275,10 @@ fn parse_ping_output(line: &str) -> Option<usize> {
     None
 }
 
-fn calculate_bool(expression: &str, metadata: &BTreeMap<String, Value>) -> bool {
-    match expressions::expression::calculate(expression, metadata) {
-        Ok(Ok(value)) => value.as_bool(),
-        Err(e) => {
-            log::warn!("Failed to parse expression {:?}: {}", expression, e);
-            false
-        }
-        Ok(Err(e)) => {
-            log::warn!("Failed to evaluate expression {:?}: {:?}", expression, e);
-            false
-        }
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::status::StatusState;
 
     const LINUX_OUTPUT: &str = r#"
 PING 8.8.8.8 (8.8.8.8) 56(84) bytes of data.
@@ -363,4 +394,79 @@ Reply from 8.8.8.8: bytes=1500 time=<1 ms TTL=54
         expect_pings(LINUX_OUTPUT_WITH_LOSS, vec![]);
         expect_pings(MACOS_OUTPUT_WITH_LOSS, vec![]);
     }
+
+    fn finalize(output: &str, orange: Expression, history: &MonitorHistory) -> Vec<String> {
+        let processor = PingMonitorMessageProcessor {
+            count: 3,
+            warning_timeout: Duration::from_millis(500),
+            red: default_red(),
+            green: default_green(),
+            blue: default_blue(),
+            orange,
+            yellow: default_yellow(),
+        };
+        let instance = processor.new(history);
+        for line in output.lines() {
+            instance.process_message(&LogStream::StdOut, line);
+        }
+        instance.finalize()
+    }
+
+    #[test]
+    fn test_ping_rtt_seconds() {
+        let history = MonitorHistory::default();
+        let result = finalize(
+            LINUX_OUTPUT,
+            "rtt_max_seconds > 250ms".parse().unwrap(),
+            &history,
+        );
+        assert!(
+            result.contains(&"status.metadata.rtt_max_seconds=\"0.0197\"".to_string()),
+            "{result:?}"
+        );
+        assert!(result.contains(&"status.metadata.warning_timeout_seconds=\"0.5\"".to_string()));
+        // The microsecond values from earlier releases are still available
+        assert!(result.contains(&"status.metadata.rtt_max=\"19700\"".to_string()));
+        assert!(result.contains(&"status.metadata.warning_timeout=\"500000\"".to_string()));
+        assert_eq!(result.last().unwrap(), "status.status=\"green\"");
+
+        let result = finalize(
+            LINUX_OUTPUT,
+            "rtt_max_seconds > 19ms".parse().unwrap(),
+            &history,
+        );
+        assert_eq!(result.last().unwrap(), "status.status=\"orange\"");
+        let result = finalize(LINUX_OUTPUT, "rtt_max > 19000".parse().unwrap(), &history);
+        assert_eq!(result.last().unwrap(), "status.status=\"orange\"");
+        let result = finalize(LINUX_OUTPUT, default_orange(), &history);
+        assert_eq!(result.last().unwrap(), "status.status=\"green\"");
+    }
+
+    #[test]
+    fn test_ping_history() {
+        let orange: Expression = "previous_status == 'red' and last_change_age >= 60"
+            .parse()
+            .unwrap();
+        let history = MonitorHistory {
+            status: Some(StatusState::Red),
+            last_change: Some(Utc::now() - chrono::Duration::minutes(5)),
+            ..Default::default()
+        };
+        let result = finalize(LINUX_OUTPUT, orange.clone(), &history);
+        assert_eq!(result.last().unwrap(), "status.status=\"orange\"");
+        // The history variables aren't reported as metadata
+        assert!(!result.iter().any(|line| line.contains("previous_status")));
+
+        let result = finalize(LINUX_OUTPUT, orange, &MonitorHistory::default());
+        assert_eq!(result.last().unwrap(), "status.status=\"green\"");
+
+        let config: PingMonitorConfig = serde_json::from_value(serde_json::json!({
+            "host": "localhost",
+            "interval": "60s",
+            "timeout": "30s",
+            "yellow": "hour < 8 or weekday > 5",
+        }))
+        .unwrap();
+        config.validate().unwrap();
+    }
 }
@@ -0,0 +1,230 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use subprocess::{Exec, Redirection};
+
+use crate::config::{MonitorDirAxisValue, MonitorDirTestConfig};
+use crate::status::MonitorStatus;
+
+/// Imports the monitors of another Stylus instance by polling its `/status.json` and `/config.json`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct RemoteMonitorConfig {
+    /// The base URL of the remote instance, such as `http://home.lan:8000`.
+    pub url: String,
+    /// Prepended to the ID of every imported monitor and group child (default: the monitor ID and a dash).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+    #[serde(skip_deserializing)]
+    pub test: Option<MonitorDirTestConfig>,
+    /// The IDs of the local monitors and group children, which imported monitors may not use.
+    #[serde(skip)]
+    pub reserved: BTreeSet<String>,
+}
+
+/// The configuration versions of a remote instance that can be imported.
+const SUPPORTED_VERSIONS: [u32; 1] = [1];
+
+/// The parts of a remote `/config.json` that are checked before importing.
+#[derive(Clone, Debug, Deserialize)]
+struct RemoteConfig {
+    version: u32,
+}
+
+/// The parts of a remote `/status.json` that are imported.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct RemoteStatus {
+    pub monitors: Vec<RemoteMonitorState>,
+    /// The IDs that were skipped because they are already used locally.
+    #[serde(skip)]
+    pub skipped: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RemoteMonitorState {
+    pub id: String,
+    /// The test configuration of the remote monitor. This is kept as JSON so that a remote running another version
+    /// of Stylus can still be imported.
+    #[serde(default)]
+    pub config: Option<serde_json::Value>,
+    #[serde(default)]
+    pub status: Option<MonitorStatus>,
+    #[serde(default)]
+    pub children: BTreeMap<String, RemoteChildState>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RemoteChildState {
+    #[serde(default)]
+    pub axes: BTreeMap<String, MonitorDirAxisValue>,
+    #[serde(default)]
+    pub status: Option<MonitorStatus>,
+}
+
+impl RemoteMonitorConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if !self.url.starts_with("http://") && !self.url.starts_with("https://") {
+            return Err(format!("Remote URL {:?} must be http:// or https://", self.url).into());
+        }
+        Ok(())
+    }
+
+    /// The URL of the remote's status.
+    pub fn status_url(&self) -> String {
+        format!("{}/status.json", self.url.trim_end_matches('/'))
+    }
+
+    /// The URL of the remote's configuration.
+    pub fn config_url(&self) -> String {
+        format!("{}/config.json", self.url.trim_end_matches('/'))
+    }
+
+    pub fn test(&self) -> MonitorDirTestConfig {
+        self.curl(self.status_url())
+    }
+
+    /// Fetches a URL of the remote instance with `curl`.
+    fn curl(&self, url: String) -> MonitorDirTestConfig {
+        MonitorDirTestConfig {
+            command: PathBuf::from("/usr/bin/env"),
+            args: vec![
+                "curl".to_string(),
+                "--silent".to_string(),
+                "--show-error".to_string(),
+                "--fail".to_string(),
+                "--max-time".to_string(),
+                self.timeout.as_secs_f64().to_string(),
+                url,
+            ],
+            interval: self.interval,
+            timeout: self.timeout,
+            ..Default::default()
+        }
+    }
+
+    /// Fetches the remote status, prefixing the ID of every monitor and group child and skipping the ones that are
+    /// already used locally.
+    pub fn fetch(&self, id: &str) -> Result<RemoteStatus, Box<dyn Error>> {
+        let output = run_command(&self.curl(self.config_url()))?;
+        let config: RemoteConfig = serde_json::from_str(&output)
+            .map_err(|e| format!("Invalid config from {}: {e}", self.config_url()))?;
+        if !SUPPORTED_VERSIONS.contains(&config.version) {
+            return Err(format!(
+                "Unsupported config version {} from {}",
+                config.version,
+                self.config_url()
+            )
+            .into());
+        }
+
+        let test = self.test.clone().unwrap_or_else(|| self.test());
+        let output = run_command(&test)?;
+        let mut status: RemoteStatus = serde_json::from_str(&output)
+            .map_err(|e| format!("Invalid status from {}: {e}", self.status_url()))?;
+        let prefix = self.prefix.clone().unwrap_or_else(|| format!("{id}-"));
+        status.add_prefix(&prefix);
+        status.skipped = status.remove_reserved(&self.reserved);
+        if !status.skipped.is_empty() {
+            log::warn!(
+                "[{id}] Skipped imported monitors with local IDs: {}",
+                status.skipped.join(", ")
+            );
+        }
+        Ok(status)
+    }
+}
+
+impl RemoteStatus {
+    /// Removes the monitors and group children whose IDs are reserved, returning their IDs.
+    pub fn remove_reserved(&mut self, reserved: &BTreeSet<String>) -> Vec<String> {
+        let mut removed = vec![];
+        self.monitors.retain_mut(|monitor| {
+            if reserved.contains(&monitor.id) {
+                removed.push(monitor.id.clone());
+                return false;
+            }
+            monitor.children.retain(|id, _| {
+                let keep = !reserved.contains(id);
+                if !keep {
+                    removed.push(id.clone());
+                }
+                keep
+            });
+            true
+        });
+        removed
+    }
+
+    /// Prefixes the ID of every monitor and group child.
+    pub fn add_prefix(&mut self, prefix: &str) {
+        for monitor in &mut self.monitors {
+            monitor.id = format!("{prefix}{}", monitor.id);
+            monitor.children = std::mem::take(&mut monitor.children)
+                .into_iter()
+                .map(|(id, child)| (format!("{prefix}{id}"), child))
+                .collect();
+        }
+    }
+}
+
+fn run_command(test: &MonitorDirTestConfig) -> Result<String, Box<dyn Error>> {
+    let mut popen = Exec::cmd(&test.command)
+        .args(&test.args)
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .popen()?;
+    let result = popen
+        .communicate_start(None)
+        .limit_time(test.timeout)
+        .read();
+    let (stdout, stderr) = match result {
+        Ok(output) => output,
+        Err(e) => {
+            let _ = popen.kill();
+            return Err(format!("Remote fetch failed: {}", e.error).into());
+        }
+    };
+    match popen.wait_timeout(Duration::from_millis(250))? {
+        Some(status) if status.success() => {}
+        Some(_) => {
+            let stderr = String::from_utf8_lossy(&stderr.unwrap_or_default()).into_owned();
+            return Err(format!("Remote fetch failed: {}", stderr.trim()).into());
+        }
+        None => {
+            let _ = popen.kill();
+            return Err("Remote fetch timed out".into());
+        }
+    }
+    Ok(String::from_utf8_lossy(&stdout.unwrap_or_default()).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_url() {
+        let config = |url: &str| RemoteMonitorConfig {
+            url: url.to_string(),
+            prefix: None,
+            interval: Duration::from_secs(30),
+            timeout: Duration::from_secs(10),
+            test: None,
+            reserved: BTreeSet::new(),
+        };
+        assert_eq!(
+            config("http://home:8000/").status_url(),
+            "http://home:8000/status.json"
+        );
+        assert!(config("https://vps.example.com/stylus").validate().is_ok());
+        assert!(config("file:///etc/passwd").validate().is_err());
+    }
+}
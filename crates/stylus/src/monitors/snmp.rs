@@ -1,20 +1,24 @@
 use std::{
     borrow::Cow,
     collections::{BTreeMap, HashMap},
+    error::Error,
     path::PathBuf,
     sync::{Arc, Mutex},
     time::Duration,
 };
 
+use chrono::{DateTime, Utc};
 use rasn_smi::rasn::types::{ObjectIdentifier, Oid};
 use rasn_smi::ObjectType;
 use serde::{Deserialize, Serialize};
 
 use crate::{
     config::{MonitorDirAxisValue, MonitorDirChildConfig, MonitorDirTestConfig},
-    expressions::{self, Value},
+    expressions::{self, Expression, Layered, Value},
     interpolate::interpolate_id,
     monitor::{MonitorMessageProcessor, MonitorMessageProcessorInstance},
+    status::MonitorHistory,
+    worker::LogStream,
 };
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -28,51 +32,53 @@ pub struct SnmpNetworkMonitorConfig {
     #[serde(with = "humantime_serde")]
     pub timeout: Duration,
     #[serde(default = "default_include")]
-    pub include: String,
+    pub include: Expression,
     #[serde(default = "default_exclude")]
-    pub exclude: String,
+    pub exclude: Expression,
     #[serde(default = "default_red")]
-    pub red: String,
+    pub red: Expression,
     #[serde(default = "default_green")]
-    pub green: String,
+    pub green: Expression,
     #[serde(default = "default_blue")]
-    pub blue: String,
+    pub blue: Expression,
     #[serde(default = "default_orange")]
-    pub orange: String,
+    pub orange: Expression,
     #[serde(default = "default_yellow")]
-    pub yellow: String,
+    pub yellow: Expression,
     #[serde(skip_deserializing)]
     pub children: BTreeMap<String, MonitorDirChildConfig>,
     #[serde(skip_deserializing)]
     pub test: Option<MonitorDirTestConfig>,
 }
 
-fn default_include() -> String {
-    format!("true")
+fn default_include() -> Expression {
+    "true".parse().unwrap()
 }
 
-fn default_exclude() -> String {
-    format!("false")
+fn default_exclude() -> Expression {
+    "false".parse().unwrap()
 }
 
-fn default_red() -> String {
-    format!("false")
+fn default_red() -> Expression {
+    "false".parse().unwrap()
 }
 
-fn default_green() -> String {
-    format!("ifOperStatus == 'up' and ifAdminStatus == 'up'")
+fn default_green() -> Expression {
+    "ifOperStatus == 'up' and ifAdminStatus == 'up'"
+        .parse()
+        .unwrap()
 }
 
-fn default_blue() -> String {
-    format!("false")
+fn default_blue() -> Expression {
+    "false".parse().unwrap()
 }
 
-fn default_orange() -> String {
-    format!("false")
+fn default_orange() -> Expression {
+    "false".parse().unwrap()
 }
 
-fn default_yellow() -> String {
-    format!("false")
+fn default_yellow() -> Expression {
+    "false".parse().unwrap()
 }
 
 const OID_MAP: &[(&str, &Oid, &[(u32, &str)])] = &[
@@ -441,6 +447,26 @@ const ENUM_MAP_TYPE: &[(u32, &str)] = &[
 ];
 
 impl SnmpNetworkMonitorConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let known = OID_MAP
+            .iter()
+            .map(|(name, ..)| *name)
+            .chain(MonitorHistory::VARIABLES)
+            .collect::<Vec<_>>();
+        expressions::validate_identifiers(
+            &[
+                ("include", &self.include),
+                ("exclude", &self.exclude),
+                ("red", &self.red),
+                ("green", &self.green),
+                ("blue", &self.blue),
+                ("orange", &self.orange),
+                ("yellow", &self.yellow),
+            ],
+            &known,
+        )
+    }
+
     pub fn test(&self) -> MonitorDirTestConfig {
         let binary = if self.target.bulk {
             "snmpbulkwalk"
@@ -542,6 +568,7 @@ impl SnmpNetworkMonitorConfig {
                 orange: self.orange.clone(),
                 yellow: self.yellow.clone(),
             })),
+            ..Default::default()
         }
     }
 }
@@ -580,30 +607,32 @@ fn default_bulk() -> bool {
 #[derive(Debug)]
 pub struct SnmpMonitorMessageProcessor {
     id: String,
-    include: String,
-    exclude: String,
-    red: String,
-    green: String,
-    blue: String,
-    orange: String,
-    yellow: String,
+    include: Expression,
+    exclude: Expression,
+    red: Expression,
+    green: Expression,
+    blue: Expression,
+    orange: Expression,
+    yellow: Expression,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct SnmpMonitorMessageProcessorInstance {
     id: String,
-    include: String,
-    exclude: String,
-    red: String,
-    green: String,
-    blue: String,
-    orange: String,
-    yellow: String,
+    include: Expression,
+    exclude: Expression,
+    red: Expression,
+    green: Expression,
+    blue: Expression,
+    orange: Expression,
+    yellow: Expression,
+    history: MonitorHistory,
+    now: DateTime<Utc>,
     ports: Mutex<BTreeMap<usize, HashMap<String, Value>>>,
 }
 
 impl MonitorMessageProcessor for SnmpMonitorMessageProcessor {
-    fn new(&self) -> Box<dyn MonitorMessageProcessorInstance> {
+    fn new(&self, history: &MonitorHistory) -> Box<dyn MonitorMessageProcessorInstance> {
         Box::new(SnmpMonitorMessageProcessorInstance {
             id: self.id.clone(),
             include: self.include.clone(),
@@ -613,6 +642,8 @@ impl MonitorMessageProcessor for SnmpMonitorMessageProcessor {
             blue: self.blue.clone(),
             orange: self.orange.clone(),
             yellow: self.yellow.clone(),
+            history: history.clone(),
+            now: Utc::now(),
             ports: Default::default(),
         })
     }
@@ -704,7 +735,7 @@ fn parse_snmp_line(input: &str) -> Option<(&'static str, u32, Value)> {
 }
 
 impl MonitorMessageProcessorInstance for SnmpMonitorMessageProcessorInstance {
-    fn process_message(&self, input: &str) -> Vec<String> {
+    fn process_message(&self, _stream: &LogStream, input: &str) -> Vec<String> {
         // Parse the input as <oid>.index = <valud>?
         // Note that value may be missing and input may end in the equals. This is considered an empty string.
         let mut result = vec![];
@@ -744,22 +775,6 @@ impl MonitorMessageProcessorInstance for SnmpMonitorMessageProcessorInstance {
         let mut result = vec![];
 
         for (port_index, port_metadata) in std::mem::take(&mut *self.ports.lock().unwrap()) {
-            let include = calculate_bool(&self.include, &port_metadata);
-            if !include {
-                continue;
-            }
-
-            let exclude = calculate_bool(&self.exclude, &port_metadata);
-            if exclude {
-                continue;
-            }
-
-            let red = calculate_bool(&self.red, &port_metadata);
-            let green = calculate_bool(&self.green, &port_metadata);
-            let blue = calculate_bool(&self.blue, &port_metadata);
-            let orange = calculate_bool(&self.orange, &port_metadata);
-            let yellow = calculate_bool(&self.yellow, &port_metadata);
-
             let mut values = BTreeMap::new();
             values.insert(
                 "index".into(),
@@ -774,6 +789,26 @@ impl MonitorMessageProcessorInstance for SnmpMonitorMessageProcessorInstance {
                 continue;
             };
 
+            // Each port sees the history of its own child
+            let history = self.history.child(&port_id).variables(self.now);
+            let context = Layered(&[&port_metadata, &history]);
+
+            let include = self.include.evaluate_bool(&context);
+            if !include {
+                continue;
+            }
+
+            let exclude = self.exclude.evaluate_bool(&context);
+            if exclude {
+                continue;
+            }
+
+            let red = self.red.evaluate_bool(&context);
+            let green = self.green.evaluate_bool(&context);
+            let blue = self.blue.evaluate_bool(&context);
+            let orange = self.orange.evaluate_bool(&context);
+            let yellow = self.yellow.evaluate_bool(&context);
+
             if red {
                 result.push(format!("group.{}.status.status=\"red\"", port_id));
             } else if orange {
@@ -793,20 +828,6 @@ impl MonitorMessageProcessorInstance for SnmpMonitorMessageProcessorInstance {
     }
 }
 
-fn calculate_bool(expression: &str, metadata: &HashMap<String, Value>) -> bool {
-    match expressions::expression::calculate(expression, metadata) {
-        Ok(Ok(value)) => value.as_bool(),
-        Err(e) => {
-            log::warn!("Failed to parse expression {:?}: {}", expression, e);
-            false
-        }
-        Ok(Err(e)) => {
-            log::warn!("Failed to evaluate expression {:?}: {:?}", expression, e);
-            false
-        }
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
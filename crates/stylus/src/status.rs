@@ -1,14 +1,24 @@
 use std::collections::{BTreeMap, VecDeque};
 use std::error::Error;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use chrono::{DateTime, Datelike, Local, Timelike, Utc};
 use keepcalm::SharedMut;
 use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
 
 use crate::config::*;
-use crate::interpolate::interpolate_modify;
+use crate::expressions::Value;
+use crate::interpolate::{interpolate_modify, CssTemplates};
+use crate::monitors::nagios::nagios_status;
+use crate::monitors::remote::RemoteStatus;
 use crate::worker::LogStream;
 use crate::worker::WorkerMessage;
+use crate::worker::TIMED_OUT;
+
+/// The number of log lines buffered for a stream that is slow to read them.
+const LOG_STREAM_CAPACITY: usize = 1024;
 
 #[derive(Clone, Copy, Debug, Display, Serialize, Deserialize, PartialEq, PartialOrd)]
 #[serde(rename_all(serialize = "lowercase", deserialize = "lowercase"))]
@@ -35,6 +45,34 @@ pub struct MonitorState {
     #[serde(skip)]
     pub css: Option<String>,
     pub children: BTreeMap<String, MonitorChildStatus>,
+    /// When the current run started.
+    #[serde(skip)]
+    pub started: Option<Instant>,
+    /// How the status of a group is computed from its children.
+    #[serde(skip)]
+    pub rollup: Option<MonitorDirRollupConfig>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// The monitor type, which CSS rules can match on.
+    #[serde(skip)]
+    pub kind: &'static str,
+    /// The monitor's own CSS rules.
+    #[serde(skip)]
+    pub css_rules: Vec<CssRule>,
+    #[serde(skip)]
+    pub css_templates: CssTemplates,
+    /// The read-only monitors imported from a remote instance.
+    #[serde(skip)]
+    pub imported: Vec<SharedMut<MonitorState>>,
+    /// Whether a run is in progress.
+    #[serde(skip)]
+    pub running: bool,
+    /// The number of runs that have finished.
+    #[serde(skip)]
+    pub finished: u64,
+    /// Sends each log line of the current run to the streams following it, and is closed when the run ends.
+    #[serde(skip)]
+    pub log_stream: Option<broadcast::Sender<String>>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
@@ -43,6 +81,15 @@ pub struct MonitorChildStatus {
 
     #[serde(skip_serializing_if = "MonitorStatus::is_uninitialized")]
     pub status: MonitorStatus,
+    /// Whether this child was found by axis discovery.
+    #[serde(skip)]
+    pub discovered: bool,
+    /// When this child vanished from the discovered axis values.
+    #[serde(skip)]
+    pub missing_since: Option<Instant>,
+    /// Whether this child has already finished its own run, in per-child mode.
+    #[serde(skip)]
+    pub finished: bool,
 }
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
@@ -52,6 +99,18 @@ pub struct MonitorStatus {
     pub description: String,
     pub css: MonitorCssStatus,
     pub metadata: BTreeMap<String, String>,
+    /// When the status last changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_change: Option<DateTime<Utc>>,
+    /// When the status was last green.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_success: Option<DateTime<Utc>>,
+    /// When the status was last red.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_failure: Option<DateTime<Utc>>,
+    /// The number of children in each state, for groups.
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub child_counts: Option<MonitorChildCounts>,
     #[serde(skip)]
     pub log: VecDeque<String>,
     #[serde(skip)]
@@ -65,6 +124,100 @@ pub struct MonitorPendingStatus {
     pub metadata: Option<BTreeMap<String, String>>,
 }
 
+/// The number of children of a group in each state after a run.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct MonitorChildCounts {
+    pub children: usize,
+    pub children_green: usize,
+    pub children_yellow: usize,
+    pub children_orange: usize,
+    pub children_red: usize,
+    pub children_blue: usize,
+    pub children_blank: usize,
+}
+
+impl MonitorChildCounts {
+    fn count(children: &BTreeMap<String, MonitorChildStatus>) -> Self {
+        let mut counts = MonitorChildCounts::default();
+        for child in children.values() {
+            counts.children += 1;
+            *match child.status.status.unwrap_or(StatusState::Blank) {
+                StatusState::Blank => &mut counts.children_blank,
+                StatusState::Green => &mut counts.children_green,
+                StatusState::Yellow => &mut counts.children_yellow,
+                StatusState::Red => &mut counts.children_red,
+                StatusState::Blue => &mut counts.children_blue,
+                StatusState::Orange => &mut counts.children_orange,
+            } += 1;
+        }
+        counts
+    }
+
+    pub fn get(&self, status: StatusState) -> usize {
+        match status {
+            StatusState::Blank => self.children_blank,
+            StatusState::Green => self.children_green,
+            StatusState::Yellow => self.children_yellow,
+            StatusState::Red => self.children_red,
+            StatusState::Blue => self.children_blue,
+            StatusState::Orange => self.children_orange,
+        }
+    }
+
+    /// The counts by name, as used by rollup rules and CSS templates.
+    pub fn variables(&self) -> [(&'static str, usize); 7] {
+        [
+            ("children", self.children),
+            ("children_green", self.children_green),
+            ("children_yellow", self.children_yellow),
+            ("children_orange", self.children_orange),
+            ("children_red", self.children_red),
+            ("children_blue", self.children_blue),
+            ("children_blank", self.children_blank),
+        ]
+    }
+
+    /// The variables available to rollup rules: the counts by name, with `<state>_count` aliases for each state
+    /// (eg: `red_count` for `children_red`).
+    pub fn context(&self) -> BTreeMap<String, Value> {
+        self.variables()
+            .into_iter()
+            .flat_map(|(name, count)| {
+                let alias = name
+                    .strip_prefix("children_")
+                    .map(|state| format!("{state}_count"));
+                std::iter::once(name.to_string())
+                    .chain(alias)
+                    .map(move |name| (name, Value::Int(count as i64)))
+            })
+            .collect()
+    }
+
+    /// Describes the counts, most severe first: `1 red, 3 green`.
+    fn description(&self) -> String {
+        [
+            StatusState::Red,
+            StatusState::Orange,
+            StatusState::Yellow,
+            StatusState::Blue,
+            StatusState::Green,
+            StatusState::Blank,
+        ]
+        .into_iter()
+        .filter(|status| self.get(*status) > 0)
+        .map(|status| format!("{} {}", self.get(status), status.to_string().to_lowercase()))
+        .collect::<Vec<_>>()
+        .join(", ")
+    }
+}
+
+/// Status rules and the facts about a run that they are evaluated over.
+pub struct MonitorRules<'a> {
+    pub rules: &'a MonitorDirRulesConfig,
+    pub duration: Duration,
+    pub timed_out: bool,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MonitorCssStatus {
     pub metadata: Arc<BTreeMap<String, String>>,
@@ -87,9 +240,74 @@ impl MonitorState {
             status: Default::default(),
             css: None,
             children: Default::default(),
+            started: None,
+            rollup: None,
+            tags: vec![],
+            kind: "test",
+            css_rules: vec![],
+            css_templates: Default::default(),
+            imported: vec![],
+            running: false,
+            finished: 0,
+            log_stream: None,
+        }
+    }
+
+    /// The state of an agent that pushes its results, which holds the monitors it has pushed.
+    pub fn new_agent(id: String, stale_after: Duration, config: &CssMetadataConfig) -> Self {
+        let test = MonitorDirTestConfig {
+            interval: stale_after,
+            timeout: stale_after,
+            ..Default::default()
+        };
+        let mut state = MonitorState::new_internal(id, test);
+        state.kind = "agent";
+        state.status.initialize(config);
+        state
+    }
+
+    /// Marks an agent and its monitors as stale if it hasn't pushed its results recently, returning whether
+    /// anything changed.
+    pub fn expire_agent(&mut self, stale_after: Duration, config: &CssMetadataConfig) -> bool {
+        let pushed = self
+            .started
+            .is_some_and(|started| started.elapsed() < stale_after);
+        if pushed || self.status.status != Some(StatusState::Green) {
+            return false;
+        }
+        let message = format!("No results pushed for {stale_after:?}");
+        self.process_log_message("exec  ", &message, &mut |_| {});
+        self.finish(StatusState::Yellow, -1, message, false, false, config);
+        true
+    }
+
+    /// The history of the monitor and its children before the next run.
+    pub fn history(&self) -> MonitorHistory {
+        MonitorHistory {
+            children: self
+                .children
+                .iter()
+                .map(|(id, child)| (id.clone(), child.status.history()))
+                .collect(),
+            ..self.status.history()
         }
     }
 
+    /// Follows the log of the current run, or of the next run if none is in progress. Returns the lines already
+    /// logged by the current run, and a receiver for the rest that is closed after the termination line.
+    pub fn follow_log(&mut self) -> (Vec<String>, broadcast::Receiver<String>) {
+        let logged = if self.running {
+            self.status.log.iter().cloned().collect()
+        } else {
+            vec![]
+        };
+        let receiver = self
+            .log_stream
+            .get_or_insert_with(|| broadcast::channel(LOG_STREAM_CAPACITY).0)
+            .subscribe();
+        (logged, receiver)
+    }
+
     fn process_log_message<T: FnMut(&str)>(
         &mut self,
         stream: &str,
@@ -103,6 +321,9 @@ impl MonitorState {
             message
         );
         direct_logger(&msg);
+        if let Some(stream) = &self.log_stream {
+            let _ = stream.send(msg.clone());
+        }
         self.status.log.push_back(msg);
     }
 
@@ -119,6 +340,8 @@ impl MonitorState {
                 // Note that we don't update the state here
                 self.status.pending = None;
                 self.status.log.clear();
+                self.started = Some(Instant::now());
+                self.running = true;
                 self.process_log_message("exec  ", "Starting".into(), direct_logger);
             }
             WorkerMessage::LogMessage(stream, m) => {
@@ -142,9 +365,51 @@ impl MonitorState {
                     self.process_log_message("meta  ", &expr.to_string(), direct_logger);
                 }
             }
+            WorkerMessage::Discovered(found, grace) => {
+                self.process_log_message(
+                    "exec  ",
+                    &format!("Discovered {} children", found.len()),
+                    direct_logger,
+                );
+                let now = Instant::now();
+                self.children.retain(|id, child| {
+                    if !child.discovered || found.contains_key(id) {
+                        return true;
+                    }
+                    let missing_since = *child.missing_since.get_or_insert(now);
+                    now.duration_since(missing_since) < grace
+                });
+                for (id, axes) in found {
+                    let child = self.children.entry(id).or_insert_with(|| {
+                        let mut status = MonitorStatus::default();
+                        status.initialize(config);
+                        MonitorChildStatus {
+                            axes,
+                            status,
+                            ..Default::default()
+                        }
+                    });
+                    child.discovered = true;
+                    child.missing_since = None;
+                }
+                self.css = None;
+            }
+            WorkerMessage::Child(child, msg) => {
+                self.process_child_message(id, child, *msg, config, direct_logger)?;
+            }
+            WorkerMessage::Remote(remote) => {
+                self.process_log_message(
+                    "exec  ",
+                    &format!("Imported {} monitors", remote.monitors.len()),
+                    direct_logger,
+                );
+                self.import_remote(*remote, config);
+            }
             WorkerMessage::AbnormalTermination(s) => {
                 self.process_log_message("exec  ", &format!("Termination: {}", s), direct_logger);
-                self.finish(StatusState::Yellow, -1, s, config);
+                self.end_log_stream();
+                let timed_out = s == TIMED_OUT;
+                self.finish(StatusState::Yellow, -1, s, false, timed_out, config);
             }
             WorkerMessage::Termination(code) => {
                 self.process_log_message(
@@ -152,47 +417,277 @@ impl MonitorState {
                     &format!("Termination: {}", code),
                     direct_logger,
                 );
-                if code == 0 {
-                    self.finish(StatusState::Green, code, "Success".into(), config);
-                } else {
-                    self.finish(StatusState::Red, code, "Failed".into(), config);
+                self.end_log_stream();
+                let (status, description, normal) = self.termination_status(code);
+                self.finish(status, code, description.into(), normal, false, config);
+            }
+        }
+        Ok(())
+    }
+
+    /// Ends the run, closing the streams following its log once they have received the termination line.
+    fn end_log_stream(&mut self) {
+        self.running = false;
+        self.finished += 1;
+        self.log_stream = None;
+    }
+
+    /// Replaces the monitors imported from a remote instance, keeping the states of those still present.
+    fn import_remote(&mut self, remote: RemoteStatus, config: &CssMetadataConfig) {
+        let mut existing: BTreeMap<_, _> = std::mem::take(&mut self.imported)
+            .into_iter()
+            .map(|state| {
+                let id = state.read().id.clone();
+                (id, state)
+            })
+            .collect();
+        let imported_status = |status: Option<MonitorStatus>| {
+            let mut status = status.unwrap_or_else(|| {
+                let mut status = MonitorStatus::default();
+                status.initialize(config);
+                status
+            });
+            // Imported monitors are styled with the local metadata
+            status.update_css(config);
+            status
+        };
+
+        let count = remote.monitors.len();
+        for monitor in remote.monitors {
+            // Imported monitors describe their own test, or a neutral one if it can't be read
+            let test = monitor
+                .config
+                .and_then(|config| serde_json::from_value(config).ok())
+                .unwrap_or_default();
+            let state = existing.remove(&monitor.id).unwrap_or_else(|| {
+                let mut state = MonitorState::new_internal(monitor.id.clone(), Default::default());
+                state.kind = self.kind;
+                state.tags = self.tags.clone();
+                SharedMut::new(state)
+            });
+            {
+                let mut state = state.write();
+                state.config = test;
+                state.status = imported_status(monitor.status);
+                state.children = monitor
+                    .children
+                    .into_iter()
+                    .map(|(id, child)| {
+                        let child = MonitorChildStatus {
+                            axes: child.axes,
+                            status: imported_status(child.status),
+                            ..Default::default()
+                        };
+                        (id, child)
+                    })
+                    .collect();
+                state.css = None;
+            }
+            self.imported.push(state);
+        }
+
+        self.status
+            .pending
+            .get_or_insert_with(MonitorPendingStatus::default)
+            .description = Some(if remote.skipped.is_empty() {
+            format!("Imported {count} monitors")
+        } else {
+            format!(
+                "Imported {count} monitors, skipped {} with local IDs: {}",
+                remote.skipped.len(),
+                remote.skipped.join(", ")
+            )
+        });
+    }
+
+    /// Blanks out the imported monitors when the remote instance can't be reached, keeping their last status
+    /// timestamps.
+    fn mark_imported_stale(&mut self, config: &CssMetadataConfig) {
+        for state in &self.imported {
+            let mut state = state.write();
+            let state = &mut *state;
+            for status in std::iter::once(&mut state.status)
+                .chain(state.children.values_mut().map(|child| &mut child.status))
+            {
+                status.status = Some(StatusState::Blank);
+                status.description = "Stale (remote unreachable)".into();
+                status.update_css(config);
+            }
+            state.css = None;
+        }
+    }
+
+    /// Process a message from the run of a single child, which can only update that child.
+    fn process_child_message<T: FnMut(&str)>(
+        &mut self,
+        id: &str,
+        child: String,
+        msg: WorkerMessage,
+        config: &CssMetadataConfig,
+        direct_logger: &mut T,
+    ) -> Result<(), Box<dyn Error>> {
+        match msg {
+            WorkerMessage::Starting => {
+                if let Some(child) = self.children.get_mut(&child) {
+                    child.status.pending = None;
                 }
             }
+            WorkerMessage::LogMessage(stream, m) => {
+                let m = format!("[{}] {}", child, m);
+                self.process_message(
+                    id,
+                    WorkerMessage::LogMessage(stream, m),
+                    config,
+                    direct_logger,
+                )?;
+            }
+            WorkerMessage::Metadata(expr) => {
+                let expr = match expr.strip_prefix("status.") {
+                    Some(expr) => format!("group.{child}.status.{expr}"),
+                    None => format!("group.{child}.{expr}"),
+                };
+                self.process_message(id, WorkerMessage::Metadata(expr), config, direct_logger)?;
+            }
+            WorkerMessage::AbnormalTermination(s) => {
+                self.process_log_message(
+                    "exec  ",
+                    &format!("[{}] Termination: {}", child, s),
+                    direct_logger,
+                );
+                self.finish_child(&child, StatusState::Yellow, -1, s, false, config);
+            }
+            WorkerMessage::Termination(code) => {
+                self.process_log_message(
+                    "exec  ",
+                    &format!("[{}] Termination: {}", child, code),
+                    direct_logger,
+                );
+                let (status, description, normal) = self.termination_status(code);
+                self.finish_child(&child, status, code, description.into(), normal, config);
+            }
+            msg => return Err(format!("Unexpected message from child {child}: {msg:?}").into()),
         }
         Ok(())
     }
 
+    /// Maps an exit code to a status and description, and whether the process terminated normally.
+    fn termination_status(&self, code: i64) -> (StatusState, &'static str, bool) {
+        if self.config.nagios && (0..=3).contains(&code) {
+            // Monitoring Plugins report their state through the exit code
+            let (status, description) = nagios_status(code);
+            (status, description, true)
+        } else if code == 0 {
+            (StatusState::Green, "Success", true)
+        } else if self.config.nagios {
+            let (status, description) = nagios_status(code);
+            (status, description, false)
+        } else {
+            (StatusState::Red, "Failed", false)
+        }
+    }
+
+    /// Finish the run of a single child in per-child mode.
+    fn finish_child(
+        &mut self,
+        child: &str,
+        status: StatusState,
+        code: i64,
+        description: String,
+        normal: bool,
+        config: &CssMetadataConfig,
+    ) {
+        self.css = None;
+        if let Some(child) = self.children.get_mut(child) {
+            child
+                .status
+                .finish(status, code, description, normal, None, config);
+            child.finished = true;
+        }
+    }
+
+    /// Set the status of a composite monitor, which has no runs of its own.
+    pub fn update_composite(
+        &mut self,
+        status: StatusState,
+        description: String,
+        config: &CssMetadataConfig,
+    ) {
+        if self.status.status != Some(status) || self.status.description != description {
+            self.finish(status, 0, description, true, false, config);
+        }
+    }
+
+    /// Finish a run with the given status. If the process terminated normally, any pending status,
+    /// description and metadata reported by the process are applied.
     fn finish(
         &mut self,
         status: StatusState,
         code: i64,
         description: String,
+        normal: bool,
+        timed_out: bool,
         config: &CssMetadataConfig,
     ) {
         self.css = None;
 
         for mut child in std::mem::take(&mut self.children) {
+            if std::mem::take(&mut child.1.finished) {
+                self.children.insert(child.0, child.1);
+                continue;
+            }
             let child_status = &mut child.1.status;
             if child_status.is_pending_status_set() || status != StatusState::Green {
-                child_status.finish(status, code, description.clone(), config);
+                child_status.finish(status, code, description.clone(), normal, None, config);
+                self.children.insert(child.0, child.1);
+            } else if child.1.discovered {
+                // Discovered children are kept until they vanish, but have no status from this run
+                child_status.pending = None;
+                child_status.initialize(config);
                 self.children.insert(child.0, child.1);
             }
         }
 
-        self.status.finish(status, code, description, config);
+        // Groups take their status from their children if they have a rollup policy
+        let child_counts = MonitorChildCounts::count(&self.children);
+        let (status, description) = match self.rollup.as_ref().filter(|_| normal) {
+            Some(rollup) => match rollup.evaluate(&child_counts) {
+                Some(status) => (status, child_counts.description()),
+                None => (status, description),
+            },
+            None => (status, description),
+        };
+
+        let rules = self.config.rules.as_ref().map(|rules| MonitorRules {
+            rules,
+            duration: self.started.map(|s| s.elapsed()).unwrap_or_default(),
+            timed_out,
+        });
+        self.status
+            .finish(status, code, description, normal, rules.as_ref(), config);
+        self.status.child_counts = (!self.children.is_empty()).then_some(child_counts);
+
+        if !normal {
+            self.mark_imported_stale(config);
+        }
     }
 }
 
 impl From<&MonitorDirConfig> for MonitorState {
     fn from(other: &MonitorDirConfig) -> Self {
         let mut state = MonitorState::new_internal(other.id.clone(), other.root.test().clone());
+        state.tags = other.tags.clone();
+        state.kind = other.root.kind();
+        state.css_rules = other.css.clone();
+        state.css_templates = other.css_templates.clone();
         if let MonitorDirRootConfig::Group(ref group) = other.root {
+            state.rollup = group.rollup.clone();
             for child in group.children.iter() {
                 state.children.insert(
                     child.0.clone(),
                     MonitorChildStatus {
                         axes: child.1.axes.clone(),
                         status: MonitorStatus::default(),
+                        ..Default::default()
                     },
                 );
             }
@@ -201,7 +696,75 @@ impl From<&MonitorDirConfig> for MonitorState {
     }
 }
 
+/// The status of a monitor before a run, which provides the time and history variables to the expressions of every
+/// monitor type.
+#[derive(Clone, Debug, Default)]
+pub struct MonitorHistory {
+    pub status: Option<StatusState>,
+    pub last_change: Option<DateTime<Utc>>,
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_failure: Option<DateTime<Utc>>,
+    /// The history of each group child.
+    pub children: BTreeMap<String, MonitorHistory>,
+}
+
+impl MonitorHistory {
+    /// The names of the variables from [`MonitorHistory::variables`].
+    pub const VARIABLES: [&'static str; 7] = [
+        "previous_status",
+        "now",
+        "hour",
+        "weekday",
+        "last_change_age",
+        "last_success_age",
+        "last_failure_age",
+    ];
+
+    /// The time, the status before the run, and how long ago the status last changed, was green and was red.
+    pub fn variables(&self, now: DateTime<Utc>) -> BTreeMap<String, Value> {
+        // Ages are in seconds, or -1 if it has never happened
+        let age = |time: Option<DateTime<Utc>>| {
+            Value::Int(time.map(|t| (now - t).num_seconds()).unwrap_or(-1))
+        };
+        let local = now.with_timezone(&Local);
+        BTreeMap::from([
+            ("previous_status".to_string(), status_value(self.status)),
+            ("now".to_string(), Value::Int(now.timestamp())),
+            ("hour".to_string(), Value::Int(local.hour() as i64)),
+            (
+                "weekday".to_string(),
+                Value::Int(local.weekday().number_from_monday() as i64),
+            ),
+            ("last_change_age".to_string(), age(self.last_change)),
+            ("last_success_age".to_string(), age(self.last_success)),
+            ("last_failure_age".to_string(), age(self.last_failure)),
+        ])
+    }
+
+    /// The history of a group child, which is blank if the child hasn't run yet.
+    pub fn child(&self, id: &str) -> MonitorHistory {
+        self.children.get(id).cloned().unwrap_or_default()
+    }
+}
+
+/// A status as the lowercase string that expressions compare against.
+fn status_value(status: Option<StatusState>) -> Value {
+    let status = status.unwrap_or(StatusState::Blank).to_string();
+    Value::Str(status.to_lowercase().into())
+}
+
 impl MonitorStatus {
+    /// The history of this status, without any children.
+    pub fn history(&self) -> MonitorHistory {
+        MonitorHistory {
+            status: self.status,
+            last_change: self.last_change,
+            last_success: self.last_success,
+            last_failure: self.last_failure,
+            children: BTreeMap::new(),
+        }
+    }
+
     pub fn initialize(&mut self, config: &CssMetadataConfig) {
         self.description = "Unknown (initializing)".into();
         self.status = Some(StatusState::Blank);
@@ -229,8 +792,11 @@ impl MonitorStatus {
         status: StatusState,
         code: i64,
         description: String,
+        normal: bool,
+        rules: Option<&MonitorRules>,
         config: &CssMetadataConfig,
     ) {
+        let previous = self.status;
         let (pending_status, pending_description, pending_metadata) = self
             .pending
             .take()
@@ -243,8 +809,11 @@ impl MonitorStatus {
         self.description = description;
         self.metadata.clear();
 
+        // Rules see the metadata the run reported, even if it failed
+        let reported = rules.and(pending_metadata.clone()).unwrap_or_default();
+
         // Metadata/status can only be overwritten if the process terminated normally
-        if status == StatusState::Green {
+        if normal {
             if let Some(metadata) = pending_metadata {
                 self.metadata = metadata;
             }
@@ -256,7 +825,61 @@ impl MonitorStatus {
             }
         }
 
-        // Update the CSS metadata with the final status
+        let now = Utc::now();
+        if let Some(rules) = rules {
+            let context = self.rules_context(rules, &reported, previous, now);
+            if let Some(status) = rules.rules.evaluate(&context) {
+                self.status = Some(status);
+            }
+        }
+
+        if self.status != previous {
+            self.last_change = Some(now);
+        }
+        match self.status {
+            Some(StatusState::Green) => self.last_success = Some(now),
+            Some(StatusState::Red) => self.last_failure = Some(now),
+            _ => {}
+        }
+
+        self.update_css(config);
+    }
+
+    /// The variables available to status rules: the result of the run, the metadata reported by the run and the
+    /// [`MonitorHistory`] variables.
+    fn rules_context(
+        &self,
+        rules: &MonitorRules,
+        metadata: &BTreeMap<String, String>,
+        previous: Option<StatusState>,
+        now: DateTime<Utc>,
+    ) -> BTreeMap<String, Value> {
+        let mut context = BTreeMap::new();
+        for (key, value) in metadata {
+            context.insert(key.clone(), Value::from_metadata(value));
+        }
+
+        context.insert("exit_code".to_string(), Value::Int(self.code));
+        context.insert(
+            "duration_ms".to_string(),
+            Value::Int(rules.duration.as_millis() as i64),
+        );
+        context.insert(
+            "duration".to_string(),
+            Value::Float(rules.duration.as_secs_f64()),
+        );
+        context.insert("timed_out".to_string(), Value::from_bool(rules.timed_out));
+        context.insert("status".to_string(), status_value(self.status));
+        let history = MonitorHistory {
+            status: previous,
+            ..self.history()
+        };
+        context.extend(history.variables(now));
+        context
+    }
+
+    /// Update the CSS metadata to match the status.
+    fn update_css(&mut self, config: &CssMetadataConfig) {
         if let Some(status) = self.status {
             self.css.metadata = match status {
                 StatusState::Blank => config.blank.clone(),
@@ -0,0 +1,6 @@
+#!/bin/bash
+set -xeuf -o pipefail
+echo '@@STYLUS@@ group.host-10.0.0.1.status.status="green"'
+echo '@@STYLUS@@ group.host-10.0.0.10.status.status="red"'
+echo '@@STYLUS@@ group.host-10.0.0.10.status.metadata.rtt="0.5"'
+echo '@@STYLUS@@ status.status="green"'
@@ -0,0 +1,6 @@
+#!/bin/bash
+set -xeuf -o pipefail
+echo '# Disks'
+echo 'sda'
+echo 'sdb'
+echo 'sdc'
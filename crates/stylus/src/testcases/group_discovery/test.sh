@@ -0,0 +1,5 @@
+#!/bin/bash
+set -xeuf -o pipefail
+echo '@@STYLUS@@ group.disk-sda.status.status="green"'
+echo '@@STYLUS@@ group.disk-sdb.status.status="red"'
+# No status for disk-sdc
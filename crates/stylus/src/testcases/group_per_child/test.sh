@@ -0,0 +1,11 @@
+#!/bin/bash
+set -xeuf -o pipefail
+[ "$1" == "--port" ] && [ "$2" == "$STYLUS_AXIS_INDEX" ]
+case "$STYLUS_AXIS_INDEX" in
+    1) exit 0 ;;
+    2) exit 1 ;;
+    3)
+        echo '@@STYLUS@@ status.status="yellow"'
+        echo "@@STYLUS@@ status.description=\"Port $2 is slow\""
+        ;;
+esac
@@ -0,0 +1,4 @@
+#!/bin/bash
+set -xeuf -o pipefail
+echo 'DISK WARNING - free space: /boot 68 MB (69%); | boot=68MB;88;93;0;98'
+exit 1
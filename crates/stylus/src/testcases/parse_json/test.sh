@@ -0,0 +1,13 @@
+#!/bin/bash
+set -xeuf -o pipefail
+cat <<JSON
+{
+    "status": "yellow",
+    "description": "Custom (yellow)",
+    "metadata": { "ports": 2 },
+    "children": {
+        "port-0": { "status": "green" },
+        "port-1": { "status": "red", "description": "Port down" }
+    }
+}
+JSON
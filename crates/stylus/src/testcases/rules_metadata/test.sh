@@ -0,0 +1,3 @@
+#!/bin/bash
+set -xeuf -o pipefail
+echo '@@STYLUS@@ status.metadata.temperature="91.5"'
@@ -0,0 +1,3 @@
+#!/bin/bash
+set -xeuf -o pipefail
+exit 0
@@ -0,0 +1,4 @@
+#!/bin/bash
+set -xeuf -o pipefail
+echo '@@STYLUS@@ status.metadata.temperature="50"'
+sleep 10
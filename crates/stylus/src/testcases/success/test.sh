@@ -0,0 +1,3 @@
+#!/bin/bash
+set -xeuf -o pipefail
+echo "All good"
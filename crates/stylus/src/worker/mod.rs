@@ -1,7 +1,8 @@
+use std::collections::BTreeMap;
 use std::error::Error;
-use std::ffi::{OsStr, OsString};
 use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 
@@ -9,14 +10,18 @@ use subprocess::{Exec, ExitStatus, Popen, Redirection};
 
 use self::linebuf::LineBuf;
 use crate::config::*;
+use crate::interpolate::interpolate_id;
 use crate::monitor::MonitorMessageProcessorInstance;
+use crate::monitors::discovery::MonitorDirAxesFromConfig;
+use crate::monitors::remote::RemoteStatus;
+use crate::status::MonitorHistory;
 
 mod linebuf;
 
 #[derive(Debug, Default, Display, Error)]
 pub struct ShuttingDown {}
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum LogStream {
     StdOut,
     StdErr,
@@ -29,14 +34,31 @@ pub enum WorkerMessage {
     Metadata(String),
     Termination(i64),
     AbnormalTermination(String),
+    /// The children discovered for a group, and how long vanished children are kept.
+    Discovered(
+        BTreeMap<String, BTreeMap<String, MonitorDirAxisValue>>,
+        Duration,
+    ),
+    /// A message from the run of a single child of a group, in per-child mode.
+    Child(String, Box<WorkerMessage>),
+    /// The monitors fetched from a remote instance.
+    Remote(Box<RemoteStatus>),
 }
 
+/// The reason given for an [`WorkerMessage::AbnormalTermination`] when a process exceeds its timeout.
+pub const TIMED_OUT: &str = "Process timed out";
+
+/// Runs the monitor every interval until the sender fails. Each message on `trigger` wakes the thread for an early
+/// run, and triggers that arrive before a run starts are coalesced into it.
 pub fn monitor_thread<T: FnMut(&str, WorkerMessage) -> Result<(), Box<dyn Error>>>(
     monitor: &MonitorDirConfig,
+    trigger: mpsc::Receiver<()>,
+    history: impl Fn() -> MonitorHistory,
     mut sender: T,
 ) {
     loop {
-        let (interval, res) = monitor_run(&monitor, &mut sender);
+        while trigger.try_recv().is_ok() {}
+        let (interval, res) = monitor_run(&monitor, &history(), &mut sender);
         if let Err(err) = res {
             // Break the loop on a task failure (but don't log ShuttingDown errors)
             if err.downcast_ref::<ShuttingDown>().is_none() {
@@ -53,38 +75,194 @@ pub fn monitor_thread<T: FnMut(&str, WorkerMessage) -> Result<(), Box<dyn Error>
         }
 
         trace!("[{}] Sleeping {}ms", monitor.id, interval.as_millis());
-        thread::sleep(interval);
+        match trigger.recv_timeout(interval) {
+            Ok(()) => debug!("[{}] Triggered", monitor.id),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            // The monitor is shutting down, which the next run will notice
+            Err(mpsc::RecvTimeoutError::Disconnected) => thread::sleep(interval),
+        }
     }
 }
 
+/// Runs the monitor once. The `history` is the state of the monitor before the run, which is made available to the
+/// monitor's expressions.
 pub fn monitor_run<T: FnMut(&str, WorkerMessage) -> Result<(), Box<dyn Error>>>(
     monitor: &MonitorDirConfig,
+    history: &MonitorHistory,
     sender: &mut T,
 ) -> (Duration, Result<(), Box<dyn Error>>) {
-    let processor = monitor.root.test().processor.as_ref().map(|p| p.new());
+    (
+        monitor.root.test().interval,
+        monitor_run_once(monitor, history, sender),
+    )
+}
+
+fn monitor_run_once<T: FnMut(&str, WorkerMessage) -> Result<(), Box<dyn Error>>>(
+    monitor: &MonitorDirConfig,
+    history: &MonitorHistory,
+    sender: &mut T,
+) -> Result<(), Box<dyn Error>> {
+    // This will fail if we're supposed to shut down
+    sender(&monitor.id, WorkerMessage::Starting)?;
+
+    if let MonitorDirRootConfig::Composite(_) = &monitor.root {
+        return Err(
+            "Composite monitors are evaluated from other monitors and cannot be run".into(),
+        );
+    }
+
+    if let MonitorDirRootConfig::Remote(remote) = &monitor.root {
+        let status = remote.fetch(&monitor.id)?;
+        sender(&monitor.id, WorkerMessage::Remote(Box::new(status)))?;
+        return sender(&monitor.id, WorkerMessage::Termination(0));
+    }
+
+    let test = monitor.root.test();
+    if let MonitorDirRootConfig::Group(group) = &monitor.root {
+        let discovered = if let Some(axes_from) = &group.axes_from {
+            Some(discover_children(
+                &monitor.id,
+                &monitor.base_path,
+                group,
+                axes_from,
+                test.timeout,
+                sender,
+            )?)
+        } else {
+            None
+        };
+        if let Some(per_child) = &group.per_child {
+            let children = match discovered {
+                Some(discovered) => discovered.into_iter().collect(),
+                None => group
+                    .children
+                    .iter()
+                    .map(|(id, child)| (id.clone(), child.axes.clone()))
+                    .collect(),
+            };
+            run_children(monitor, per_child, children, history, sender)?;
+            return sender(&monitor.id, WorkerMessage::Termination(0));
+        }
+    }
+
+    let processor = test.processor.as_ref().map(|p| p.new(history));
     let processor = processor.as_deref();
 
-    let args = monitor
-        .root
-        .test()
+    let exec = Exec::cmd(&test.command)
+        .cwd(&monitor.base_path)
+        .args(&test.args);
+    monitor_thread_impl(&monitor.id, exec, test.timeout, sender, processor)
+}
+
+/// Discovers the children of a group, combining the discovered values with any static axes.
+fn discover_children<T: FnMut(&str, WorkerMessage) -> Result<(), Box<dyn Error>>>(
+    id: &str,
+    base_path: &Path,
+    group: &MonitorDirGroupConfig,
+    axes_from: &MonitorDirAxesFromConfig,
+    timeout: Duration,
+    sender: &mut T,
+) -> Result<BTreeMap<String, BTreeMap<String, MonitorDirAxisValue>>, Box<dyn Error>> {
+    let discovered = axes_from.discover(base_path, timeout)?;
+    let mut children = BTreeMap::new();
+    for axes in expand_axes(&group.axes) {
+        for values in &discovered {
+            let mut axes = axes.clone();
+            axes.extend(values.clone());
+            children.insert(interpolate_id(&axes, &group.id)?, axes);
+        }
+    }
+    sender(
+        id,
+        WorkerMessage::Discovered(children.clone(), axes_from.grace),
+    )?;
+    Ok(children)
+}
+
+/// Runs the test once for each child of a group, with at most `concurrency` runs at a time.
+fn run_children<T: FnMut(&str, WorkerMessage) -> Result<(), Box<dyn Error>>>(
+    monitor: &MonitorDirConfig,
+    per_child: &MonitorDirPerChildConfig,
+    children: Vec<(String, BTreeMap<String, MonitorDirAxisValue>)>,
+    history: &MonitorHistory,
+    sender: &mut T,
+) -> Result<(), Box<dyn Error>> {
+    let queue = Mutex::new(children.into_iter());
+    let (tx, rx) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..per_child.concurrency {
+            let tx = tx.clone();
+            let queue = &queue;
+            scope.spawn(move || loop {
+                let Some((id, axes)) = queue.lock().unwrap().next() else {
+                    break;
+                };
+                let mut sender = |_: &str, msg| -> Result<(), Box<dyn Error>> {
+                    tx.send(WorkerMessage::Child(id.clone(), Box::new(msg)))
+                        .map_err(|_| ShuttingDown::default().into())
+                };
+                let history = history.child(&id);
+                if let Err(err) = run_child(monitor, per_child, &id, &axes, &history, &mut sender) {
+                    if sender(&id, WorkerMessage::AbnormalTermination(err.to_string())).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        // Forward everything from the children, abandoning the queued ones if we're supposed to shut down
+        let mut result = Ok(());
+        for msg in rx {
+            if result.is_ok() {
+                result = sender(&monitor.id, msg);
+                if result.is_err() {
+                    *queue.lock().unwrap() = vec![].into_iter();
+                }
+            }
+        }
+        result
+    })
+}
+
+fn run_child<T: FnMut(&str, WorkerMessage) -> Result<(), Box<dyn Error>>>(
+    monitor: &MonitorDirConfig,
+    per_child: &MonitorDirPerChildConfig,
+    id: &str,
+    axes: &BTreeMap<String, MonitorDirAxisValue>,
+    history: &MonitorHistory,
+    sender: &mut T,
+) -> Result<(), Box<dyn Error>> {
+    sender(id, WorkerMessage::Starting)?;
+
+    let test = monitor.root.test();
+    let args = test
         .args
         .iter()
-        .map(|s| OsString::from(s))
-        .collect::<Vec<_>>();
-    let args: Option<&[OsString]> = Some(args.as_slice());
-    let test = monitor.root.test();
-    (
-        test.interval,
-        monitor_thread_impl(
-            &monitor.id,
-            &test.command,
-            &monitor.base_path,
-            args,
-            test.timeout,
-            sender,
-            processor,
-        ),
-    )
+        .chain(&per_child.args)
+        .map(|arg| interpolate_id(axes, arg))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut exec = Exec::cmd(&test.command).cwd(&monitor.base_path).args(&args);
+    for (name, value) in axes {
+        exec = exec.env(axis_env(name), value.to_string());
+    }
+    let processor = test.processor.as_ref().map(|p| p.new(history));
+    monitor_thread_impl(id, exec, test.timeout, sender, processor.as_deref())
+}
+
+/// The environment variable holding an axis value, e.g. `STYLUS_AXIS_INDEX`.
+fn axis_env(name: &str) -> String {
+    let name = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    format!("STYLUS_AXIS_{name}")
 }
 
 fn append<T: FnMut(LogStream, String)>(
@@ -114,6 +292,8 @@ fn append<T: FnMut(LogStream, String)>(
 
 enum DeathResult {
     ExitStatus(ExitStatus),
+    /// The process outlived its timeout and was terminated or killed.
+    Killed,
     Wedged(Popen),
 }
 
@@ -132,8 +312,7 @@ fn aggressively_wait_for_death(id: &str, mut popen: Popen, duration: Duration) -
     // Now give it 5 seconds to exit for good
     let r = popen.wait_timeout(Duration::from_millis(5000));
     if let Ok(Some(_)) = r {
-        // Always return the signal
-        return DeathResult::ExitStatus(ExitStatus::Signaled(1));
+        return DeathResult::Killed;
     }
 
     // Kill with prejudice
@@ -143,8 +322,7 @@ fn aggressively_wait_for_death(id: &str, mut popen: Popen, duration: Duration) -
     // Give it another 5 seconds
     let r = popen.wait_timeout(Duration::from_millis(5000));
     if let Ok(Some(_)) = r {
-        // Always return the signal
-        return DeathResult::ExitStatus(ExitStatus::Signaled(9));
+        return DeathResult::Killed;
     }
 
     // This process is probably wedged and will become a zombie
@@ -168,7 +346,7 @@ fn process_log_message<T: FnMut(&str, WorkerMessage) -> Result<(), Box<dyn Error
         WorkerMessage::Metadata(s.trim().to_owned())
     } else {
         if let Some(processor) = processor {
-            processed = processor.process_message(&s);
+            processed = processor.process_message(&stream, &s);
         }
         WorkerMessage::LogMessage(stream, s)
     };
@@ -186,27 +364,16 @@ fn process_log_message<T: FnMut(&str, WorkerMessage) -> Result<(), Box<dyn Error
 
 fn monitor_thread_impl<T: FnMut(&str, WorkerMessage) -> Result<(), Box<dyn Error>>>(
     id: &str,
-    cmd: &Path,
-    base_path: &Path,
-    args: Option<&[impl AsRef<OsStr> + std::fmt::Debug]>,
+    exec: Exec,
     timeout: Duration,
     sender: &mut T,
     processor: Option<&dyn MonitorMessageProcessorInstance>,
 ) -> Result<(), Box<dyn Error>> {
-    // This will fail if we're supposed to shut down
-    sender(id, WorkerMessage::Starting)?;
-
-    let mut exec = Exec::cmd(cmd)
-        .cwd(base_path)
+    debug!("[{}] Starting {}", id, exec.to_cmdline_lossy());
+    let exec = exec
         .env("STYLUS_MONITOR_ID", id)
         .stdout(Redirection::Pipe)
         .stderr(Redirection::Pipe);
-    if let Some(args) = args {
-        exec = exec.args(args);
-        debug!("[{}] Starting {:?} {args:?}", id, cmd);
-    } else {
-        debug!("[{}] Starting {:?}", id, cmd);
-    }
     let mut popen = exec.popen()?;
 
     let failed = AtomicBool::new(false);
@@ -291,11 +458,11 @@ fn monitor_thread_impl<T: FnMut(&str, WorkerMessage) -> Result<(), Box<dyn Error
                 WorkerMessage::AbnormalTermination("Process exited for unknown reason".into()),
             )?;
         }
+        DeathResult::Killed => {
+            sender(id, WorkerMessage::AbnormalTermination(TIMED_OUT.into()))?;
+        }
         DeathResult::Wedged(mut popen) => {
-            sender(
-                id,
-                WorkerMessage::AbnormalTermination("Process timed out".into()),
-            )?;
+            sender(id, WorkerMessage::AbnormalTermination(TIMED_OUT.into()))?;
             // We can wait here after we notify the monitor system
             let _ = popen.wait();
         }
@@ -309,14 +476,18 @@ mod tests {
     use super::*;
     use std::sync::mpsc::*;
 
+    #[test]
+    fn test_axis_env() {
+        assert_eq!(axis_env("index"), "STYLUS_AXIS_INDEX");
+        assert_eq!(axis_env("host-name"), "STYLUS_AXIS_HOST_NAME");
+    }
+
     #[test]
     fn test_timeout() {
         let (tx, rx) = channel();
         monitor_thread_impl(
             "test",
-            Path::new("/bin/sleep"),
-            Path::new("/tmp"),
-            Some(&["10"]),
+            Exec::cmd("/bin/sleep").cwd("/tmp").arg("10"),
             Duration::from_millis(250),
             &mut |_, m| {
                 tx.send(m)?;